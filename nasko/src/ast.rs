@@ -1,15 +1,18 @@
 #![allow(dead_code)]
-use std::fmt;
+use std::{any::Any, fmt};
 use crate::semantics::*;
+use crate::diagnostic::Span;
 use nasko_proc_macro::GenericASTNode;
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub enum ExtraNodeData {
   String(String),
-  Number(f64),
+  Integer(i128),
+  Float(f64),
   Boolean(bool),
   Vec(Vec<ExtraNodeData>),
   BoxedNode(Box<dyn ASTNode>),
+  #[default]
   None
 }
 
@@ -17,7 +20,8 @@ impl fmt::Debug for ExtraNodeData {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     match self {
       ExtraNodeData::String(s) => write!(f, "{}", s),
-      ExtraNodeData::Number(n) => write!(f, "{}", n),
+      ExtraNodeData::Integer(n) => write!(f, "{}", n),
+      ExtraNodeData::Float(n) => write!(f, "{:?}", n),
       ExtraNodeData::Boolean(b) => write!(f, "{}", b),
       ExtraNodeData::Vec(v) => write!(f, "{:?}", v),
       ExtraNodeData::BoxedNode(b) => write!(f, "{:?}", b),
@@ -26,12 +30,6 @@ impl fmt::Debug for ExtraNodeData {
   }
 }
 
-impl Default for ExtraNodeData {
-  fn default() -> Self {
-    ExtraNodeData::None
-  }
-}
-
 pub trait ASTClone {
   fn clone_box(&self) -> Box<dyn ASTNode>;
 }
@@ -56,12 +54,26 @@ pub trait ASTNode: ASTClone {
   fn debug_fmt(&self) -> String;
   /// Node Type
   fn node_type(&self) -> &str;
+  /// Source code span of the node
+  fn span(&self) -> Span;
+  /// Type annotation of the node
+  fn ta(&self) -> &NaskoType;
+  /// Mutable type annotation of the node
+  fn ta_mut(&mut self) -> &mut NaskoType;
   /// Gets all children as &Vec
   fn get_leaves(&self) -> &Vec<Box<dyn ASTNode>>;
+  /// Gets all children as &mut Vec
+  fn get_leaves_mut(&mut self) -> &mut Vec<Box<dyn ASTNode>>;
   /// Add a leaf to the tree branch
   fn push_leaf(&mut self, data: Box<dyn ASTNode>);
   /// Get a mutable reference to a leaf
   fn get_leaf_mut(&mut self, index: usize) -> Option<&mut Box<dyn ASTNode>>;
+  /// The node as `Any`, for checked downcasting
+  fn as_any(&self) -> &dyn Any;
+  /// The node as mutable `Any`, for checked downcasting
+  fn as_any_mut(&mut self) -> &mut dyn Any;
+  /// The boxed node as `Any`, for checked downcasting
+  fn into_any(self: Box<Self>) -> Box<dyn Any>;
 }
 
 impl fmt::Debug for dyn ASTNode {
@@ -70,22 +82,82 @@ impl fmt::Debug for dyn ASTNode {
   }
 }
 
+impl dyn ASTNode {
+  /// Checks whether the node is a `T`
+  pub fn is<T: ASTNode + 'static>(&self) -> bool {
+    self.as_any().is::<T>()
+  }
+
+  /// Gets a reference to the node as a `T`
+  pub fn downcast_ref<T: ASTNode + 'static>(&self) -> Option<&T> {
+    self.as_any().downcast_ref::<T>()
+  }
+
+  /// Gets a mutable reference to the node as a `T`
+  pub fn downcast_mut<T: ASTNode + 'static>(&mut self) -> Option<&mut T> {
+    self.as_any_mut().downcast_mut::<T>()
+  }
+
+  /// Converts the boxed node into a boxed `T`
+  ///
+  /// The node is handed back untouched if it is not a `T`
+  pub fn downcast<T: ASTNode + 'static>(self: Box<Self>) -> Result<Box<T>, Box<dyn ASTNode>> {
+    if self.is::<T>() {
+      Ok(self.into_any().downcast::<T>().unwrap())
+    } else {
+      Err(self)
+    }
+  }
+}
+
 #[derive(GenericASTNode, Default, Debug, Clone)]
 pub struct SourceNode {
   #[node_type(|| "Source")]
 
   pub ta: NaskoType,
 
+  #[span]
+  pub span: Span,
+
   #[children]
   pub children: Vec<Box<dyn ASTNode>>,
 }
 
+impl SourceNode {
+  /// Whether every top level declaration parsed and every import loaded
+  ///
+  /// Names missing from an incomplete tree may be declared by what failed,
+  /// so they aren't reported.
+  pub fn is_complete(&self) -> bool {
+    !self.children.iter().any(|leaf| leaf.is::<ErrorNode>())
+  }
+}
+
 #[derive(GenericASTNode, Debug, Clone)]
 pub struct EmptyNode {
   #[node_type(|| "Empty")]
 
   pub ta: NaskoType,
 
+  #[span]
+  pub span: Span,
+
+  #[children]
+  pub children: Vec<Box<dyn ASTNode>>,
+}
+
+/// Placeholder for a construct that failed to parse, or for an import that
+/// failed to load
+#[derive(GenericASTNode, Debug, Clone)]
+pub struct ErrorNode {
+  #[node_type(|| "Error")]
+
+  pub ta: NaskoType,
+  pub message: String,
+
+  #[span]
+  pub span: Span,
+
   #[children]
   pub children: Vec<Box<dyn ASTNode>>,
 }
@@ -98,6 +170,42 @@ pub struct ValueNode {
   pub ntype: String,
   pub value: ExtraNodeData,
 
+  #[span]
+  pub span: Span,
+
+  #[children]
+  pub children: Vec<Box<dyn ASTNode>>,
+}
+
+/// What an `import` names
+#[derive(Debug, PartialEq, Clone)]
+pub enum ImportPath {
+  /// `import "path/to/module.nasko";`
+  File(String),
+  /// `import path.to.module;`
+  Module(Vec<String>)
+}
+
+impl fmt::Display for ImportPath {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ImportPath::File(path) => write!(f, "\"{}\"", path),
+      ImportPath::Module(segments) => write!(f, "{}", segments.join("."))
+    }
+  }
+}
+
+/// `import path;`, resolved and merged away by the module loader
+#[derive(GenericASTNode, Debug, Clone)]
+pub struct ImportNode {
+  #[node_type(|| "Import")]
+
+  pub ta: NaskoType,
+  pub path: ImportPath,
+
+  #[span]
+  pub span: Span,
+
   #[children]
   pub children: Vec<Box<dyn ASTNode>>,
 }
@@ -108,18 +216,178 @@ pub struct FunctionDeclNode {
 
   pub ta: NaskoType,
   pub value: ExtraNodeData,
-  pub params: Vec<Box<ValueNode>>,
+  pub params: Vec<ValueNode>,
+
+  #[span]
+  pub span: Span,
 
   #[children]
   pub children: Vec<Box<dyn ASTNode>>
 }
 
+/// `let name: type = initializer;`, the initializer is the only child
+#[derive(GenericASTNode, Debug, Clone)]
+pub struct VariableDeclNode {
+  #[node_type(|| "VariableDecl")]
+
+  pub ta: NaskoType,
+  pub name: String,
+
+  #[span]
+  pub span: Span,
+
+  #[children]
+  pub children: Vec<Box<dyn ASTNode>>,
+}
+
+/// `struct Name { field: type, ... }`
+#[derive(GenericASTNode, Debug, Clone)]
+pub struct StructDeclNode {
+  #[node_type(|| "StructDecl")]
+
+  pub ta: NaskoType,
+  pub name: String,
+  pub fields: Vec<ValueNode>,
+
+  #[span]
+  pub span: Span,
+
+  #[children]
+  pub children: Vec<Box<dyn ASTNode>>,
+}
+
+/// `enum Name { Variant, Variant = value, ... }`
+///
+/// Each variant is an `EnumVariantDecl` value node whose only child, if any,
+/// is its explicit discriminant. Checking gives every variant a `Constant`
+/// child holding its discriminant.
+#[derive(GenericASTNode, Debug, Clone)]
+pub struct EnumDeclNode {
+  #[node_type(|| "EnumDecl")]
+
+  pub ta: NaskoType,
+  pub name: String,
+  pub variants: Vec<ValueNode>,
+
+  #[span]
+  pub span: Span,
+
+  #[children]
+  pub children: Vec<Box<dyn ASTNode>>,
+}
+
+/// `Name { field: value, ... }`, each child is a `FieldInit` value node
+#[derive(GenericASTNode, Debug, Clone)]
+pub struct StructLiteralNode {
+  #[node_type(|| "StructLiteral")]
+
+  pub ta: NaskoType,
+  pub name: String,
+
+  #[span]
+  pub span: Span,
+
+  #[children]
+  pub children: Vec<Box<dyn ASTNode>>,
+}
+
+/// `object.field`, the object is the only child
+#[derive(GenericASTNode, Debug, Clone)]
+pub struct FieldAccessNode {
+  #[node_type(|| "FieldAccess")]
+
+  pub ta: NaskoType,
+  pub field: String,
+
+  #[span]
+  pub span: Span,
+
+  #[children]
+  pub children: Vec<Box<dyn ASTNode>>,
+}
+
+/// `name(argument, ...)`, each argument is a child
+#[derive(GenericASTNode, Debug, Clone)]
+pub struct CallNode {
+  #[node_type(|| "Call")]
+
+  pub ta: NaskoType,
+  pub name: String,
+  pub name_span: Span,
+  /// What `name` refers to, `None` until names are resolved
+  pub binding: Option<Binding>,
+
+  #[span]
+  pub span: Span,
+
+  #[children]
+  pub children: Vec<Box<dyn ASTNode>>,
+}
+
+/// `expression as type`, the expression is the only child and `ta` is the
+/// type it is converted to
+#[derive(GenericASTNode, Debug, Clone)]
+pub struct CastNode {
+  #[node_type(|| "Cast")]
+
+  pub ta: NaskoType,
+
+  #[span]
+  pub span: Span,
+
+  #[children]
+  pub children: Vec<Box<dyn ASTNode>>,
+}
+
+/// `[element, ...]`, each element is a child
+#[derive(GenericASTNode, Debug, Clone)]
+pub struct ArrayLiteralNode {
+  #[node_type(|| "ArrayLiteral")]
+
+  pub ta: NaskoType,
+
+  #[span]
+  pub span: Span,
+
+  #[children]
+  pub children: Vec<Box<dyn ASTNode>>,
+}
+
+/// `array[index]`, the children are the array and the index
+#[derive(GenericASTNode, Debug, Clone)]
+pub struct IndexNode {
+  #[node_type(|| "Index")]
+
+  pub ta: NaskoType,
+
+  #[span]
+  pub span: Span,
+
+  #[children]
+  pub children: Vec<Box<dyn ASTNode>>,
+}
+
+/// What a name refers to, filled in by name resolution
+#[derive(Debug, PartialEq, Clone)]
+pub enum Binding {
+  /// A variable or parameter, by the span of its name in the declaration
+  Local(Span),
+  Function,
+  /// A struct or enum
+  Type
+}
+
+/// An identifier bound to its declaration, resolved from an `UnknownIdent`
 #[derive(GenericASTNode, Debug, Clone)]
 pub struct NameNode {
   #[node_type(|| "Ident")]
 
   pub ta: NaskoType,
   pub name: String,
+  pub binding: Binding,
+
+  #[span]
+  pub span: Span,
 
   #[children]
   pub children: Vec<Box<dyn ASTNode>>,
@@ -131,6 +399,9 @@ pub struct BlockNode {
 
   pub ta: NaskoType,
 
+  #[span]
+  pub span: Span,
+
   #[children]
   pub children: Vec<Box<dyn ASTNode>>,
 }
@@ -142,6 +413,9 @@ pub struct StatementNode {
   pub ta: NaskoType,
   pub ntype: String,
 
+  #[span]
+  pub span: Span,
+
   #[children]
   pub children: Vec<Box<dyn ASTNode>>,
 }
@@ -153,10 +427,28 @@ pub struct ExpressionNode {
   pub ta: NaskoType,
   pub ntype: String,
 
+  #[span]
+  pub span: Span,
+
   #[children]
   pub children: Vec<Box<dyn ASTNode>>,
 }
 
+/// A prefix operator applied to the only child
+#[derive(GenericASTNode, Debug, Clone)]
+pub struct UnaryExpression {
+  #[node_type(|| "UnaryExpression")]
+
+  pub ta: NaskoType,
+  pub expression: NaskoUnary,
+
+  #[span]
+  pub span: Span,
+
+  #[children]
+  pub children: Vec<Box<dyn ASTNode>>
+}
+
 #[derive(GenericASTNode, Debug, Clone)]
 pub struct BinaryExpression {
   #[node_type(|| "BinaryExpression")]
@@ -166,6 +458,9 @@ pub struct BinaryExpression {
   pub lhs: Option<Box<dyn ASTNode>>,
   pub expression: NaskoArithmetic,
 
+  #[span]
+  pub span: Span,
+
   #[children]
   pub children: Vec<Box<dyn ASTNode>>
 }
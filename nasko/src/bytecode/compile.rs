@@ -0,0 +1,471 @@
+//! Compilation of the checked tree into bytecode
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use crate::ast::*;
+use crate::diagnostic::{Diagnostic, Span};
+use crate::semantics::*;
+use super::*;
+
+/// Compiles a program that was checked without errors
+///
+/// Fails if the program has more of something than the bytecode can
+/// number, like locals in a function or constants in the module.
+pub fn compile(tree: &SourceNode) -> Result<Module, Diagnostic> {
+  let mut structs = HashMap::new();
+  let mut functions = HashMap::new();
+  for leaf in &tree.children {
+    if let Some(decl) = leaf.downcast_ref::<StructDeclNode>() {
+      let fields = decl.fields.iter().filter_map(|field| match &field.value {
+        ExtraNodeData::String(name) => Some(name.clone()),
+        _ => None
+      }).collect();
+      structs.insert(decl.name.clone(), fields);
+    } else if let Some(func) = leaf.downcast_ref::<FunctionDeclNode>() {
+      if let ExtraNodeData::String(name) = &func.value {
+        let index = u32::try_from(functions.len())
+          .map_err(|_| too_many("functions", u32::MAX as usize, func.span.clone()))?;
+        functions.entry(name.clone()).or_insert((index, func.ta.clone()));
+      }
+    }
+  }
+
+  let mut compiler = Compiler {
+    structs: &structs,
+    functions: &functions,
+    constants: vec![],
+    code: vec![],
+    spans: vec![],
+    locals: HashMap::new(),
+    loops: vec![],
+    error: None
+  };
+  let mut module = Module::default();
+  for leaf in &tree.children {
+    if let Some(func) = leaf.downcast_ref::<FunctionDeclNode>() {
+      module.functions.push(compiler.function(func));
+    }
+  }
+  module.constants = compiler.constants;
+
+  match compiler.error {
+    Some(error) => Err(error),
+    None => Ok(module)
+  }
+}
+
+fn too_many(what: &str, max: usize, span: Span) -> Diagnostic {
+  Diagnostic::error(format!("Too many {} to compile to bytecode", what), span)
+    .with_label(format!("the bytecode holds at most {} {}", max, what))
+}
+
+/// The operand type of a checked type, enums are their discriminants
+fn prim(ta: &NaskoType) -> Prim {
+  match ta {
+    NaskoType::Int(kind) => Prim::Int(*kind),
+    NaskoType::Float(kind) => Prim::Float(*kind),
+    NaskoType::Boolean => Prim::Boolean,
+    NaskoType::Enum(_) => Prim::Int(IntKind::I64),
+    _ => Prim::String
+  }
+}
+
+/// A loop being compiled
+struct Loop {
+  start: u32,
+  /// `jump`s to patch with the end of the loop
+  breaks: Vec<usize>
+}
+
+struct Compiler<'a> {
+  /// Field names of every struct, in declaration order
+  structs: &'a HashMap<String, Vec<String>>,
+  /// Index and return type of every function, by name
+  functions: &'a HashMap<String, (u32, NaskoType)>,
+  constants: Vec<Constant>,
+  code: Vec<Instr>,
+  spans: Vec<Span>,
+  /// Slots of the locals of the function being compiled, by the span of
+  /// their declaration
+  locals: HashMap<Span, u16>,
+  loops: Vec<Loop>,
+  /// The first limit of the bytecode the program went past
+  error: Option<Diagnostic>
+}
+
+impl<'a> Compiler<'a> {
+  fn function(&mut self, func: &FunctionDeclNode) -> Function {
+    self.locals.clear();
+    for param in &func.params {
+      self.slot(param.span.clone());
+    }
+
+    self.statements(&func.children);
+    // Running off the end returns nothing, like in the interpreter
+    self.emit(Instr::ReturnVoid, func.span.clone());
+
+    Function {
+      name: match &func.value {
+        ExtraNodeData::String(name) => name.clone(),
+        _ => String::new()
+      },
+      params: self.u16(func.params.len(), "parameters", &func.span),
+      locals: self.u16(self.locals.len(), "locals", &func.span),
+      code: std::mem::take(&mut self.code),
+      spans: std::mem::take(&mut self.spans)
+    }
+  }
+
+  /// Narrows a count to a `u16` operand, recording an error if it doesn't
+  /// fit
+  fn u16(&mut self, n: usize, what: &str, span: &Span) -> u16 {
+    u16::try_from(n).unwrap_or_else(|_| {
+      self.error.get_or_insert_with(|| too_many(what, u16::MAX as usize, span.clone()));
+      0
+    })
+  }
+
+  /// Narrows a count to a `u32` operand, recording an error if it doesn't
+  /// fit
+  fn u32(&mut self, n: usize, what: &str, span: &Span) -> u32 {
+    u32::try_from(n).unwrap_or_else(|_| {
+      self.error.get_or_insert_with(|| too_many(what, u32::MAX as usize, span.clone()));
+      0
+    })
+  }
+
+  fn emit(&mut self, instr: Instr, span: Span) -> usize {
+    self.code.push(instr);
+    self.spans.push(span);
+    self.code.len() - 1
+  }
+
+  /// Index of the next instruction, where jumps to it go
+  fn here(&mut self) -> u32 {
+    let span = self.spans.last().cloned().unwrap_or_default();
+    self.u32(self.code.len(), "instructions", &span)
+  }
+
+  /// Points the jump at `at` to the next instruction
+  fn patch(&mut self, at: usize) {
+    let target = self.here();
+    match &mut self.code[at] {
+      Instr::Jump(to) | Instr::JumpIfFalse(to) => *to = target,
+      _ => unreachable!("only jumps are patched")
+    }
+  }
+
+  fn slot(&mut self, declaration: Span) -> u16 {
+    // Functions count their locals in a `u16` as well
+    let count = self.u16(self.locals.len() + 1, "locals", &declaration);
+    *self.locals.entry(declaration).or_insert(count.saturating_sub(1))
+  }
+
+  fn constant(&mut self, constant: Constant, span: Span) {
+    // Strings are written with their length as a `u32`
+    if let Constant::String(s) = &constant {
+      self.u32(s.len(), "bytes in a string", &span);
+    }
+    // Floats are compared by their bits, `-0.0` and `0.0` are different
+    // constants
+    let same = |c: &Constant| match (c, &constant) {
+      (Constant::Float(a), Constant::Float(b)) => a.to_bits() == b.to_bits(),
+      (c, constant) => c == constant
+    };
+    let index = match self.constants.iter().position(same) {
+      Some(index) => index,
+      None => {
+        self.constants.push(constant);
+        self.constants.len() - 1
+      }
+    };
+    let index = self.u32(index, "constants", &span);
+    self.emit(Instr::Const(index), span);
+  }
+
+  /// The type of an expression, which calls take from their function
+  fn type_of(&self, node: &(dyn ASTNode + 'static)) -> NaskoType {
+    match node.downcast_ref::<CallNode>() {
+      Some(call) => self.functions[&call.name].1.clone(),
+      None => node.ta().clone()
+    }
+  }
+
+  fn field(&mut self, ta: &NaskoType, field: &str, span: &Span) -> u16 {
+    let fields = match ta {
+      NaskoType::Struct(name) => &self.structs[name],
+      _ => unreachable!("fields are only accessed on structs")
+    };
+    let index = fields.iter().position(|name| name == field).expect("fields are checked to exist");
+    self.u16(index, "fields", span)
+  }
+
+  fn statements(&mut self, statements: &[Box<dyn ASTNode>]) {
+    for statement in statements {
+      self.statement(statement.as_ref());
+    }
+  }
+
+  fn statement(&mut self, node: &(dyn ASTNode + 'static)) {
+    if let Some(decl) = node.downcast_ref::<VariableDeclNode>() {
+      self.expression(decl.children[0].as_ref());
+      let slot = self.slot(decl.span.clone());
+      self.emit(Instr::Store(slot), decl.span.clone());
+      return;
+    }
+    if let Some(block) = node.downcast_ref::<BlockNode>() {
+      return self.statements(&block.children);
+    }
+    let statement = match node.downcast_ref::<StatementNode>() {
+      Some(statement) => statement,
+      None => {
+        self.expression(node);
+        self.emit(Instr::Pop, node.span());
+        return;
+      }
+    };
+
+    let span = statement.span.clone();
+    match statement.ntype.as_str() {
+      "IfStatement" => {
+        self.expression(statement.children[0].as_ref());
+        let skip = self.emit(Instr::JumpIfFalse(0), span.clone());
+        self.statement(statement.children[1].as_ref());
+        match statement.children.get(2) {
+          Some(otherwise) => {
+            let end = self.emit(Instr::Jump(0), span);
+            self.patch(skip);
+            self.statement(otherwise.as_ref());
+            self.patch(end);
+          },
+          None => self.patch(skip)
+        }
+      },
+      "WhileStatement" => {
+        let start = self.here();
+        self.expression(statement.children[0].as_ref());
+        let exit = self.emit(Instr::JumpIfFalse(0), span.clone());
+        self.loops.push(Loop { start, breaks: vec![exit] });
+        self.statement(statement.children[1].as_ref());
+        self.emit(Instr::Jump(start), span);
+        let finished = self.loops.pop().expect("the loop was pushed above");
+        for jump in finished.breaks {
+          self.patch(jump);
+        }
+      },
+      "BreakStatement" => {
+        let jump = self.emit(Instr::Jump(0), span);
+        self.loops.last_mut().expect("`break` is checked to be in a loop").breaks.push(jump);
+      },
+      "ContinueStatement" => {
+        let start = self.loops.last().expect("`continue` is checked to be in a loop").start;
+        self.emit(Instr::Jump(start), span);
+      },
+      "ReturnStatement" => match statement.children.first() {
+        Some(value) => {
+          self.expression(value.as_ref());
+          self.emit(Instr::Return, span);
+        },
+        None => {
+          self.emit(Instr::ReturnVoid, span);
+        }
+      },
+      "AssignmentStatement" => {
+        self.expression(statement.children[1].as_ref());
+        self.assign(statement.children[0].as_ref());
+      },
+      _ => for child in &statement.children {
+        self.expression(child.as_ref());
+        self.emit(Instr::Pop, child.span());
+      }
+    }
+  }
+
+  /// Stores the value on top of the stack in an assignment target
+  fn assign(&mut self, target: &(dyn ASTNode + 'static)) {
+    // Indices are evaluated from the outside in, so the innermost ends up
+    // on top of the stack
+    let mut steps = vec![];
+    let mut root = target;
+    loop {
+      if let Some(access) = root.downcast_ref::<FieldAccessNode>() {
+        let object = access.children[0].as_ref();
+        let ta = self.type_of(object);
+        steps.push(Step::Field(self.field(&ta, &access.field, &access.span)));
+        root = object;
+      } else if let Some(index) = root.downcast_ref::<IndexNode>() {
+        self.expression(index.children[1].as_ref());
+        steps.push(Step::Index);
+        root = index.children[0].as_ref();
+      } else {
+        break;
+      }
+    }
+
+    let slot = match root.downcast_ref::<NameNode>().map(|name| &name.binding) {
+      Some(Binding::Local(declaration)) => self.locals[declaration],
+      _ => unreachable!("assignment targets are checked to be places")
+    };
+    if steps.is_empty() {
+      self.emit(Instr::Store(slot), target.span());
+    } else {
+      steps.reverse();
+      self.u32(steps.len(), "steps in an assignment target", &target.span());
+      self.emit(Instr::StoreIn(slot, steps), target.span());
+    }
+  }
+
+  fn expression(&mut self, node: &(dyn ASTNode + 'static)) {
+    let span = node.span();
+    if let Some(name) = node.downcast_ref::<NameNode>() {
+      let slot = match &name.binding {
+        Binding::Local(declaration) => self.locals[declaration],
+        _ => unreachable!("only locals are checked to be values")
+      };
+      self.emit(Instr::Load(slot), span);
+    } else if let Some(value) = node.downcast_ref::<ValueNode>() {
+      let constant = match &value.value {
+        ExtraNodeData::Integer(n) => self.integer(*n, &value.ta),
+        ExtraNodeData::Float(n) => Constant::Float(round(*n, &value.ta)),
+        ExtraNodeData::Boolean(b) => Constant::Boolean(*b),
+        ExtraNodeData::String(s) => Constant::String(s.clone()),
+        _ => unreachable!("constants are checked")
+      };
+      self.constant(constant, span);
+    } else if let Some(binary) = node.downcast_ref::<BinaryExpression>() {
+      self.binary(binary);
+    } else if let Some(unary) = node.downcast_ref::<UnaryExpression>() {
+      let operand = unary.children[0].as_ref();
+      // `-128` is a valid `i8`, even though `128` isn't
+      if let (NaskoUnary::Negate, Some(literal)) = (&unary.expression, operand.downcast_ref::<ValueNode>()) {
+        if let ExtraNodeData::Integer(n) = &literal.value {
+          let constant = self.integer(-n, &unary.ta);
+          return self.constant(constant, span);
+        }
+      }
+
+      self.expression(operand);
+      let prim = prim(&unary.ta);
+      let instr = match unary.expression {
+        NaskoUnary::Negate => Instr::Neg(prim),
+        NaskoUnary::Not => Instr::Not,
+        NaskoUnary::BitNot => Instr::BitNot(prim)
+      };
+      self.emit(instr, span);
+    } else if let Some(call) = node.downcast_ref::<CallNode>() {
+      for arg in &call.children {
+        self.expression(arg.as_ref());
+      }
+      let func = self.functions[&call.name].0;
+      self.emit(Instr::Call(func), span);
+    } else if let Some(cast) = node.downcast_ref::<CastNode>() {
+      let value = cast.children[0].as_ref();
+      self.expression(value);
+      let (from, to) = (prim(&self.type_of(value)), prim(&cast.ta));
+      // Only scalars can be cast to other types
+      if from != to && !matches!(cast.ta, NaskoType::Array(..) | NaskoType::Struct(_) | NaskoType::Enum(_)) {
+        self.emit(Instr::Cast(from, to), span);
+      }
+    } else if let Some(literal) = node.downcast_ref::<ArrayLiteralNode>() {
+      for element in &literal.children {
+        self.expression(element.as_ref());
+      }
+      let len = self.u32(literal.children.len(), "array elements", &span);
+      self.emit(Instr::Array(len), span);
+    } else if let Some(index) = node.downcast_ref::<IndexNode>() {
+      self.expression(index.children[0].as_ref());
+      self.expression(index.children[1].as_ref());
+      // Out of bounds errors point at the index
+      self.emit(Instr::Index, index.children[1].span());
+    } else if let Some(literal) = node.downcast_ref::<StructLiteralNode>() {
+      // Initializers run in source order, the instruction puts them in
+      // declaration order
+      let ta = NaskoType::Struct(literal.name.clone());
+      let mut fields = vec![];
+      for init in &literal.children {
+        let init = init.downcast_ref::<ValueNode>().expect("struct literals hold field initializers");
+        let name = match &init.value {
+          ExtraNodeData::String(name) => name,
+          _ => unreachable!("field initializers are named")
+        };
+        fields.push(self.field(&ta, name, &init.span));
+        self.expression(init.children[0].as_ref());
+      }
+      self.emit(Instr::Struct(fields), span);
+    } else if let Some(access) = node.downcast_ref::<FieldAccessNode>() {
+      let object = access.children[0].as_ref();
+      self.expression(object);
+      let ta = self.type_of(object);
+      let field = self.field(&ta, &access.field, &span);
+      self.emit(Instr::Field(field), span);
+    } else {
+      unreachable!("every expression is compiled")
+    }
+  }
+
+  /// An integer constant, or a float one for an integer literal used as a
+  /// float
+  fn integer(&self, n: i128, ta: &NaskoType) -> Constant {
+    match ta {
+      NaskoType::Float(_) => Constant::Float(round(n as f64, ta)),
+      _ => Constant::Int(n as i64)
+    }
+  }
+
+  fn binary(&mut self, binary: &BinaryExpression) {
+    let (lhs, rhs) = match (&binary.lhs, &binary.rhs) {
+      (Some(lhs), Some(rhs)) => (lhs.as_ref(), rhs.as_ref()),
+      _ => unreachable!("binary expressions have two operands")
+    };
+    let span = binary.span.clone();
+    let op = &binary.expression;
+
+    // Logical operators only evaluate the right side if they need it
+    if op.is_logical() {
+      self.expression(lhs);
+      if *op == NaskoArithmetic::Or {
+        self.emit(Instr::Not, span.clone());
+      }
+      let short = self.emit(Instr::JumpIfFalse(0), span.clone());
+      self.expression(rhs);
+      let end = self.emit(Instr::Jump(0), span.clone());
+      self.patch(short);
+      self.constant(Constant::Boolean(*op == NaskoArithmetic::Or), span);
+      self.patch(end);
+      return;
+    }
+
+    self.expression(lhs);
+    self.expression(rhs);
+    let prim = prim(&self.type_of(lhs));
+    let instr = match op {
+      NaskoArithmetic::Add => Instr::Add(prim),
+      NaskoArithmetic::Subtract => Instr::Sub(prim),
+      NaskoArithmetic::Multiply => Instr::Mul(prim),
+      NaskoArithmetic::Divide => Instr::Div(prim),
+      NaskoArithmetic::Modulo => Instr::Rem(prim),
+      NaskoArithmetic::Power => Instr::Pow(prim),
+      NaskoArithmetic::BitAnd => Instr::BitAnd,
+      NaskoArithmetic::BitOr => Instr::BitOr,
+      NaskoArithmetic::BitXor => Instr::BitXor,
+      NaskoArithmetic::ShiftLeft => Instr::Shl(prim),
+      NaskoArithmetic::ShiftRight => Instr::Shr(prim),
+      NaskoArithmetic::Equal => Instr::Eq,
+      NaskoArithmetic::NotEqual => Instr::Ne,
+      NaskoArithmetic::Less => Instr::Lt(prim),
+      NaskoArithmetic::LessEqual => Instr::Le(prim),
+      NaskoArithmetic::Greater => Instr::Gt(prim),
+      NaskoArithmetic::GreaterEqual => Instr::Ge(prim),
+      _ => unreachable!("logical operators are compiled to jumps")
+    };
+    self.emit(instr, span);
+  }
+}
+
+/// A float rounded to the precision of its type
+fn round(n: f64, ta: &NaskoType) -> f64 {
+  match ta {
+    NaskoType::Float(FloatKind::Float) => n as f32 as f64,
+    _ => n
+  }
+}
@@ -0,0 +1,224 @@
+//! Bytecode for the stack-based virtual machine
+//!
+//! Every function is a flat list of instructions that pop their operands
+//! off a value stack and push their result. Locals live in slots at the
+//! bottom of the function's part of the stack, the parameters first.
+//! Constants are shared by the whole module and referenced by index, jumps
+//! by the index of their target instruction.
+//!
+//! Arithmetic instructions carry the type of their operands, since the VM
+//! keeps every integer as an `i64` and wraps it to that type. Enums are
+//! `i64`s and `&&` and `||` are compiled to jumps.
+
+mod compile;
+mod serialize;
+mod vm;
+
+pub use self::compile::compile;
+pub use self::serialize::{deserialize, serialize};
+pub use self::vm::run;
+
+use std::fmt;
+use crate::diagnostic::Span;
+use crate::semantics::*;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Constant {
+  /// Integers of unsigned types are stored as their bits
+  Int(i64),
+  Float(f64),
+  Boolean(bool),
+  String(String)
+}
+
+/// The type of the operands of an arithmetic instruction or conversion
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Prim {
+  Int(IntKind),
+  Float(FloatKind),
+  Boolean,
+  String
+}
+
+/// How `store_in` reaches the value it stores, from the local outwards
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Step {
+  Field(u16),
+  /// Pops the index
+  Index
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Instr {
+  Const(u32),
+  Load(u16),
+  Store(u16),
+  /// Pops the indices of the steps, the innermost on top, then the value
+  StoreIn(u16, Vec<Step>),
+  Pop,
+  Add(Prim),
+  Sub(Prim),
+  Mul(Prim),
+  /// Traps on integer division by zero
+  Div(Prim),
+  Rem(Prim),
+  /// Traps on a negative integer exponent
+  Pow(Prim),
+  BitAnd,
+  BitOr,
+  BitXor,
+  Shl(Prim),
+  Shr(Prim),
+  Eq,
+  Ne,
+  Lt(Prim),
+  Le(Prim),
+  Gt(Prim),
+  Ge(Prim),
+  Neg(Prim),
+  Not,
+  BitNot(Prim),
+  /// An `as` conversion between two different types
+  Cast(Prim, Prim),
+  Jump(u32),
+  /// Pops a boolean
+  JumpIfFalse(u32),
+  /// Pops the arguments, the last on top
+  Call(u32),
+  Return,
+  ReturnVoid,
+  /// Pops the elements, the last on top
+  Array(u32),
+  /// Pops the fields in the order they are listed, the last on top
+  Struct(Vec<u16>),
+  /// Pops the index, then the array. Traps if the index is out of bounds.
+  Index,
+  Field(u16)
+}
+
+#[derive(Debug, Clone)]
+pub struct Function {
+  pub name: String,
+  pub params: u16,
+  /// Slots for the parameters and every other local
+  pub locals: u16,
+  pub code: Vec<Instr>,
+  /// Source of every instruction, empty for deserialized modules
+  pub spans: Vec<Span>
+}
+
+/// A whole program
+#[derive(Debug, Default, Clone)]
+pub struct Module {
+  pub constants: Vec<Constant>,
+  pub functions: Vec<Function>
+}
+
+impl Module {
+  pub fn function(&self, name: &str) -> Option<usize> {
+    self.functions.iter().position(|func| func.name == name)
+  }
+}
+
+impl fmt::Display for Constant {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Constant::Int(n) => write!(f, "{}", n),
+      Constant::Float(n) => write!(f, "{:?}", n),
+      Constant::Boolean(b) => write!(f, "{}", b),
+      Constant::String(s) => write!(f, "{:?}", s)
+    }
+  }
+}
+
+impl fmt::Display for Prim {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Prim::Int(kind) => write!(f, "{}", kind),
+      Prim::Float(kind) => write!(f, "{}", kind),
+      Prim::Boolean => write!(f, "boolean"),
+      Prim::String => write!(f, "string")
+    }
+  }
+}
+
+impl fmt::Display for Instr {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Instr::Const(constant) => write!(f, "const #{}", constant),
+      Instr::Load(local) => write!(f, "load {}", local),
+      Instr::Store(local) => write!(f, "store {}", local),
+      Instr::StoreIn(local, steps) => {
+        write!(f, "store_in {} ", local)?;
+        for step in steps {
+          match step {
+            Step::Field(field) => write!(f, ".{}", field)?,
+            Step::Index => write!(f, "[]")?
+          }
+        }
+        Ok(())
+      },
+      Instr::Pop => write!(f, "pop"),
+      Instr::Add(prim) => write!(f, "add {}", prim),
+      Instr::Sub(prim) => write!(f, "sub {}", prim),
+      Instr::Mul(prim) => write!(f, "mul {}", prim),
+      Instr::Div(prim) => write!(f, "div {}", prim),
+      Instr::Rem(prim) => write!(f, "rem {}", prim),
+      Instr::Pow(prim) => write!(f, "pow {}", prim),
+      Instr::BitAnd => write!(f, "bit_and"),
+      Instr::BitOr => write!(f, "bit_or"),
+      Instr::BitXor => write!(f, "bit_xor"),
+      Instr::Shl(prim) => write!(f, "shl {}", prim),
+      Instr::Shr(prim) => write!(f, "shr {}", prim),
+      Instr::Eq => write!(f, "eq"),
+      Instr::Ne => write!(f, "ne"),
+      Instr::Lt(prim) => write!(f, "lt {}", prim),
+      Instr::Le(prim) => write!(f, "le {}", prim),
+      Instr::Gt(prim) => write!(f, "gt {}", prim),
+      Instr::Ge(prim) => write!(f, "ge {}", prim),
+      Instr::Neg(prim) => write!(f, "neg {}", prim),
+      Instr::Not => write!(f, "not"),
+      Instr::BitNot(prim) => write!(f, "bit_not {}", prim),
+      Instr::Cast(from, to) => write!(f, "cast {} {}", from, to),
+      Instr::Jump(target) => write!(f, "jump {}", target),
+      Instr::JumpIfFalse(target) => write!(f, "jump_if_false {}", target),
+      Instr::Call(func) => write!(f, "call {}", func),
+      Instr::Return => write!(f, "return"),
+      Instr::ReturnVoid => write!(f, "return_void"),
+      Instr::Array(len) => write!(f, "array {}", len),
+      Instr::Struct(fields) => {
+        let fields: Vec<String> = fields.iter().map(|field| format!(".{}", field)).collect();
+        write!(f, "struct {}", fields.join(" "))
+      },
+      Instr::Index => write!(f, "index"),
+      Instr::Field(field) => write!(f, "field .{}", field)
+    }
+  }
+}
+
+/// The disassembly printed by `--emit=bytecode`, with the constants and
+/// functions that instructions refer to written next to them
+impl fmt::Display for Module {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    writeln!(f, "constants:")?;
+    for (i, constant) in self.constants.iter().enumerate() {
+      writeln!(f, "  #{}: {}", i, constant)?;
+    }
+
+    for (i, func) in self.functions.iter().enumerate() {
+      writeln!(f, "\nfunc {} {} (params: {}, locals: {}):", i, func.name, func.params, func.locals)?;
+      for (pc, instr) in func.code.iter().enumerate() {
+        let comment = match instr {
+          Instr::Const(constant) => self.constants.get(*constant as usize).map(|c| c.to_string()),
+          Instr::Call(callee) => self.functions.get(*callee as usize).map(|callee| callee.name.clone()),
+          _ => None
+        };
+        match comment {
+          Some(comment) => writeln!(f, "  {:4}  {:<24}; {}", pc, instr.to_string(), comment)?,
+          None => writeln!(f, "  {:4}  {}", pc, instr)?
+        }
+      }
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,412 @@
+//! The `.naskoc` file format
+//!
+//! A magic number and the version of the compiler that wrote the file,
+//! then the constants and the functions. Numbers are little endian, and
+//! strings and lists are prefixed with their length as a `u32`. Spans are
+//! not written, they refer to sources the file doesn't include.
+
+use std::convert::TryFrom;
+use super::*;
+
+const MAGIC: &[u8] = b"NASKOC";
+
+/// Files are only read by the compiler version that wrote them
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+const INT_KINDS: [IntKind; 8] = [
+  IntKind::I8, IntKind::I16, IntKind::I32, IntKind::I64,
+  IntKind::U8, IntKind::U16, IntKind::U32, IntKind::U64
+];
+
+/// Writes a module
+///
+/// Fails if a list or string is too long for its length to be written,
+/// which `compile` already rules out.
+pub fn serialize(module: &Module) -> Result<Vec<u8>, String> {
+  let mut writer = Writer { bytes: MAGIC.to_vec() };
+  writer.string(VERSION)?;
+
+  writer.len(module.constants.len(), "constants")?;
+  for constant in &module.constants {
+    match constant {
+      Constant::Int(n) => {
+        writer.u8(0);
+        writer.bytes.extend_from_slice(&n.to_le_bytes());
+      },
+      Constant::Float(n) => {
+        writer.u8(1);
+        writer.bytes.extend_from_slice(&n.to_bits().to_le_bytes());
+      },
+      Constant::Boolean(b) => {
+        writer.u8(2);
+        writer.u8(*b as u8);
+      },
+      Constant::String(s) => {
+        writer.u8(3);
+        writer.string(s)?;
+      }
+    }
+  }
+
+  writer.len(module.functions.len(), "functions")?;
+  for func in &module.functions {
+    writer.string(&func.name)?;
+    writer.u16(func.params);
+    writer.u16(func.locals);
+    writer.len(func.code.len(), "instructions")?;
+    for instr in &func.code {
+      writer.instr(instr)?;
+    }
+  }
+
+  Ok(writer.bytes)
+}
+
+/// Reads a module written by `serialize`, checking that the VM can run it
+/// without going out of bounds
+///
+/// Every index into the module must be in range and the stack must have
+/// the same depth on every path to an instruction, which never pops more
+/// than was pushed. The types of values depend on what the program does,
+/// the VM checks those as it runs.
+pub fn deserialize(bytes: &[u8]) -> Result<Module, String> {
+  let mut reader = Reader { bytes, at: 0 };
+  if reader.take(MAGIC.len())? != MAGIC {
+    return Err("Not a compiled Nasko module".to_string());
+  }
+  let version = reader.string()?;
+  if version != VERSION {
+    return Err(format!("The module was compiled by naskoc {}, this is naskoc {}", version, VERSION));
+  }
+
+  let mut module = Module::default();
+  for _ in 0..reader.u32()? {
+    let constant = match reader.u8()? {
+      0 => Constant::Int(i64::from_le_bytes(reader.array()?)),
+      1 => Constant::Float(f64::from_bits(u64::from_le_bytes(reader.array()?))),
+      2 => Constant::Boolean(reader.u8()? != 0),
+      3 => Constant::String(reader.string()?),
+      tag => return Err(format!("Unknown constant tag {}", tag))
+    };
+    module.constants.push(constant);
+  }
+
+  for _ in 0..reader.u32()? {
+    let name = reader.string()?;
+    let params = reader.u16()?;
+    let locals = reader.u16()?;
+    let mut code = vec![];
+    for _ in 0..reader.u32()? {
+      code.push(reader.instr()?);
+    }
+    module.functions.push(Function { name, params, locals, code, spans: vec![] });
+  }
+  if reader.at != bytes.len() {
+    return Err("Unexpected data after the last function".to_string());
+  }
+
+  check(&module)?;
+  Ok(module)
+}
+
+/// Checks everything the VM relies on without looking
+fn check(module: &Module) -> Result<(), String> {
+  // `run` only has `argc` and `argv` to pass
+  if module.function("main").is_some_and(|main| module.functions[main].params > 2) {
+    return Err("`main` has more than 2 parameters".to_string());
+  }
+
+  for func in &module.functions {
+    if func.params > func.locals {
+      return Err(format!("`{}` has more parameters than locals", func.name));
+    }
+    let valid = |instr: &Instr| match instr {
+      Instr::Const(constant) => (*constant as usize) < module.constants.len(),
+      Instr::Load(local) | Instr::Store(local) | Instr::StoreIn(local, _) => *local < func.locals,
+      Instr::Jump(target) | Instr::JumpIfFalse(target) => (*target as usize) < func.code.len(),
+      Instr::Call(callee) => (*callee as usize) < module.functions.len(),
+      // Every field exactly once
+      Instr::Struct(fields) => fields.iter().enumerate()
+        .all(|(i, field)| (*field as usize) < fields.len() && !fields[..i].contains(field)),
+      Instr::Add(prim) | Instr::Sub(prim) | Instr::Mul(prim) | Instr::Div(prim) | Instr::Rem(prim) |
+      Instr::Pow(prim) | Instr::Lt(prim) | Instr::Le(prim) | Instr::Gt(prim) | Instr::Ge(prim) |
+      Instr::Neg(prim) => matches!(prim, Prim::Int(_) | Prim::Float(_)),
+      Instr::Shl(prim) | Instr::Shr(prim) | Instr::BitNot(prim) => matches!(prim, Prim::Int(_)),
+      _ => true
+    };
+    if let Some(pc) = func.code.iter().position(|instr| !valid(instr)) {
+      return Err(format!("Invalid operand at {} in `{}`", pc, func.name));
+    }
+    check_stack(module, func)?;
+  }
+  Ok(())
+}
+
+/// Follows every path through a function, checking the depth of the stack
+/// above its locals
+fn check_stack(module: &Module, func: &Function) -> Result<(), String> {
+  let mut depths: Vec<Option<usize>> = vec![None; func.code.len()];
+  let mut pending = vec![(0, 0)];
+
+  while let Some((pc, depth)) = pending.pop() {
+    let instr = match func.code.get(pc) {
+      Some(instr) => instr,
+      None => return Err(format!("`{}` runs past its end", func.name))
+    };
+    match depths[pc] {
+      Some(known) if known == depth => continue,
+      Some(_) => return Err(format!("Stack depth differs between paths to {} in `{}`", pc, func.name)),
+      None => depths[pc] = Some(depth)
+    }
+
+    let (pops, pushes) = match instr {
+      Instr::Const(_) | Instr::Load(_) => (0, 1),
+      Instr::Store(_) | Instr::Pop | Instr::JumpIfFalse(_) | Instr::Return => (1, 0),
+      Instr::StoreIn(_, steps) => (1 + steps.iter().filter(|step| **step == Step::Index).count(), 0),
+      Instr::Neg(_) | Instr::Not | Instr::BitNot(_) | Instr::Cast(..) | Instr::Field(_) => (1, 1),
+      Instr::Jump(_) | Instr::ReturnVoid => (0, 0),
+      Instr::Call(callee) => (module.functions[*callee as usize].params as usize, 1),
+      Instr::Array(len) => (*len as usize, 1),
+      Instr::Struct(fields) => (fields.len(), 1),
+      // Every other instruction is a binary operator
+      _ => (2, 1)
+    };
+    if depth < pops {
+      return Err(format!("Stack underflow at {} in `{}`", pc, func.name));
+    }
+    let depth = depth - pops + pushes;
+
+    match instr {
+      Instr::Jump(target) => pending.push((*target as usize, depth)),
+      Instr::JumpIfFalse(target) => {
+        pending.push((*target as usize, depth));
+        pending.push((pc + 1, depth));
+      },
+      Instr::Return | Instr::ReturnVoid => {},
+      _ => pending.push((pc + 1, depth))
+    }
+  }
+  Ok(())
+}
+
+struct Writer {
+  bytes: Vec<u8>
+}
+
+impl Writer {
+  fn u8(&mut self, n: u8) {
+    self.bytes.push(n);
+  }
+
+  fn u16(&mut self, n: u16) {
+    self.bytes.extend_from_slice(&n.to_le_bytes());
+  }
+
+  fn u32(&mut self, n: u32) {
+    self.bytes.extend_from_slice(&n.to_le_bytes());
+  }
+
+  /// Writes the length of a list or string
+  fn len(&mut self, len: usize, what: &str) -> Result<(), String> {
+    let len = u32::try_from(len).map_err(|_| format!("Too many {} to write, {} is more than {}", what, len, u32::MAX))?;
+    self.u32(len);
+    Ok(())
+  }
+
+  fn string(&mut self, s: &str) -> Result<(), String> {
+    self.len(s.len(), "bytes in a string")?;
+    self.bytes.extend_from_slice(s.as_bytes());
+    Ok(())
+  }
+
+  fn prim(&mut self, prim: &Prim) {
+    let tag = match prim {
+      Prim::Int(kind) => INT_KINDS.iter().position(|k| k == kind).expect("every kind is listed") as u8,
+      Prim::Float(FloatKind::Float) => 8,
+      Prim::Float(FloatKind::Double) => 9,
+      Prim::Boolean => 10,
+      Prim::String => 11
+    };
+    self.u8(tag);
+  }
+
+  fn instr(&mut self, instr: &Instr) -> Result<(), String> {
+    self.u8(opcode(instr));
+    match instr {
+      Instr::Const(n) | Instr::Jump(n) | Instr::JumpIfFalse(n) | Instr::Call(n) | Instr::Array(n) => self.u32(*n),
+      Instr::Load(n) | Instr::Store(n) | Instr::Field(n) => self.u16(*n),
+      Instr::StoreIn(local, steps) => {
+        self.u16(*local);
+        self.len(steps.len(), "steps in an assignment target")?;
+        for step in steps {
+          match step {
+            // Fields are `u16`s, so indices can use the value above them
+            Step::Field(field) => self.u32(u32::from(*field)),
+            Step::Index => self.u32(u32::MAX)
+          }
+        }
+      },
+      Instr::Struct(fields) => {
+        self.len(fields.len(), "fields")?;
+        for field in fields {
+          self.u16(*field);
+        }
+      },
+      Instr::Add(prim) | Instr::Sub(prim) | Instr::Mul(prim) | Instr::Div(prim) | Instr::Rem(prim) |
+      Instr::Pow(prim) | Instr::Shl(prim) | Instr::Shr(prim) | Instr::Lt(prim) | Instr::Le(prim) |
+      Instr::Gt(prim) | Instr::Ge(prim) | Instr::Neg(prim) | Instr::BitNot(prim) => self.prim(prim),
+      Instr::Cast(from, to) => {
+        self.prim(from);
+        self.prim(to);
+      },
+      Instr::Pop | Instr::BitAnd | Instr::BitOr | Instr::BitXor | Instr::Eq | Instr::Ne |
+      Instr::Not | Instr::Return | Instr::ReturnVoid | Instr::Index => {}
+    }
+    Ok(())
+  }
+}
+
+fn opcode(instr: &Instr) -> u8 {
+  match instr {
+    Instr::Const(_) => 0,
+    Instr::Load(_) => 1,
+    Instr::Store(_) => 2,
+    Instr::StoreIn(..) => 3,
+    Instr::Pop => 4,
+    Instr::Add(_) => 5,
+    Instr::Sub(_) => 6,
+    Instr::Mul(_) => 7,
+    Instr::Div(_) => 8,
+    Instr::Rem(_) => 9,
+    Instr::Pow(_) => 10,
+    Instr::BitAnd => 11,
+    Instr::BitOr => 12,
+    Instr::BitXor => 13,
+    Instr::Shl(_) => 14,
+    Instr::Shr(_) => 15,
+    Instr::Eq => 16,
+    Instr::Ne => 17,
+    Instr::Lt(_) => 18,
+    Instr::Le(_) => 19,
+    Instr::Gt(_) => 20,
+    Instr::Ge(_) => 21,
+    Instr::Neg(_) => 22,
+    Instr::Not => 23,
+    Instr::BitNot(_) => 24,
+    Instr::Cast(..) => 25,
+    Instr::Jump(_) => 26,
+    Instr::JumpIfFalse(_) => 27,
+    Instr::Call(_) => 28,
+    Instr::Return => 29,
+    Instr::ReturnVoid => 30,
+    Instr::Array(_) => 31,
+    Instr::Struct(_) => 32,
+    Instr::Index => 33,
+    Instr::Field(_) => 34
+  }
+}
+
+struct Reader<'a> {
+  bytes: &'a [u8],
+  at: usize
+}
+
+impl<'a> Reader<'a> {
+  fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+    let bytes = self.bytes.get(self.at..self.at.saturating_add(len)).ok_or("Unexpected end of the module")?;
+    self.at += len;
+    Ok(bytes)
+  }
+
+  fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+    let mut array = [0; N];
+    array.copy_from_slice(self.take(N)?);
+    Ok(array)
+  }
+
+  fn u8(&mut self) -> Result<u8, String> {
+    Ok(self.take(1)?[0])
+  }
+
+  fn u16(&mut self) -> Result<u16, String> {
+    Ok(u16::from_le_bytes(self.array()?))
+  }
+
+  fn u32(&mut self) -> Result<u32, String> {
+    Ok(u32::from_le_bytes(self.array()?))
+  }
+
+  fn string(&mut self) -> Result<String, String> {
+    let len = self.u32()? as usize;
+    String::from_utf8(self.take(len)?.to_vec()).map_err(|_| "Invalid UTF-8 in a string".to_string())
+  }
+
+  fn prim(&mut self) -> Result<Prim, String> {
+    match self.u8()? {
+      tag @ 0..=7 => Ok(Prim::Int(INT_KINDS[tag as usize])),
+      8 => Ok(Prim::Float(FloatKind::Float)),
+      9 => Ok(Prim::Float(FloatKind::Double)),
+      10 => Ok(Prim::Boolean),
+      11 => Ok(Prim::String),
+      tag => Err(format!("Unknown type tag {}", tag))
+    }
+  }
+
+  fn instr(&mut self) -> Result<Instr, String> {
+    let instr = match self.u8()? {
+      0 => Instr::Const(self.u32()?),
+      1 => Instr::Load(self.u16()?),
+      2 => Instr::Store(self.u16()?),
+      3 => {
+        let local = self.u16()?;
+        let mut steps = vec![];
+        for _ in 0..self.u32()? {
+          steps.push(match self.u32()? {
+            u32::MAX => Step::Index,
+            field => Step::Field(u16::try_from(field).map_err(|_| "Field out of range".to_string())?)
+          });
+        }
+        Instr::StoreIn(local, steps)
+      },
+      4 => Instr::Pop,
+      5 => Instr::Add(self.prim()?),
+      6 => Instr::Sub(self.prim()?),
+      7 => Instr::Mul(self.prim()?),
+      8 => Instr::Div(self.prim()?),
+      9 => Instr::Rem(self.prim()?),
+      10 => Instr::Pow(self.prim()?),
+      11 => Instr::BitAnd,
+      12 => Instr::BitOr,
+      13 => Instr::BitXor,
+      14 => Instr::Shl(self.prim()?),
+      15 => Instr::Shr(self.prim()?),
+      16 => Instr::Eq,
+      17 => Instr::Ne,
+      18 => Instr::Lt(self.prim()?),
+      19 => Instr::Le(self.prim()?),
+      20 => Instr::Gt(self.prim()?),
+      21 => Instr::Ge(self.prim()?),
+      22 => Instr::Neg(self.prim()?),
+      23 => Instr::Not,
+      24 => Instr::BitNot(self.prim()?),
+      25 => Instr::Cast(self.prim()?, self.prim()?),
+      26 => Instr::Jump(self.u32()?),
+      27 => Instr::JumpIfFalse(self.u32()?),
+      28 => Instr::Call(self.u32()?),
+      29 => Instr::Return,
+      30 => Instr::ReturnVoid,
+      31 => Instr::Array(self.u32()?),
+      32 => {
+        let mut fields = vec![];
+        for _ in 0..self.u32()? {
+          fields.push(self.u16()?);
+        }
+        Instr::Struct(fields)
+      },
+      33 => Instr::Index,
+      34 => Instr::Field(self.u16()?),
+      opcode => return Err(format!("Unknown opcode {}", opcode))
+    };
+    Ok(instr)
+  }
+}
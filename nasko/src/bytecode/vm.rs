@@ -0,0 +1,461 @@
+//! The virtual machine running bytecode
+//!
+//! Arrays and structs are reference counted and copied on write, which
+//! gives them the value semantics of the language without copying them on
+//! every load.
+//!
+//! `deserialize` checks the indices and stack depths of a module, but not
+//! the types of its values. A value of the wrong type stops the program
+//! with an error, the same as any other runtime error.
+
+use std::cmp::Ordering;
+use std::rc::Rc;
+use crate::diagnostic::Span;
+use crate::interpreter::MAX_CALL_DEPTH;
+use crate::semantics::*;
+use super::*;
+
+#[derive(Debug, PartialEq, Clone)]
+enum Value {
+  /// Integers of unsigned types are kept as their bits
+  Int(i64),
+  Float(f64),
+  Boolean(bool),
+  String(Rc<str>),
+  Array(Rc<Vec<Value>>),
+  Struct(Rc<Vec<Value>>),
+  Void
+}
+
+/// An error that stops the program
+#[derive(Debug)]
+pub struct RuntimeError {
+  pub message: String,
+  pub label: String,
+  /// Where in the source it happened, if the module has spans
+  pub span: Option<Span>
+}
+
+/// A running function
+struct Frame {
+  func: usize,
+  pc: usize,
+  /// Where its locals start on the stack
+  base: usize
+}
+
+type RunResult<T> = Result<T, RuntimeError>;
+
+/// Values on the stack of all running functions together, past which the
+/// program stops like it does at `MAX_CALL_DEPTH`
+const MAX_STACK: usize = 1 << 22;
+
+/// Runs `main` with `args` as its `argv`, returning the exit status
+pub fn run(module: &Module, args: Vec<String>) -> Result<i32, RuntimeError> {
+  let main = match module.function("main") {
+    Some(main) => main,
+    None => return Err(RuntimeError {
+      message: "The module has no `main` function".to_string(),
+      label: String::new(),
+      span: None
+    })
+  };
+
+  let argc = Value::Int(args.len() as i64);
+  let argv = Value::Array(Rc::new(args.into_iter().map(|arg| Value::String(arg.into())).collect()));
+  let stack = vec![argc, argv].into_iter().take(module.functions[main].params as usize).collect();
+
+  let constants = module.constants.iter().map(|constant| match constant {
+    Constant::Int(n) => Value::Int(*n),
+    Constant::Float(n) => Value::Float(*n),
+    Constant::Boolean(b) => Value::Boolean(*b),
+    Constant::String(s) => Value::String(s.as_str().into())
+  }).collect();
+
+  let mut vm = Vm { module, constants, stack, frames: vec![] };
+  vm.enter(main)?;
+  match vm.execute()? {
+    Value::Int(code) => Ok(code as i32),
+    _ => Ok(0)
+  }
+}
+
+struct Vm<'a> {
+  module: &'a Module,
+  /// The constants of the module, made into values once
+  constants: Vec<Value>,
+  stack: Vec<Value>,
+  frames: Vec<Frame>
+}
+
+impl<'a> Vm<'a> {
+  /// Starts running a function whose arguments are on top of the stack
+  fn enter(&mut self, func: usize) -> RunResult<()> {
+    let function = &self.module.functions[func];
+    let base = self.stack.len() - function.params as usize;
+    if base + function.locals as usize > MAX_STACK {
+      return Err(self.error("Stack overflow".to_string(), format!("more than {} values on the stack", MAX_STACK)));
+    }
+    self.stack.resize(base + function.locals as usize, Value::Void);
+    self.frames.push(Frame { func, pc: 0, base });
+    Ok(())
+  }
+
+  fn pop(&mut self) -> Value {
+    self.stack.pop().expect("the stack depth is checked by `deserialize`")
+  }
+
+  fn pop_int(&mut self) -> RunResult<i64> {
+    match self.pop() {
+      Value::Int(n) => Ok(n),
+      value => Err(self.mismatch("an integer", &value))
+    }
+  }
+
+  fn pop_float(&mut self) -> RunResult<f64> {
+    match self.pop() {
+      Value::Float(n) => Ok(n),
+      value => Err(self.mismatch("a float", &value))
+    }
+  }
+
+  fn pop_bool(&mut self) -> RunResult<bool> {
+    match self.pop() {
+      Value::Boolean(b) => Ok(b),
+      value => Err(self.mismatch("a boolean", &value))
+    }
+  }
+
+  fn error(&self, message: String, label: String) -> RuntimeError {
+    error(self.module, &self.frames, message, label)
+  }
+
+  /// The error for an operand of the wrong type, which only a module that
+  /// wasn't written by `compile` has
+  fn mismatch(&self, expected: &str, found: &Value) -> RuntimeError {
+    mismatch(self.module, &self.frames, expected, describe(found))
+  }
+
+  /// Runs until the first function returns, giving its result
+  fn execute(&mut self) -> RunResult<Value> {
+    let module = self.module;
+    loop {
+      let frame = self.frames.last_mut().expect("a function is running");
+      let base = frame.base;
+      let instr = &module.functions[frame.func].code[frame.pc];
+      frame.pc += 1;
+
+      match instr {
+        Instr::Const(constant) => self.stack.push(self.constants[*constant as usize].clone()),
+        Instr::Load(local) => self.stack.push(self.stack[base + *local as usize].clone()),
+        Instr::Store(local) => self.stack[base + *local as usize] = self.pop(),
+        Instr::StoreIn(local, steps) => self.store_in(base + *local as usize, steps)?,
+        Instr::Pop => {
+          self.pop();
+        },
+        Instr::Add(prim) | Instr::Sub(prim) | Instr::Mul(prim) | Instr::Div(prim) |
+        Instr::Rem(prim) | Instr::Pow(prim) | Instr::Shl(prim) | Instr::Shr(prim) => {
+          let value = match prim {
+            Prim::Float(kind) => {
+              let (b, a) = (self.pop_float()?, self.pop_float()?);
+              Value::Float(float_arithmetic(instr, a, b, *kind))
+            },
+            Prim::Int(kind) => {
+              let (b, a) = (self.pop_int()?, self.pop_int()?);
+              Value::Int(self.int_arithmetic(instr, a, b, *kind)?)
+            },
+            _ => unreachable!("`deserialize` checks that arithmetic is on numbers")
+          };
+          self.stack.push(value);
+        },
+        Instr::BitAnd | Instr::BitOr | Instr::BitXor => {
+          // The bits outside the type are the same in both operands, and
+          // stay that way
+          let (b, a) = (self.pop_int()?, self.pop_int()?);
+          let n = match instr {
+            Instr::BitAnd => a & b,
+            Instr::BitOr => a | b,
+            _ => a ^ b
+          };
+          self.stack.push(Value::Int(n));
+        },
+        Instr::Eq | Instr::Ne => {
+          let (b, a) = (self.pop(), self.pop());
+          self.stack.push(Value::Boolean((a == b) == (*instr == Instr::Eq)));
+        },
+        Instr::Lt(prim) | Instr::Le(prim) | Instr::Gt(prim) | Instr::Ge(prim) => {
+          let ordering = match prim {
+            Prim::Float(_) => {
+              let (b, a) = (self.pop_float()?, self.pop_float()?);
+              a.partial_cmp(&b)
+            },
+            Prim::Int(kind) if kind.is_signed() => {
+              let (b, a) = (self.pop_int()?, self.pop_int()?);
+              Some(a.cmp(&b))
+            },
+            _ => {
+              let (b, a) = (self.pop_int()?, self.pop_int()?);
+              Some((a as u64).cmp(&(b as u64)))
+            }
+          };
+          let result = match instr {
+            Instr::Lt(_) => ordering == Some(Ordering::Less),
+            Instr::Le(_) => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+            Instr::Gt(_) => ordering == Some(Ordering::Greater),
+            _ => matches!(ordering, Some(Ordering::Greater | Ordering::Equal))
+          };
+          self.stack.push(Value::Boolean(result));
+        },
+        Instr::Neg(Prim::Float(kind)) => {
+          let n = self.pop_float()?;
+          self.stack.push(Value::Float(round(-n, *kind)));
+        },
+        Instr::Neg(prim) => {
+          let n = self.pop_int()?;
+          self.stack.push(Value::Int(wrap(n.wrapping_neg(), int_kind(*prim))));
+        },
+        Instr::Not => {
+          let b = self.pop_bool()?;
+          self.stack.push(Value::Boolean(!b));
+        },
+        Instr::BitNot(prim) => {
+          let n = self.pop_int()?;
+          self.stack.push(Value::Int(wrap(!n, int_kind(*prim))));
+        },
+        Instr::Cast(from, to) => {
+          let value = self.pop();
+          self.stack.push(convert(value, *from, *to));
+        },
+        Instr::Jump(target) => self.jump(*target),
+        Instr::JumpIfFalse(target) => {
+          if !self.pop_bool()? {
+            self.jump(*target);
+          }
+        },
+        Instr::Call(func) => {
+          if self.frames.len() == MAX_CALL_DEPTH {
+            return Err(self.error(
+              "Stack overflow".to_string(),
+              format!("more than {} nested calls", MAX_CALL_DEPTH)
+            ));
+          }
+          self.enter(*func as usize)?;
+        },
+        Instr::Return | Instr::ReturnVoid => {
+          let value = if *instr == Instr::Return { self.pop() } else { Value::Void };
+          self.frames.pop();
+          self.stack.truncate(base);
+          if self.frames.is_empty() {
+            return Ok(value);
+          }
+          self.stack.push(value);
+        },
+        Instr::Array(len) => {
+          let elements = self.stack.split_off(self.stack.len() - *len as usize);
+          self.stack.push(Value::Array(Rc::new(elements)));
+        },
+        Instr::Struct(order) => {
+          let mut fields = vec![Value::Void; order.len()];
+          let values = self.stack.split_off(self.stack.len() - order.len());
+          for (field, value) in order.iter().zip(values) {
+            fields[*field as usize] = value;
+          }
+          self.stack.push(Value::Struct(Rc::new(fields)));
+        },
+        Instr::Index => {
+          let i = self.pop_int()?;
+          let element = match self.pop() {
+            Value::Array(elements) => elements[self.bounds(i, elements.len())?].clone(),
+            value => return Err(self.mismatch("an array", &value))
+          };
+          self.stack.push(element);
+        },
+        Instr::Field(field) => {
+          let value = match self.pop() {
+            Value::Struct(fields) => match fields.get(*field as usize) {
+              Some(value) => value.clone(),
+              None => return Err(mismatch(self.module, &self.frames, &format!("a struct with a field {}", field), "a struct"))
+            },
+            value => return Err(self.mismatch("a struct", &value))
+          };
+          self.stack.push(value);
+        }
+      }
+    }
+  }
+
+  fn jump(&mut self, target: u32) {
+    self.frames.last_mut().expect("a function is running").pc = target as usize;
+  }
+
+  fn bounds(&self, i: i64, len: usize) -> RunResult<usize> {
+    bounds(self.module, &self.frames, i, len)
+  }
+
+  fn store_in(&mut self, slot: usize, steps: &[Step]) -> RunResult<()> {
+    let count = steps.iter().filter(|step| **step == Step::Index).count();
+    let mut indices = vec![];
+    for _ in 0..count {
+      indices.push(self.pop_int()?);
+    }
+    let value = self.pop();
+
+    // Shared arrays and structs are copied before they are changed
+    let mut indices = indices.into_iter();
+    let mut place = &mut self.stack[slot];
+    for step in steps {
+      place = match (step, place) {
+        (Step::Field(field), Value::Struct(fields)) => {
+          if *field as usize >= fields.len() {
+            let expected = format!("a struct with a field {}", field);
+            return Err(mismatch(self.module, &self.frames, &expected, "a struct"));
+          }
+          &mut Rc::make_mut(fields)[*field as usize]
+        },
+        (Step::Index, Value::Array(elements)) => {
+          let i = indices.next().expect("every index was popped");
+          let i = bounds(self.module, &self.frames, i, elements.len())?;
+          &mut Rc::make_mut(elements)[i]
+        },
+        (Step::Field(_), place) => return Err(mismatch(self.module, &self.frames, "a struct", describe(place))),
+        (Step::Index, place) => return Err(mismatch(self.module, &self.frames, "an array", describe(place)))
+      };
+    }
+    *place = value;
+
+    Ok(())
+  }
+
+  fn int_arithmetic(&self, instr: &Instr, a: i64, b: i64, kind: IntKind) -> RunResult<i64> {
+    let signed = kind.is_signed();
+    let n = match instr {
+      Instr::Add(_) => a.wrapping_add(b),
+      Instr::Sub(_) => a.wrapping_sub(b),
+      Instr::Mul(_) => a.wrapping_mul(b),
+      Instr::Div(_) | Instr::Rem(_) if b == 0 => {
+        let action = if matches!(instr, Instr::Div(_)) { "divide" } else { "calculate the remainder" };
+        return Err(self.error(format!("Attempt to {} by zero", action), "the divisor is zero".to_string()));
+      },
+      Instr::Div(_) if signed => a.wrapping_div(b),
+      Instr::Div(_) => ((a as u64) / (b as u64)) as i64,
+      Instr::Rem(_) if signed => a.wrapping_rem(b),
+      Instr::Rem(_) => ((a as u64) % (b as u64)) as i64,
+      Instr::Pow(_) if signed && b < 0 => return Err(self.error(
+        "Attempt to raise an integer to a negative power".to_string(),
+        format!("the exponent is {}", b)
+      )),
+      Instr::Pow(_) => power(a as u64, b as u64) as i64,
+      // Shift amounts are taken modulo the width of the type, which is a
+      // power of two
+      Instr::Shl(_) => a.wrapping_shl((b & (kind.bits() as i64 - 1)) as u32),
+      Instr::Shr(_) if signed => a >> (b & (kind.bits() as i64 - 1)),
+      Instr::Shr(_) => ((a as u64) >> (b & (kind.bits() as i64 - 1))) as i64,
+      _ => unreachable!("not an integer operation")
+    };
+    Ok(wrap(n, kind))
+  }
+}
+
+/// An error at the instruction that is running
+fn error(module: &Module, frames: &[Frame], message: String, label: String) -> RuntimeError {
+  let frame = frames.last().expect("errors happen in a function");
+  let span = module.functions[frame.func].spans.get(frame.pc - 1).cloned();
+  RuntimeError { message, label, span }
+}
+
+/// The error for an operand of the wrong type
+fn mismatch(module: &Module, frames: &[Frame], expected: &str, found: &str) -> RuntimeError {
+  error(module, frames, format!("Invalid module: expected {}, found {}", expected, found), "wrong type of value".to_string())
+}
+
+fn describe(value: &Value) -> &'static str {
+  match value {
+    Value::Int(_) => "an integer",
+    Value::Float(_) => "a float",
+    Value::Boolean(_) => "a boolean",
+    Value::String(_) => "a string",
+    Value::Array(_) => "an array",
+    Value::Struct(_) => "a struct",
+    Value::Void => "no value"
+  }
+}
+
+/// Checks an index against the length of an array
+fn bounds(module: &Module, frames: &[Frame], i: i64, len: usize) -> RunResult<usize> {
+  if i < 0 || i as u64 >= len as u64 {
+    return Err(error(
+      module,
+      frames,
+      format!("Index out of bounds: the length is {} but the index is {}", len, i),
+      "index out of bounds".to_string()
+    ));
+  }
+  Ok(i as usize)
+}
+
+fn float_arithmetic(instr: &Instr, a: f64, b: f64, kind: FloatKind) -> f64 {
+  let n = match instr {
+    Instr::Add(_) => a + b,
+    Instr::Sub(_) => a - b,
+    Instr::Mul(_) => a * b,
+    Instr::Div(_) => a / b,
+    Instr::Rem(_) => a % b,
+    Instr::Pow(_) => a.powf(b),
+    _ => unreachable!("not a float operation")
+  };
+  round(n, kind)
+}
+
+fn int_kind(prim: Prim) -> IntKind {
+  match prim {
+    Prim::Int(kind) => kind,
+    _ => unreachable!("`deserialize` checks that `{}` is an integer type", prim)
+  }
+}
+
+/// An integer wrapped into the range of its type, sign extended for signed
+/// types
+fn wrap(n: i64, kind: IntKind) -> i64 {
+  let shift = 64 - kind.bits();
+  if kind.is_signed() { (n << shift) >> shift } else { (((n as u64) << shift) >> shift) as i64 }
+}
+
+/// A float rounded to the precision of its type
+fn round(n: f64, kind: FloatKind) -> f64 {
+  match kind {
+    FloatKind::Float => n as f32 as f64,
+    FloatKind::Double => n
+  }
+}
+
+/// `base ** exponent`, wrapping on overflow
+fn power(mut base: u64, mut exponent: u64) -> u64 {
+  let mut result: u64 = 1;
+  while exponent > 0 {
+    if exponent & 1 == 1 {
+      result = result.wrapping_mul(base);
+    }
+    base = base.wrapping_mul(base);
+    exponent >>= 1;
+  }
+  result
+}
+
+/// The value of an `as` cast, the same as in the interpreter
+fn convert(value: Value, from: Prim, to: Prim) -> Value {
+  match (value, to) {
+    (Value::Int(n), Prim::Int(kind)) => Value::Int(wrap(n, kind)),
+    (Value::Int(n), Prim::Float(kind)) if from == Prim::Int(IntKind::U64) => Value::Float(round(n as u64 as f64, kind)),
+    (Value::Int(n), Prim::Float(kind)) => Value::Float(round(n as f64, kind)),
+    (Value::Int(n), Prim::Boolean) => Value::Boolean(n != 0),
+    (Value::Int(n), Prim::String) if from == Prim::Int(IntKind::U64) => Value::String((n as u64).to_string().into()),
+    (Value::Int(n), Prim::String) => Value::String(n.to_string().into()),
+    // Saturating, with NaN as zero
+    (Value::Float(n), Prim::Int(kind)) => Value::Int((n as i128).max(kind.min()).min(kind.max()) as i64),
+    (Value::Float(n), Prim::Float(kind)) => Value::Float(round(n, kind)),
+    (Value::Float(n), Prim::String) => Value::String(n.to_string().into()),
+    (Value::Boolean(b), Prim::Int(_)) => Value::Int(b as i64),
+    (Value::Boolean(b), Prim::String) => Value::String(b.to_string().into()),
+    (value, _) => value
+  }
+}
@@ -0,0 +1,168 @@
+//! Command line handling for `naskoc`
+
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+Usage: naskoc [OPTIONS] <FILE>...
+       naskoc run [-I <PATH>]... [--vm] <FILE> [ARGS]...
+
+Commands:
+  run                Check FILE and run it with the interpreter, passing
+                     ARGS to `main` and exiting with its return value.
+                     FILE may also be a module compiled with
+                     --emit=naskoc, which always runs in the VM
+
+Options:
+  -o <PATH>          Write output to PATH instead of stdout, or the
+                     executable or module to PATH instead of the FILE
+                     name without its extension
+  -I <PATH>          Also search PATH for imported modules
+  --emit=<PHASE>     Stop after PHASE and emit its result (tokens, ast,
+                     typed-ast, ir, c, bytecode, naskoc, exe)
+                     [default: exe]
+  --vm               Compile FILE to bytecode and run it in the VM
+                     instead of the interpreter
+  -h, --help         Print this message
+  -V, --version      Print the compiler version";
+
+/// The compiler phase to stop after
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum EmitKind {
+  Tokens,
+  Ast,
+  TypedAst,
+  Ir,
+  /// C99 source that builds without the compiler's runtime
+  C,
+  /// The disassembled bytecode
+  Bytecode,
+  /// The bytecode as a `.naskoc` module for `naskoc run`
+  Naskoc,
+  /// An executable linked with the system `cc`
+  Exe
+}
+
+impl EmitKind {
+  pub fn parse(slice: &str) -> Option<EmitKind> {
+    match slice {
+      "tokens" => Some(EmitKind::Tokens),
+      "ast" => Some(EmitKind::Ast),
+      "typed-ast" => Some(EmitKind::TypedAst),
+      "ir" => Some(EmitKind::Ir),
+      "c" => Some(EmitKind::C),
+      "bytecode" => Some(EmitKind::Bytecode),
+      "naskoc" => Some(EmitKind::Naskoc),
+      "exe" => Some(EmitKind::Exe),
+      _ => None
+    }
+  }
+}
+
+#[derive(Debug)]
+pub struct Options {
+  pub inputs: Vec<PathBuf>,
+  pub output: Option<PathBuf>,
+  /// Directories searched for imports after the importing file's own
+  pub search_paths: Vec<PathBuf>,
+  pub emit: EmitKind
+}
+
+#[derive(Debug)]
+pub struct RunOptions {
+  pub input: PathBuf,
+  pub search_paths: Vec<PathBuf>,
+  /// Compile to bytecode and run it in the VM instead of the interpreter
+  pub vm: bool,
+  /// Arguments passed on to the program, after its own path
+  pub args: Vec<String>
+}
+
+#[derive(Debug)]
+pub enum Command {
+  Compile(Options),
+  Run(RunOptions),
+  Help,
+  Version
+}
+
+/// Parses the command line arguments (without the program name)
+pub fn parse_args<I: Iterator<Item = String>>(args: I) -> Result<Command, String> {
+  let mut args = args.peekable();
+  if args.peek().map(|arg| arg.as_str()) == Some("run") {
+    args.next();
+    return parse_run_args(args);
+  }
+
+  let mut inputs = vec![];
+  let mut output = None;
+  let mut search_paths = vec![];
+  let mut emit = EmitKind::Exe;
+
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "-h" | "--help" => return Ok(Command::Help),
+      "-V" | "--version" => return Ok(Command::Version),
+      "-o" => match args.next() {
+        Some(path) => output = Some(PathBuf::from(path)),
+        None => return Err("Missing path after `-o`".to_string())
+      },
+      "-I" => match args.next() {
+        Some(path) => search_paths.push(PathBuf::from(path)),
+        None => return Err("Missing path after `-I`".to_string())
+      },
+      _ if arg.starts_with("-I") => search_paths.push(PathBuf::from(&arg[2..])),
+      "--emit" => match args.next() {
+        Some(phase) => emit = parse_emit(&phase)?,
+        None => return Err("Missing phase after `--emit`".to_string())
+      },
+      _ if arg.starts_with("--emit=") => emit = parse_emit(&arg["--emit=".len()..])?,
+      _ if arg.starts_with('-') && arg.len() > 1 => {
+        return Err(format!("Unknown option `{}`", arg))
+      },
+      _ => inputs.push(PathBuf::from(arg))
+    }
+  }
+
+  if inputs.is_empty() {
+    return Err("No input files".to_string());
+  }
+  if output.is_some() && inputs.len() > 1 {
+    return Err("Cannot use `-o` with multiple input files".to_string());
+  }
+
+  Ok(Command::Compile(Options { inputs, output, search_paths, emit }))
+}
+
+/// Options come before the file, everything after it belongs to the program
+fn parse_run_args<I: Iterator<Item = String>>(mut args: I) -> Result<Command, String> {
+  let mut search_paths = vec![];
+  let mut vm = false;
+
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "-h" | "--help" => return Ok(Command::Help),
+      "--vm" => vm = true,
+      "-I" => match args.next() {
+        Some(path) => search_paths.push(PathBuf::from(path)),
+        None => return Err("Missing path after `-I`".to_string())
+      },
+      _ if arg.starts_with("-I") => search_paths.push(PathBuf::from(&arg[2..])),
+      _ if arg.starts_with('-') && arg.len() > 1 => {
+        return Err(format!("Unknown option `{}`", arg))
+      },
+      _ => return Ok(Command::Run(RunOptions {
+        input: PathBuf::from(arg),
+        search_paths,
+        vm,
+        args: args.collect()
+      }))
+    }
+  }
+
+  Err("No input file to run".to_string())
+}
+
+fn parse_emit(phase: &str) -> Result<EmitKind, String> {
+  EmitKind::parse(phase)
+    .ok_or_else(|| format!("Unknown emit phase `{}` (expected tokens, ast, typed-ast, ir, c, bytecode, naskoc or exe)", phase))
+}
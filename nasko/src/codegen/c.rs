@@ -0,0 +1,741 @@
+//! C source output
+//!
+//! Translates the checked tree into a single C99 file that keeps the shape
+//! of the program: every function becomes a C function and every statement
+//! a C statement. The runtime and a prelude of integer operations are
+//! copied in first, so the file builds on its own with `cc program.c -lm`.
+//!
+//! Functions of the program are prefixed with `nu_` and its types with
+//! `nt_`, which the runtime and prelude never use, so they can't clash with
+//! those or with the C library. Integers are `<stdint.h>` types, enums
+//! `int64_t`s with a constant per variant and strings `const char *`. Structs are C structs and an array
+//! is a struct of its length and a pointer to its elements, declared once
+//! per element type along with its helpers. Helpers are `static inline`, so
+//! the ones a program doesn't use don't warn. C copies of arrays share their
+//! elements, so the `_copy` helpers run wherever Nasko copies a value that
+//! holds one.
+//!
+//! Runtime errors exit with status 101 like everywhere else, except running
+//! out of stack, which is left to the platform.
+
+use super::RUNTIME;
+use crate::ast::*;
+use crate::diagnostic::Span;
+use crate::semantics::*;
+use crate::type_check::discriminant;
+use std::collections::{HashMap, HashSet};
+
+/// Integer helpers, which need the runtime
+const PRELUDE: &str = include_str!("prelude.c");
+
+/// Names a local or field can't have in C
+const RESERVED: &[&str] = &[
+  "auto", "break", "case", "char", "const", "continue", "default", "do", "double", "else", "enum",
+  "extern", "float", "for", "goto", "if", "inline", "int", "long", "register", "restrict", "return",
+  "short", "signed", "sizeof", "static", "struct", "switch", "typedef", "union", "unsigned", "void",
+  "volatile", "while", "bool", "true", "false", "NULL", "main", "int8_t", "int16_t", "int32_t",
+  "int64_t", "uint8_t", "uint16_t", "uint32_t", "uint64_t", "size_t", "strcmp", "fmod", "pow"
+];
+
+/// Translates a program that was checked without errors
+pub fn c_source(tree: &SourceNode) -> String {
+  let mut writer = Writer {
+    structs: vec![],
+    enums: vec![],
+    returns: HashMap::new(),
+    arrays: vec![],
+    prototypes: String::new(),
+    out: String::new(),
+    indent: 0,
+    locals: HashMap::new(),
+    taken: HashSet::new()
+  };
+
+  let mut functions = vec![];
+  for leaf in &tree.children {
+    if let Some(decl) = leaf.downcast_ref::<StructDeclNode>() {
+      writer.structs.push(decl);
+    } else if let Some(decl) = leaf.downcast_ref::<EnumDeclNode>() {
+      writer.enums.push((decl.name.clone(), variants(decl)));
+    } else if let Some(func) = leaf.downcast_ref::<FunctionDeclNode>() {
+      if let ExtraNodeData::String(name) = &func.value {
+        writer.returns.insert(name.clone(), func.ta.clone());
+        functions.push((name.as_str(), func));
+      }
+    }
+  }
+
+  writer.program(&functions)
+}
+
+/// Every variant of an enum with the discriminant the checker gave it
+fn variants(decl: &EnumDeclNode) -> Vec<(String, i64)> {
+  decl.variants.iter()
+    .filter_map(|variant| match (&variant.value, variant.children.first()) {
+      (ExtraNodeData::String(name), Some(value)) => Some((name.clone(), discriminant(value.as_ref())?)),
+      _ => None
+    })
+    .collect()
+}
+
+/// The type a value is stored as, arrays of any length are the same
+fn storage(ta: &NaskoType) -> NaskoType {
+  match ta {
+    NaskoType::Array(element, _) => NaskoType::Array(Box::new(storage(element)), None),
+    ta => ta.clone()
+  }
+}
+
+/// The C name of a function of the program
+fn function_name(name: &str) -> String {
+  format!("nu_{}", name)
+}
+
+/// The C name of a struct or enum of the program
+fn type_name(name: &str) -> String {
+  format!("nt_{}", name)
+}
+
+/// A name for the type usable in C identifiers
+fn mangle(ta: &NaskoType) -> String {
+  match ta {
+    NaskoType::Boolean => "bool".to_string(),
+    NaskoType::Array(element, _) => format!("array_{}", mangle(element)),
+    NaskoType::Struct(name) | NaskoType::Enum(name) => type_name(name),
+    ta => ta.to_string()
+  }
+}
+
+/// Declares `name` with the type `ty`, which may end in a `*`
+fn declaration(ty: &str, name: &str) -> String {
+  if ty.ends_with('*') { format!("{}{}", ty, name) } else { format!("{} {}", ty, name) }
+}
+
+/// Whether the expression names storage that a copy of the value would
+/// share arrays with
+fn is_place(node: &(dyn ASTNode + 'static)) -> bool {
+  if let Some(cast) = node.downcast_ref::<CastNode>() {
+    // Casts between different types make new values, and calls are never
+    // places
+    let value = cast.children[0].as_ref();
+    return storage(&cast.ta) == storage(value.ta()) && is_place(value);
+  }
+  node.is::<NameNode>() || node.is::<FieldAccessNode>() || node.is::<IndexNode>()
+}
+
+fn string_literal(s: &str) -> String {
+  let mut literal = String::from("\"");
+  for byte in s.bytes() {
+    match byte {
+      b'"' => literal.push_str("\\\""),
+      b'\\' => literal.push_str("\\\\"),
+      b'\n' => literal.push_str("\\n"),
+      b'\t' => literal.push_str("\\t"),
+      // Octal escapes always have three digits, so they can't run into the
+      // next character
+      0x20..=0x7e => literal.push(byte as char),
+      _ => literal.push_str(&format!("\\{:03o}", byte))
+    }
+  }
+  literal.push('"');
+  literal
+}
+
+struct Writer<'a> {
+  structs: Vec<&'a StructDeclNode>,
+  /// Variants of every enum, by enum name
+  enums: Vec<(String, Vec<(String, i64)>)>,
+  /// Return type of every function, by name
+  returns: HashMap<String, NaskoType>,
+  /// Element type of every array type used, each after its own element
+  /// types
+  arrays: Vec<NaskoType>,
+  /// Declarations of every function, so calls can come before definitions
+  prototypes: String,
+  out: String,
+  indent: usize,
+  /// C names of the locals of the function being written, by the span of
+  /// their declaration
+  locals: HashMap<Span, String>,
+  /// Names of the locals of the function being written
+  taken: HashSet<String>
+}
+
+impl<'a> Writer<'a> {
+  fn program(&mut self, functions: &[(&str, &'a FunctionDeclNode)]) -> String {
+    // Functions first, so the declarations include every array type they use
+    for (name, func) in functions {
+      self.function(name, func);
+    }
+    if let Some((_, main)) = functions.iter().find(|(name, _)| *name == "main") {
+      self.entry(main);
+    }
+    let definitions = std::mem::take(&mut self.out);
+
+    for (name, variants) in &self.enums {
+      self.out.push_str(&format!("typedef int64_t {};\n", type_name(name)));
+      let constants: Vec<String> = variants.iter()
+        .map(|(variant, value)| format!("  {}_{} = {}", type_name(name), variant, value))
+        .collect();
+      self.out.push_str(&format!("enum {{\n{}\n}};\n\n", constants.join(",\n")));
+    }
+    let structs = self.structs.clone();
+    for decl in &structs {
+      self.out.push_str(&format!("struct {};\n", type_name(&decl.name)));
+    }
+    if !structs.is_empty() {
+      self.out.push('\n');
+    }
+
+    // Struct definitions may add array types, which only need the structs
+    // to be declared
+    let mut struct_defs = String::new();
+    for decl in self.sorted_structs() {
+      let fields: Vec<String> = decl.fields.iter()
+        .map(|field| format!("  {};\n", declaration(&self.c_type(&field.ta), &self.field(field))))
+        .collect();
+      struct_defs.push_str(&format!("struct {} {{\n{}}};\n\n", type_name(&decl.name), fields.concat()));
+    }
+    // Array helpers and struct copies can call each other, so every copy
+    // is declared before any of them is defined
+    let mut copies = String::new();
+    for decl in &structs {
+      let ta = NaskoType::Struct(decl.name.clone());
+      if self.needs_copy(&ta) {
+        let c_type = self.c_type(&ta);
+        struct_defs.push_str(&format!("static inline {} {}({} value);\n", c_type, self.copy_function(&ta), c_type));
+      }
+      copies.push_str(&self.struct_copy(decl));
+    }
+    if !copies.is_empty() {
+      struct_defs.push('\n');
+    }
+
+    let mut arrays = String::new();
+    let mut helpers = String::new();
+    let mut i = 0;
+    while i < self.arrays.len() {
+      let element = self.arrays[i].clone();
+      arrays.push_str(&self.array_type(&element));
+      helpers.push_str(&self.array_helpers(&element));
+      i += 1;
+    }
+
+    format!(
+      "/* Generated by naskoc */\n\n{}\n{}\n{}{}{}{}{}{}\n{}",
+      RUNTIME, PRELUDE, std::mem::take(&mut self.out), arrays, struct_defs, helpers, copies, self.prototypes, definitions
+    )
+  }
+
+  /// Structs in an order where every struct comes after those it holds
+  fn sorted_structs(&self) -> Vec<&'a StructDeclNode> {
+    let mut sorted: Vec<&'a StructDeclNode> = vec![];
+    let mut pending = self.structs.clone();
+    while !pending.is_empty() {
+      let before = pending.len();
+      pending.retain(|decl| {
+        let ready = decl.fields.iter().all(|field| match &field.ta {
+          NaskoType::Struct(name) => sorted.iter().any(|done| done.name == *name),
+          _ => true
+        });
+        if ready {
+          sorted.push(decl);
+        }
+        !ready
+      });
+      // The checker rejects structs holding themselves, never loop on them
+      if pending.len() == before {
+        sorted.append(&mut pending);
+      }
+    }
+    sorted
+  }
+
+  fn c_type(&mut self, ta: &NaskoType) -> String {
+    match ta {
+      NaskoType::Int(kind) => format!("{}int{}_t", if kind.is_signed() { "" } else { "u" }, kind.bits()),
+      NaskoType::Float(kind) => kind.to_string(),
+      NaskoType::Boolean => "bool".to_string(),
+      NaskoType::String => "const char *".to_string(),
+      NaskoType::Struct(name) => format!("struct {}", type_name(name)),
+      NaskoType::Enum(name) => type_name(name),
+      NaskoType::Array(element, _) => {
+        let element = storage(element);
+        if !self.arrays.contains(&element) {
+          self.c_type(&element);
+          self.arrays.push(element.clone());
+        }
+        format!("nasko_array_{}", mangle(&element))
+      },
+      _ => "void".to_string()
+    }
+  }
+
+  /// Whether values of the type hold arrays
+  fn needs_copy(&self, ta: &NaskoType) -> bool {
+    self.holds_array(ta, &mut HashSet::new())
+  }
+
+  /// Whether values of the type hold arrays, not looking into the structs
+  /// in `visited` again
+  fn holds_array(&self, ta: &NaskoType, visited: &mut HashSet<String>) -> bool {
+    match ta {
+      NaskoType::Array(..) => true,
+      NaskoType::Struct(name) if visited.insert(name.clone()) => self.structs.iter()
+        .find(|decl| decl.name == *name)
+        .is_some_and(|decl| decl.fields.iter().any(|field| self.holds_array(&field.ta, visited))),
+      _ => false
+    }
+  }
+
+  /// Name of the function copying values of the type
+  fn copy_function(&mut self, ta: &NaskoType) -> String {
+    match ta {
+      NaskoType::Array(..) => format!("{}_copy", self.c_type(ta)),
+      _ => format!("nasko_{}_copy", mangle(ta))
+    }
+  }
+
+  fn field(&self, field: &ValueNode) -> String {
+    match &field.value {
+      ExtraNodeData::String(name) => self.ident(name),
+      _ => String::new()
+    }
+  }
+
+  /// A C identifier for a local or field of the program
+  fn ident(&self, name: &str) -> String {
+    let prefixed = ["nasko", "nu_", "nt_"].iter().any(|prefix| name.starts_with(prefix));
+    if RESERVED.contains(&name) || prefixed {
+      format!("{}_", name)
+    } else {
+      name.to_string()
+    }
+  }
+
+  fn array_type(&mut self, element: &NaskoType) -> String {
+    let array = format!("nasko_array_{}", mangle(element));
+    let data = declaration(&self.c_type(element), "*data");
+    format!("typedef struct {{\n  int64_t len;\n  {};\n}} {};\n\n", data, array)
+  }
+
+  fn array_helpers(&mut self, element: &NaskoType) -> String {
+    let array = format!("nasko_array_{}", mangle(element));
+    let element_type = self.c_type(element);
+    let mut out = String::new();
+
+    out.push_str(&format!(
+      "static inline {} {}_new(int64_t len, {}) {{\n  {} array = {{ len, naskort_alloc(len * sizeof *array.data) }};\n  \
+       if (len > 0) memcpy(array.data, elements, len * sizeof *array.data);\n  return array;\n}}\n\n",
+      array, array, declaration(&element_type, "*elements"), array
+    ));
+    out.push_str(&format!(
+      "static inline {}({} array, int64_t index) {{\n  return &array.data[nasko_index(array.len, index)];\n}}\n\n",
+      declaration(&element_type, &format!("*{}_at", array)), array
+    ));
+
+    out.push_str(&format!("static inline {} {}_copy({} array) {{\n  {} copy = {}_new(array.len, array.data);\n", array, array, array, array, array));
+    if self.needs_copy(element) {
+      let copy = self.copy_function(element);
+      out.push_str(&format!("  for (int64_t i = 0; i < copy.len; i++) {{\n    copy.data[i] = {}(copy.data[i]);\n  }}\n", copy));
+    }
+    out.push_str("  return copy;\n}\n\n");
+    out
+  }
+
+  fn struct_copy(&mut self, decl: &StructDeclNode) -> String {
+    let ta = NaskoType::Struct(decl.name.clone());
+    if !self.needs_copy(&ta) {
+      return String::new();
+    }
+
+    let c_type = self.c_type(&ta);
+    let mut out = format!("static inline {} {}({} value) {{\n", c_type, self.copy_function(&ta), c_type);
+    for field in &decl.fields {
+      if self.needs_copy(&field.ta) {
+        let name = self.field(field);
+        out.push_str(&format!("  value.{} = {}(value.{});\n", name, self.copy_function(&field.ta), name));
+      }
+    }
+    out.push_str("  return value;\n}\n\n");
+    out
+  }
+
+  fn line(&mut self, text: &str) {
+    for _ in 0..self.indent {
+      self.out.push_str("  ");
+    }
+    self.out.push_str(text);
+    self.out.push('\n');
+  }
+
+  /// Names a local, renaming it if the function already has one of that
+  /// name, since C doesn't let a body redeclare a parameter
+  fn declare(&mut self, name: &str, span: Span) -> String {
+    let base = self.ident(name);
+    let mut local = base.clone();
+    let mut n = 2;
+    while self.taken.contains(&local) {
+      local = format!("{}_{}", base, n);
+      n += 1;
+    }
+    self.taken.insert(local.clone());
+    self.locals.insert(span, local.clone());
+    local
+  }
+
+  fn function(&mut self, name: &str, func: &'a FunctionDeclNode) {
+    self.locals.clear();
+    self.taken.clear();
+
+    let mut params = vec![];
+    for param in &func.params {
+      if let ExtraNodeData::String(name) = &param.value {
+        let local = self.declare(name, param.span.clone());
+        params.push(declaration(&self.c_type(&param.ta), &local));
+      }
+    }
+    let params = if params.is_empty() { "void".to_string() } else { params.join(", ") };
+    let ret = self.c_type(&func.ta);
+
+    let signature = format!("static {}({})", declaration(&ret, &function_name(name)), params);
+    self.prototypes.push_str(&format!("{};\n", signature));
+    self.line(&format!("{} {{", signature));
+    self.indent += 1;
+    self.statements(&func.children);
+    self.indent -= 1;
+    self.line("}\n");
+  }
+
+  /// The C `main`, which calls the Nasko one
+  fn entry(&mut self, main: &FunctionDeclNode) {
+    match main.params.len() {
+      0 => self.line("int main(void) {\n  return nu_main();"),
+      1 => self.line("int main(int argc, char **argv) {\n  (void)argv;\n  return nu_main(argc);"),
+      _ => {
+        let array = self.c_type(&NaskoType::Array(Box::new(NaskoType::String), None));
+        self.line(&format!(
+          "int main(int argc, char **argv) {{\n  return nu_main(argc, {}_new(argc, (const char **)argv));",
+          array
+        ));
+      }
+    }
+    self.line("}");
+  }
+
+  fn statements(&mut self, statements: &[Box<dyn ASTNode>]) {
+    for statement in statements {
+      self.statement(statement.as_ref());
+    }
+  }
+
+  /// The statements of a block, or a single statement
+  fn body(&mut self, node: &(dyn ASTNode + 'static)) {
+    self.indent += 1;
+    match node.downcast_ref::<BlockNode>() {
+      Some(block) => self.statements(&block.children),
+      None => self.statement(node)
+    }
+    self.indent -= 1;
+  }
+
+  fn statement(&mut self, node: &(dyn ASTNode + 'static)) {
+    if let Some(decl) = node.downcast_ref::<VariableDeclNode>() {
+      // The initializer can't see the variable it initializes
+      let value = self.stored(decl.children[0].as_ref());
+      let ty = self.c_type(&decl.ta);
+      let name = self.declare(&decl.name, decl.span.clone());
+      return self.line(&format!("{} = {};", declaration(&ty, &name), value));
+    }
+    if let Some(block) = node.downcast_ref::<BlockNode>() {
+      self.line("{");
+      self.body(block);
+      return self.line("}");
+    }
+    let statement = match node.downcast_ref::<StatementNode>() {
+      Some(statement) => statement,
+      None => {
+        let value = self.value(node);
+        return self.line(&format!("{};", value));
+      }
+    };
+
+    match statement.ntype.as_str() {
+      "IfStatement" => {
+        self.if_statement(statement, "");
+        self.line("}");
+      },
+      "WhileStatement" => {
+        let cond = self.value(statement.children[0].as_ref());
+        self.line(&format!("while ({}) {{", cond));
+        self.body(statement.children[1].as_ref());
+        self.line("}");
+      },
+      "BreakStatement" => self.line("break;"),
+      "ContinueStatement" => self.line("continue;"),
+      "ReturnStatement" => match statement.children.first() {
+        // The locals die with the function, so the value needs no copy
+        Some(value) => {
+          let value = self.value(value.as_ref());
+          self.line(&format!("return {};", value));
+        },
+        None => self.line("return;")
+      },
+      "AssignmentStatement" => {
+        let value = self.stored(statement.children[1].as_ref());
+        let target = self.value(statement.children[0].as_ref());
+        self.line(&format!("{} = {};", target, value));
+      },
+      _ => for child in &statement.children {
+        let value = self.value(child.as_ref());
+        self.line(&format!("{};", value));
+      }
+    }
+  }
+
+  /// Writes an `if` and its `else if`s up to the closing brace
+  fn if_statement(&mut self, statement: &StatementNode, prefix: &str) {
+    let cond = self.value(statement.children[0].as_ref());
+    self.line(&format!("{}if ({}) {{", prefix, cond));
+    self.body(statement.children[1].as_ref());
+
+    let otherwise = match statement.children.get(2) {
+      Some(otherwise) => otherwise.as_ref(),
+      None => return
+    };
+    match otherwise.downcast_ref::<StatementNode>().filter(|s| s.ntype == "IfStatement") {
+      Some(chained) => self.if_statement(chained, "} else "),
+      None => {
+        self.line("} else {");
+        self.body(otherwise);
+      }
+    }
+  }
+
+  /// An expression whose value is stored somewhere new, copied if other
+  /// storage could share arrays with it
+  fn stored(&mut self, node: &(dyn ASTNode + 'static)) -> String {
+    let value = self.value(node);
+    let ta = self.type_of(node);
+    if is_place(node) && self.needs_copy(&ta) {
+      format!("{}({})", self.copy_function(&ta), value)
+    } else {
+      value
+    }
+  }
+
+  /// The type of an expression, which calls take from their function
+  fn type_of(&self, node: &(dyn ASTNode + 'static)) -> NaskoType {
+    match node.downcast_ref::<CallNode>() {
+      Some(call) => self.returns[&call.name].clone(),
+      None => node.ta().clone()
+    }
+  }
+
+  fn value(&mut self, node: &(dyn ASTNode + 'static)) -> String {
+    self.expression(node).0
+  }
+
+  /// An expression, in parentheses unless it's a primary or postfix one
+  fn operand(&mut self, node: &(dyn ASTNode + 'static)) -> String {
+    match self.expression(node) {
+      (text, true) => text,
+      (text, false) => format!("({})", text)
+    }
+  }
+
+  /// The C of an expression, and whether operators can apply to it as is
+  fn expression(&mut self, node: &(dyn ASTNode + 'static)) -> (String, bool) {
+    if let Some(name) = node.downcast_ref::<NameNode>() {
+      return match &name.binding {
+        Binding::Local(declaration) => (self.locals[declaration].clone(), true),
+        _ => unreachable!("only locals are checked to be values")
+      };
+    }
+    if let Some(value) = node.downcast_ref::<ValueNode>() {
+      return match &value.value {
+        ExtraNodeData::Integer(n) => self.integer(*n, &value.ta),
+        ExtraNodeData::Float(n) => (self.float(*n, &value.ta), true),
+        ExtraNodeData::Boolean(b) => (b.to_string(), true),
+        ExtraNodeData::String(s) => (string_literal(s), true),
+        _ => unreachable!("constants are checked")
+      };
+    }
+    if let Some(binary) = node.downcast_ref::<BinaryExpression>() {
+      return self.binary(binary);
+    }
+    if let Some(unary) = node.downcast_ref::<UnaryExpression>() {
+      return self.unary(unary);
+    }
+    if let Some(call) = node.downcast_ref::<CallNode>() {
+      let mut args = vec![];
+      for arg in &call.children {
+        args.push(self.stored(arg.as_ref()));
+      }
+      return (format!("{}({})", function_name(&call.name), args.join(", ")), true);
+    }
+    if let Some(cast) = node.downcast_ref::<CastNode>() {
+      return self.cast(cast.children[0].as_ref(), &cast.ta);
+    }
+    if let Some(literal) = node.downcast_ref::<ArrayLiteralNode>() {
+      let array = self.c_type(&literal.ta);
+      if literal.children.is_empty() {
+        return (format!("{}_new(0, NULL)", array), true);
+      }
+      let element = match &literal.ta {
+        NaskoType::Array(element, _) => self.c_type(element),
+        _ => unreachable!("array literals are arrays")
+      };
+      let mut elements = vec![];
+      for element in &literal.children {
+        elements.push(self.stored(element.as_ref()));
+      }
+      let len = elements.len();
+      return (format!("{}_new({}, ({}[]){{ {} }})", array, len, element, elements.join(", ")), true);
+    }
+    if let Some(index) = node.downcast_ref::<IndexNode>() {
+      let array = self.type_of(index.children[0].as_ref());
+      let array = self.c_type(&array);
+      let (object, i) = (self.value(index.children[0].as_ref()), self.value(index.children[1].as_ref()));
+      return (format!("*{}_at({}, {})", array, object, i), false);
+    }
+    if let Some(literal) = node.downcast_ref::<StructLiteralNode>() {
+      let mut fields = vec![];
+      for init in &literal.children {
+        let init = init.downcast_ref::<ValueNode>().expect("struct literals hold field initializers");
+        let value = self.stored(init.children[0].as_ref());
+        fields.push(format!(".{} = {}", self.field(init), value));
+      }
+      return (format!("(struct {}){{ {} }}", type_name(&literal.name), fields.join(", ")), true);
+    }
+    if let Some(access) = node.downcast_ref::<FieldAccessNode>() {
+      let object = self.operand(access.children[0].as_ref());
+      return (format!("{}.{}", object, self.ident(&access.field)), true);
+    }
+
+    unreachable!("every expression is translated")
+  }
+
+  fn integer(&mut self, n: i128, ta: &NaskoType) -> (String, bool) {
+    let literal = match ta {
+      NaskoType::Enum(name) => {
+        let variant = self.enums.iter()
+          .find(|(enum_name, _)| enum_name == name)
+          .and_then(|(_, variants)| variants.iter().find(|(_, value)| *value as i128 == n));
+        match variant {
+          Some((variant, _)) => format!("{}_{}", type_name(name), variant),
+          None => n.to_string()
+        }
+      },
+      NaskoType::Float(_) => return (self.float(n as f64, ta), true),
+      NaskoType::Int(IntKind::I64) if n == i64::MIN as i128 => "INT64_MIN".to_string(),
+      NaskoType::Int(IntKind::I64) => format!("INT64_C({})", n),
+      NaskoType::Int(IntKind::U64) => format!("UINT64_C({})", n),
+      NaskoType::Int(IntKind::I32) if n == i32::MIN as i128 => "INT32_MIN".to_string(),
+      NaskoType::Int(IntKind::U32) if n > i32::MAX as i128 => format!("{}u", n),
+      _ => n.to_string()
+    };
+    let atomic = !literal.starts_with('-');
+    (literal, atomic)
+  }
+
+  fn float(&self, n: f64, ta: &NaskoType) -> String {
+    let literal = format!("{:?}", n);
+    if *ta == NaskoType::Float(FloatKind::Float) { format!("{}f", literal) } else { literal }
+  }
+
+  fn binary(&mut self, binary: &BinaryExpression) -> (String, bool) {
+    let (lhs, rhs) = match (&binary.lhs, &binary.rhs) {
+      (Some(lhs), Some(rhs)) => (lhs.as_ref(), rhs.as_ref()),
+      _ => unreachable!("binary expressions have two operands")
+    };
+    let op = &binary.expression;
+    let ta = self.type_of(lhs);
+
+    if op.is_logical() || op.is_comparison() {
+      if ta == NaskoType::String {
+        let (a, b) = (self.value(lhs), self.value(rhs));
+        return (format!("strcmp({}, {}) {} 0", a, b, op), false);
+      }
+      let (a, b) = (self.operand(lhs), self.operand(rhs));
+      return (format!("{} {} {}", a, op, b), false);
+    }
+
+    match ta {
+      NaskoType::Int(kind) => {
+        let helper = match op {
+          NaskoArithmetic::BitAnd | NaskoArithmetic::BitOr | NaskoArithmetic::BitXor => {
+            let (a, b) = (self.operand(lhs), self.operand(rhs));
+            return (format!("{} {} {}", a, op, b), false);
+          },
+          NaskoArithmetic::Add => "add",
+          NaskoArithmetic::Subtract => "sub",
+          NaskoArithmetic::Multiply => "mul",
+          NaskoArithmetic::Divide => "div",
+          NaskoArithmetic::Modulo => "rem",
+          NaskoArithmetic::Power => "pow",
+          NaskoArithmetic::ShiftLeft => "shl",
+          _ => "shr"
+        };
+        let (a, b) = (self.value(lhs), self.value(rhs));
+        (format!("nasko_{}_{}({}, {})", helper, kind, a, b), true)
+      },
+      NaskoType::Float(kind) => {
+        let function = match op {
+          NaskoArithmetic::Modulo => "fmod",
+          NaskoArithmetic::Power => "pow",
+          _ => {
+            let (a, b) = (self.operand(lhs), self.operand(rhs));
+            return (format!("{} {} {}", a, op, b), false);
+          }
+        };
+        let (a, b) = (self.value(lhs), self.value(rhs));
+        match kind {
+          FloatKind::Double => (format!("{}({}, {})", function, a, b), true),
+          FloatKind::Float => (format!("(float){}({}, {})", function, a, b), false)
+        }
+      },
+      _ => unreachable!("arithmetic is checked to apply to numbers")
+    }
+  }
+
+  fn unary(&mut self, unary: &UnaryExpression) -> (String, bool) {
+    let operand = unary.children[0].as_ref();
+    if let (NaskoUnary::Negate, Some(literal)) = (&unary.expression, operand.downcast_ref::<ValueNode>()) {
+      if let ExtraNodeData::Integer(n) = &literal.value {
+        return self.integer(-n, &unary.ta);
+      }
+    }
+
+    match (&unary.expression, &unary.ta) {
+      (NaskoUnary::Negate, NaskoType::Int(kind)) => (format!("nasko_neg_{}({})", kind, self.value(operand)), true),
+      (NaskoUnary::BitNot, ta) => {
+        let ty = self.c_type(ta);
+        (format!("({})~{}", ty, self.operand(operand)), false)
+      },
+      (op, _) => (format!("{}{}", op, self.operand(operand)), false)
+    }
+  }
+
+  /// An `as` conversion, with the same results as in the interpreter
+  fn cast(&mut self, node: &(dyn ASTNode + 'static), to: &NaskoType) -> (String, bool) {
+    let from = self.type_of(node);
+    if storage(&from) == storage(to) {
+      return self.expression(node);
+    }
+
+    let function = match (&from, to) {
+      (NaskoType::Int(kind), NaskoType::String) if kind.is_signed() => "naskort_int_to_string".to_string(),
+      (NaskoType::Int(_), NaskoType::String) => "naskort_uint_to_string".to_string(),
+      (NaskoType::Float(_), NaskoType::String) => "naskort_float_to_string".to_string(),
+      (NaskoType::Boolean, NaskoType::String) => "naskort_bool_to_string".to_string(),
+      (NaskoType::Float(_), NaskoType::Int(kind)) => format!("nasko_{}_from_double", kind),
+      (NaskoType::Int(_), NaskoType::Boolean) => return (format!("{} != 0", self.operand(node)), false),
+      _ => {
+        let ty = self.c_type(to);
+        return (format!("({}){}", ty, self.operand(node)), false);
+      }
+    };
+    (format!("{}({})", function, self.value(node)), true)
+  }
+}
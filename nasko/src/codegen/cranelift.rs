@@ -0,0 +1,833 @@
+//! Lowering of the IR to machine code with Cranelift
+//!
+//! IR registers become Cranelift variables and IR blocks Cranelift blocks.
+//! Runtime errors call into the C runtime, which reports them and exits.
+
+use super::{is_aggregate, layout, size_of, ARRAY_HEADER};
+use crate::ir::{self, Const, Inst, Reg};
+use crate::semantics::*;
+use cranelift_codegen::ir as clif;
+use cranelift_codegen::ir::condcodes::{FloatCC, IntCC};
+use cranelift_codegen::ir::{types, AbiParam, InstBuilder, MemFlags, TrapCode, Type, Value};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_module::{default_libcall_names, DataDescription, DataId, FuncId, Linkage, Module};
+use cranelift_object::{ObjectBuilder, ObjectModule};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Strings, arrays and structs are pointers, only 64 bit targets are
+/// supported
+const POINTER: Type = types::I64;
+
+/// Reaching an `unreachable` instruction, or returning from a runtime error
+const UNREACHABLE: TrapCode = TrapCode::unwrap_user(1);
+
+/// Functions from the runtime and the C library, with their parameter and
+/// result types
+const LIBRARY: &[(&str, &[Type], Option<Type>)] = &[
+  ("naskort_panic", &[POINTER], None),
+  ("naskort_panic_bounds", &[types::I64, types::I64], None),
+  ("naskort_alloc", &[types::I64], Some(POINTER)),
+  ("naskort_args", &[types::I32, POINTER], Some(POINTER)),
+  ("naskort_pow", &[types::I64, types::I64], Some(types::I64)),
+  ("naskort_int_to_string", &[types::I64], Some(POINTER)),
+  ("naskort_uint_to_string", &[types::I64], Some(POINTER)),
+  ("naskort_bool_to_string", &[types::I32], Some(POINTER)),
+  ("naskort_float_to_string", &[types::F64], Some(POINTER)),
+  ("memcpy", &[POINTER, POINTER, types::I64], Some(POINTER)),
+  ("strcmp", &[POINTER, POINTER], Some(types::I32)),
+  ("fmod", &[types::F64, types::F64], Some(types::F64)),
+  ("pow", &[types::F64, types::F64], Some(types::F64))
+];
+
+const DIVIDE_BY_ZERO: &str = "Attempt to divide by zero";
+const REMAINDER_BY_ZERO: &str = "Attempt to calculate the remainder by zero";
+const NEGATIVE_POWER: &str = "Attempt to raise an integer to a negative power";
+
+/// Compiles a verified module to an object file for the host
+///
+/// Nasko functions are prefixed with `nasko_`, and a C `main` calls the
+/// Nasko one with the program's arguments.
+pub fn object(module: &ir::Module) -> Result<Vec<u8>, String> {
+  let mut flags = settings::builder();
+  flags.set("opt_level", "speed").map_err(error)?;
+  flags.set("is_pic", "true").map_err(error)?;
+  let isa = cranelift_native::builder()
+    .map_err(|err| format!("Unsupported host: {}", err))?
+    .finish(settings::Flags::new(flags))
+    .map_err(error)?;
+  let builder = ObjectBuilder::new(isa, "nasko", default_libcall_names()).map_err(error)?;
+
+  let mut gen = Generator::new(module, ObjectModule::new(builder))?;
+  for func in &module.functions {
+    gen.function(func)?;
+  }
+  // Copy functions are all declared upfront, by `Generator::new`
+  for i in 0..gen.copies.len() {
+    gen.copy_function(i)?;
+  }
+  gen.entry()?;
+
+  gen.module.finish().emit().map_err(error)
+}
+
+fn error<E: fmt::Display>(err: E) -> String {
+  format!("Code generation failed: {}", err)
+}
+
+fn variable(reg: Reg) -> Variable {
+  Variable::from_u32(reg.0 as u32)
+}
+
+/// The Cranelift type of a value of the type
+fn clif_type(ta: &NaskoType) -> Type {
+  match ta {
+    NaskoType::Int(IntKind::I8 | IntKind::U8) | NaskoType::Boolean => types::I8,
+    NaskoType::Int(IntKind::I16 | IntKind::U16) => types::I16,
+    NaskoType::Int(IntKind::I32 | IntKind::U32) => types::I32,
+    NaskoType::Int(IntKind::I64 | IntKind::U64) => types::I64,
+    NaskoType::Float(FloatKind::Float) => types::F32,
+    NaskoType::Float(FloatKind::Double) => types::F64,
+    _ => POINTER
+  }
+}
+
+fn is_signed(ta: &NaskoType) -> bool {
+  matches!(ta, NaskoType::Int(kind) if kind.is_signed())
+}
+
+fn int_cc(op: &NaskoArithmetic, signed: bool) -> IntCC {
+  match (op, signed) {
+    (NaskoArithmetic::Equal, _) => IntCC::Equal,
+    (NaskoArithmetic::NotEqual, _) => IntCC::NotEqual,
+    (NaskoArithmetic::Less, true) => IntCC::SignedLessThan,
+    (NaskoArithmetic::Less, false) => IntCC::UnsignedLessThan,
+    (NaskoArithmetic::LessEqual, true) => IntCC::SignedLessThanOrEqual,
+    (NaskoArithmetic::LessEqual, false) => IntCC::UnsignedLessThanOrEqual,
+    (NaskoArithmetic::Greater, true) => IntCC::SignedGreaterThan,
+    (NaskoArithmetic::Greater, false) => IntCC::UnsignedGreaterThan,
+    (_, true) => IntCC::SignedGreaterThanOrEqual,
+    (_, false) => IntCC::UnsignedGreaterThanOrEqual
+  }
+}
+
+/// Unordered operands, like comparisons with NaN, are only not equal
+fn float_cc(op: &NaskoArithmetic) -> FloatCC {
+  match op {
+    NaskoArithmetic::Equal => FloatCC::Equal,
+    NaskoArithmetic::NotEqual => FloatCC::NotEqual,
+    NaskoArithmetic::Less => FloatCC::LessThan,
+    NaskoArithmetic::LessEqual => FloatCC::LessThanOrEqual,
+    NaskoArithmetic::Greater => FloatCC::GreaterThan,
+    _ => FloatCC::GreaterThanOrEqual
+  }
+}
+
+/// The type copy functions are keyed by, arrays share one whatever their
+/// length
+fn copy_key(ta: &NaskoType) -> Option<NaskoType> {
+  match ta {
+    NaskoType::Array(element, _) => Some(NaskoType::Array(element.clone(), None)),
+    NaskoType::Struct(_) => Some(ta.clone()),
+    _ => None
+  }
+}
+
+struct Generator<'a> {
+  ir: &'a ir::Module,
+  module: ObjectModule,
+  /// Nasko functions, by name
+  functions: HashMap<&'a str, FuncId>,
+  /// Imported functions, by symbol
+  library: HashMap<&'static str, FuncId>,
+  /// String constants, by contents
+  strings: HashMap<String, DataId>,
+  /// The function deep copying each array and struct type
+  copies: Vec<(NaskoType, FuncId)>
+}
+
+impl<'a> Generator<'a> {
+  /// Declares every function and defines the string constants
+  fn new(ir: &'a ir::Module, mut module: ObjectModule) -> Result<Generator<'a>, String> {
+    let mut library = HashMap::new();
+    for (name, params, ret) in LIBRARY {
+      let mut sig = module.make_signature();
+      sig.params.extend(params.iter().map(|ta| AbiParam::new(*ta)));
+      sig.returns.extend(ret.map(AbiParam::new));
+      library.insert(*name, module.declare_function(name, Linkage::Import, &sig).map_err(error)?);
+    }
+
+    let mut functions = HashMap::new();
+    for func in &ir.functions {
+      let mut sig = module.make_signature();
+      sig.params.extend(func.params.iter().map(|param| AbiParam::new(clif_type(func.reg_type(*param)))));
+      if func.ret != NaskoType::Void {
+        sig.returns.push(AbiParam::new(clif_type(&func.ret)));
+      }
+      let id = module.declare_function(&format!("nasko_{}", func.name), Linkage::Local, &sig).map_err(error)?;
+      functions.insert(func.name.as_str(), id);
+    }
+
+    let mut gen = Generator {
+      ir,
+      module,
+      functions,
+      library,
+      strings: HashMap::new(),
+      copies: vec![]
+    };
+
+    for message in [DIVIDE_BY_ZERO, REMAINDER_BY_ZERO, NEGATIVE_POWER] {
+      gen.string(message)?;
+    }
+    for func in &ir.functions {
+      for ta in &func.regs {
+        gen.declare_copy(ta)?;
+      }
+      for inst in func.blocks.iter().flat_map(|block| &block.insts) {
+        if let Inst::Const { value: Const::String(s), .. } = inst {
+          gen.string(s)?;
+        }
+      }
+    }
+
+    Ok(gen)
+  }
+
+  fn string(&mut self, s: &str) -> Result<DataId, String> {
+    if let Some(id) = self.strings.get(s) {
+      return Ok(*id);
+    }
+
+    let mut bytes = s.as_bytes().to_vec();
+    bytes.push(0);
+    let mut data = DataDescription::new();
+    data.define(bytes.into_boxed_slice());
+    let id = self.module.declare_anonymous_data(false, false).map_err(error)?;
+    self.module.define_data(id, &data).map_err(error)?;
+
+    self.strings.insert(s.to_string(), id);
+    Ok(id)
+  }
+
+  /// Declares the copy functions of a type and of everything it contains
+  fn declare_copy(&mut self, ta: &NaskoType) -> Result<(), String> {
+    let key = match copy_key(ta) {
+      Some(key) => key,
+      None => return Ok(())
+    };
+    if self.copies.iter().any(|(copied, _)| *copied == key) {
+      return Ok(());
+    }
+
+    let mut sig = self.module.make_signature();
+    sig.params.push(AbiParam::new(POINTER));
+    sig.returns.push(AbiParam::new(POINTER));
+    let id = self.module.declare_anonymous_function(&sig).map_err(error)?;
+    self.copies.push((key.clone(), id));
+
+    match &key {
+      NaskoType::Array(element, _) => self.declare_copy(element),
+      _ => {
+        for (_, field) in &self.struct_def(&key).fields {
+          self.declare_copy(field)?;
+        }
+        Ok(())
+      }
+    }
+  }
+
+  fn struct_def(&self, ta: &NaskoType) -> &'a ir::StructDef {
+    match ta {
+      NaskoType::Struct(name) => self.ir.struct_def(name).expect("struct types are checked to exist"),
+      _ => unreachable!("`{}` is not a struct", ta)
+    }
+  }
+
+  fn function(&mut self, func: &'a ir::Function) -> Result<(), String> {
+    let id = self.functions[func.name.as_str()];
+    let mut ctx = self.module.make_context();
+    ctx.func.signature = self.module.declarations().get_function_decl(id).signature.clone();
+    let mut builder_ctx = FunctionBuilderContext::new();
+
+    let mut translator = Translator {
+      emit: Emitter::new(self, &mut ctx.func, &mut builder_ctx),
+      func,
+      blocks: vec![]
+    };
+    translator.body();
+    translator.emit.finish();
+
+    self.module.define_function(id, &mut ctx)
+      .map_err(|err| error(format!("in `{}`: {}", func.name, err)))
+  }
+
+  /// Defines the function deep copying the `i`th type of `copies`
+  fn copy_function(&mut self, i: usize) -> Result<(), String> {
+    let (ta, id) = self.copies[i].clone();
+    let mut ctx = self.module.make_context();
+    ctx.func.signature = self.module.declarations().get_function_decl(id).signature.clone();
+    let mut builder_ctx = FunctionBuilderContext::new();
+
+    let mut emit = Emitter::new(self, &mut ctx.func, &mut builder_ctx);
+    let entry = emit.builder.create_block();
+    emit.builder.append_block_params_for_function_params(entry);
+    emit.builder.switch_to_block(entry);
+    let source = emit.builder.block_params(entry)[0];
+    let copy = emit.copy_body(&ta, source);
+    emit.builder.ins().return_(&[copy]);
+    emit.finish();
+
+    self.module.define_function(id, &mut ctx).map_err(error)
+  }
+
+  /// Defines the C `main`, which calls the Nasko one
+  fn entry(&mut self) -> Result<(), String> {
+    let main = self.ir.function("main").expect("`main` is checked to exist");
+    let mut sig = self.module.make_signature();
+    sig.params.push(AbiParam::new(types::I32));
+    sig.params.push(AbiParam::new(POINTER));
+    sig.returns.push(AbiParam::new(types::I32));
+    let id = self.module.declare_function("main", Linkage::Export, &sig).map_err(error)?;
+    let mut ctx = self.module.make_context();
+    ctx.func.signature = sig;
+    let mut builder_ctx = FunctionBuilderContext::new();
+
+    let mut emit = Emitter::new(self, &mut ctx.func, &mut builder_ctx);
+    let entry = emit.builder.create_block();
+    emit.builder.append_block_params_for_function_params(entry);
+    emit.builder.switch_to_block(entry);
+    let (argc, argv) = (emit.builder.block_params(entry)[0], emit.builder.block_params(entry)[1]);
+    let args = emit.library("naskort_args", &[argc, argv]);
+    let code = emit.call(emit.gen.functions["main"], &[argc, args][..main.params.len()])
+      .expect("`main` is checked to return an int");
+    emit.builder.ins().return_(&[code]);
+    emit.finish();
+
+    self.module.define_function(id, &mut ctx).map_err(error)
+  }
+}
+
+/// Builds the body of a single Cranelift function
+struct Emitter<'g, 'a, 'f> {
+  gen: &'g mut Generator<'a>,
+  builder: FunctionBuilder<'f>,
+  /// Functions called so far, by their module ID
+  callees: HashMap<FuncId, clif::FuncRef>
+}
+
+impl<'g, 'a, 'f> Emitter<'g, 'a, 'f> {
+  fn new(gen: &'g mut Generator<'a>, func: &'f mut clif::Function, ctx: &'f mut FunctionBuilderContext) -> Emitter<'g, 'a, 'f> {
+    Emitter {
+      gen,
+      builder: FunctionBuilder::new(func, ctx),
+      callees: HashMap::new()
+    }
+  }
+
+  fn finish(mut self) {
+    self.builder.seal_all_blocks();
+    self.builder.finalize();
+  }
+
+  /// Calls a function, returning its result if it has one
+  fn call(&mut self, callee: FuncId, args: &[Value]) -> Option<Value> {
+    let Emitter { gen, builder, callees } = self;
+    let func_ref = *callees.entry(callee)
+      .or_insert_with(|| gen.module.declare_func_in_func(callee, builder.func));
+    let call = self.builder.ins().call(func_ref, args);
+    self.builder.inst_results(call).first().copied()
+  }
+
+  /// Calls a function of `LIBRARY`, returning a dummy value for those that
+  /// return nothing
+  fn library(&mut self, name: &str, args: &[Value]) -> Value {
+    let callee = self.gen.library[name];
+    match self.call(callee, args) {
+      Some(value) => value,
+      None => self.builder.ins().iconst(types::I64, 0)
+    }
+  }
+
+  /// A pointer to the bytes of a string constant
+  fn string(&mut self, s: &str) -> Value {
+    let id = self.gen.strings[s];
+    let data = self.gen.module.declare_data_in_func(id, self.builder.func);
+    self.builder.ins().symbol_value(POINTER, data)
+  }
+
+  /// An integer constant, truncated to the width of `ty`
+  fn int(&mut self, ty: Type, n: i128) -> Value {
+    let n = if ty.bits() < 64 { n & ((1 << ty.bits()) - 1) } else { n };
+    self.builder.ins().iconst(ty, n as i64)
+  }
+
+  /// Widens an integer to 64 bits
+  fn extend(&mut self, value: Value, signed: bool) -> Value {
+    let ty = self.builder.func.dfg.value_type(value);
+    match (ty == types::I64, signed) {
+      (true, _) => value,
+      (false, true) => self.builder.ins().sextend(types::I64, value),
+      (false, false) => self.builder.ins().uextend(types::I64, value)
+    }
+  }
+
+  /// Truncates a 64 bit integer to `ty`
+  fn reduce(&mut self, value: Value, ty: Type) -> Value {
+    if ty == types::I64 { value } else { self.builder.ins().ireduce(ty, value) }
+  }
+
+  fn alloc(&mut self, size: Value) -> Value {
+    self.library("naskort_alloc", &[size])
+  }
+
+  fn load(&mut self, ta: &NaskoType, address: Value, offset: i64) -> Value {
+    self.builder.ins().load(clif_type(ta), MemFlags::trusted(), address, offset as i32)
+  }
+
+  fn store(&mut self, value: Value, address: Value, offset: i64) {
+    self.builder.ins().store(MemFlags::trusted(), value, address, offset as i32);
+  }
+
+  /// Calls `function` from `LIBRARY` to report a runtime error if `cond`
+  /// holds
+  fn panic_if(&mut self, cond: Value, function: &str, args: &[Value]) {
+    let panic = self.builder.create_block();
+    let next = self.builder.create_block();
+    self.builder.set_cold_block(panic);
+    self.builder.ins().brif(cond, panic, &[], next, &[]);
+
+    self.builder.switch_to_block(panic);
+    self.library(function, args);
+    self.builder.ins().trap(UNREACHABLE);
+    self.builder.switch_to_block(next);
+  }
+
+  fn panic_with_message_if(&mut self, cond: Value, message: &str) {
+    let message = self.string(message);
+    self.panic_if(cond, "naskort_panic", &[message]);
+  }
+
+  /// A copy of a value that shares no memory with it
+  fn copy(&mut self, ta: &NaskoType, value: Value) -> Value {
+    let key = match copy_key(ta) {
+      Some(key) => key,
+      None => return value
+    };
+    let callee = self.gen.copies.iter()
+      .find(|(copied, _)| *copied == key)
+      .map(|(_, id)| *id)
+      .expect("copy functions are declared for every register type");
+    self.call(callee, &[value]).expect("copy functions return the copy")
+  }
+
+  /// Body of the copy function of an array or struct type
+  fn copy_body(&mut self, ta: &NaskoType, source: Value) -> Value {
+    if let NaskoType::Array(element, _) = ta {
+      let size = size_of(element);
+      let len = self.load(&NaskoType::Int(IntKind::I64), source, 0);
+      let bytes = self.builder.ins().imul_imm(len, size);
+      let bytes = self.builder.ins().iadd_imm(bytes, ARRAY_HEADER);
+      let copy = self.alloc(bytes);
+      self.library("memcpy", &[copy, source, bytes]);
+      if !is_aggregate(element) {
+        return copy;
+      }
+
+      // Then every element gets a copy of its own
+      let header = self.builder.create_block();
+      let body = self.builder.create_block();
+      let done = self.builder.create_block();
+      let i = self.builder.append_block_param(header, types::I64);
+      let zero = self.builder.ins().iconst(types::I64, 0);
+      self.builder.ins().jump(header, &[zero]);
+
+      self.builder.switch_to_block(header);
+      let more = self.builder.ins().icmp(IntCC::SignedLessThan, i, len);
+      self.builder.ins().brif(more, body, &[], done, &[]);
+
+      self.builder.switch_to_block(body);
+      let offset = self.builder.ins().imul_imm(i, size);
+      let address = self.builder.ins().iadd(copy, offset);
+      let value = self.load(element, address, ARRAY_HEADER);
+      let value = self.copy(element, value);
+      self.store(value, address, ARRAY_HEADER);
+      let next = self.builder.ins().iadd_imm(i, 1);
+      self.builder.ins().jump(header, &[next]);
+
+      self.builder.switch_to_block(done);
+      return copy;
+    }
+
+    let def = self.gen.struct_def(ta);
+    let (offsets, size) = layout(def);
+    let bytes = self.builder.ins().iconst(types::I64, size);
+    let copy = self.alloc(bytes);
+    self.library("memcpy", &[copy, source, bytes]);
+    for ((_, field), offset) in def.fields.iter().zip(offsets) {
+      if is_aggregate(field) {
+        let value = self.load(field, copy, offset);
+        let value = self.copy(field, value);
+        self.store(value, copy, offset);
+      }
+    }
+    copy
+  }
+
+  fn constant(&mut self, ta: &NaskoType, value: &Const) -> Value {
+    match value {
+      Const::Int(n) => self.int(clif_type(ta), *n),
+      Const::Float(n) if clif_type(ta) == types::F32 => self.builder.ins().f32const(*n as f32),
+      Const::Float(n) => self.builder.ins().f64const(*n),
+      Const::Boolean(b) => self.builder.ins().iconst(types::I8, *b as i64),
+      Const::String(s) => self.string(s)
+    }
+  }
+
+  /// `lhs op rhs` with both operands of type `ta`
+  fn binary(&mut self, op: &NaskoArithmetic, ta: &NaskoType, lhs: Value, rhs: Value) -> Value {
+    match ta {
+      NaskoType::Float(kind) => self.float_binary(op, *kind, lhs, rhs),
+      NaskoType::String => {
+        let order = self.library("strcmp", &[lhs, rhs]);
+        self.builder.ins().icmp_imm(int_cc(op, true), order, 0)
+      },
+      // Booleans are compared like unsigned integers
+      _ => self.int_binary(op, is_signed(ta), lhs, rhs)
+    }
+  }
+
+  fn int_binary(&mut self, op: &NaskoArithmetic, signed: bool, lhs: Value, rhs: Value) -> Value {
+    let ty = self.builder.func.dfg.value_type(lhs);
+    match op {
+      NaskoArithmetic::Add => self.builder.ins().iadd(lhs, rhs),
+      NaskoArithmetic::Subtract => self.builder.ins().isub(lhs, rhs),
+      NaskoArithmetic::Multiply => self.builder.ins().imul(lhs, rhs),
+      NaskoArithmetic::Divide | NaskoArithmetic::Modulo => {
+        let divide = *op == NaskoArithmetic::Divide;
+        let zero = self.builder.ins().icmp_imm(IntCC::Equal, rhs, 0);
+        self.panic_with_message_if(zero, if divide { DIVIDE_BY_ZERO } else { REMAINDER_BY_ZERO });
+        if !signed {
+          return if divide { self.builder.ins().udiv(lhs, rhs) } else { self.builder.ins().urem(lhs, rhs) };
+        }
+
+        // `MIN / -1` overflows and traps, in Nasko it wraps around
+        let minus_one = self.int(ty, -1);
+        let is_minus_one = self.builder.ins().icmp(IntCC::Equal, rhs, minus_one);
+        let one = self.builder.ins().iconst(ty, 1);
+        let divisor = self.builder.ins().select(is_minus_one, one, rhs);
+        if divide {
+          let quotient = self.builder.ins().sdiv(lhs, divisor);
+          let negated = self.builder.ins().ineg(lhs);
+          self.builder.ins().select(is_minus_one, negated, quotient)
+        } else {
+          self.builder.ins().srem(lhs, divisor)
+        }
+      },
+      NaskoArithmetic::Power => {
+        if signed {
+          let negative = self.builder.ins().icmp_imm(IntCC::SignedLessThan, rhs, 0);
+          self.panic_with_message_if(negative, NEGATIVE_POWER);
+        }
+        let base = self.extend(lhs, signed);
+        let exponent = self.extend(rhs, signed);
+        let n = self.library("naskort_pow", &[base, exponent]);
+        self.reduce(n, ty)
+      },
+      NaskoArithmetic::BitAnd => self.builder.ins().band(lhs, rhs),
+      NaskoArithmetic::BitOr => self.builder.ins().bor(lhs, rhs),
+      NaskoArithmetic::BitXor => self.builder.ins().bxor(lhs, rhs),
+      // Shift amounts are taken modulo the width of the type, like Cranelift
+      // does
+      NaskoArithmetic::ShiftLeft => self.builder.ins().ishl(lhs, rhs),
+      NaskoArithmetic::ShiftRight if signed => self.builder.ins().sshr(lhs, rhs),
+      NaskoArithmetic::ShiftRight => self.builder.ins().ushr(lhs, rhs),
+      _ => self.builder.ins().icmp(int_cc(op, signed), lhs, rhs)
+    }
+  }
+
+  fn float_binary(&mut self, op: &NaskoArithmetic, kind: FloatKind, lhs: Value, rhs: Value) -> Value {
+    match op {
+      NaskoArithmetic::Add => self.builder.ins().fadd(lhs, rhs),
+      NaskoArithmetic::Subtract => self.builder.ins().fsub(lhs, rhs),
+      NaskoArithmetic::Multiply => self.builder.ins().fmul(lhs, rhs),
+      NaskoArithmetic::Divide => self.builder.ins().fdiv(lhs, rhs),
+      NaskoArithmetic::Modulo | NaskoArithmetic::Power => {
+        let function = if *op == NaskoArithmetic::Modulo { "fmod" } else { "pow" };
+        if kind == FloatKind::Double {
+          return self.library(function, &[lhs, rhs]);
+        }
+        // Computed in double precision, like the interpreter does
+        let lhs = self.builder.ins().fpromote(types::F64, lhs);
+        let rhs = self.builder.ins().fpromote(types::F64, rhs);
+        let n = self.library(function, &[lhs, rhs]);
+        self.builder.ins().fdemote(types::F32, n)
+      },
+      _ => self.builder.ins().fcmp(float_cc(op), lhs, rhs)
+    }
+  }
+
+  fn unary(&mut self, op: &NaskoUnary, ta: &NaskoType, value: Value) -> Value {
+    match (op, ta) {
+      (NaskoUnary::Negate, NaskoType::Float(_)) => self.builder.ins().fneg(value),
+      (NaskoUnary::Negate, _) => self.builder.ins().ineg(value),
+      (NaskoUnary::Not, _) => self.builder.ins().bxor_imm(value, 1),
+      (NaskoUnary::BitNot, _) => self.builder.ins().bnot(value)
+    }
+  }
+
+  /// An `as` conversion, with the same results as in the interpreter
+  fn cast(&mut self, from: &NaskoType, to: &NaskoType, value: Value) -> Value {
+    let ty = clif_type(to);
+    match (from, to) {
+      (NaskoType::Int(kind), NaskoType::String) => {
+        let n = self.extend(value, kind.is_signed());
+        let function = if kind.is_signed() { "naskort_int_to_string" } else { "naskort_uint_to_string" };
+        self.library(function, &[n])
+      },
+      (NaskoType::Float(kind), NaskoType::String) => {
+        let n = match kind {
+          FloatKind::Float => self.builder.ins().fpromote(types::F64, value),
+          FloatKind::Double => value
+        };
+        self.library("naskort_float_to_string", &[n])
+      },
+      (NaskoType::Boolean, NaskoType::String) => {
+        let b = self.builder.ins().uextend(types::I32, value);
+        self.library("naskort_bool_to_string", &[b])
+      },
+      (NaskoType::Int(from), NaskoType::Int(_)) => {
+        let bits = self.builder.func.dfg.value_type(value).bits();
+        if bits > ty.bits() {
+          self.builder.ins().ireduce(ty, value)
+        } else if bits == ty.bits() {
+          value
+        } else if from.is_signed() {
+          self.builder.ins().sextend(ty, value)
+        } else {
+          self.builder.ins().uextend(ty, value)
+        }
+      },
+      (NaskoType::Int(from), NaskoType::Float(_)) if from.is_signed() => self.builder.ins().fcvt_from_sint(ty, value),
+      (NaskoType::Int(_), NaskoType::Float(_)) => self.builder.ins().fcvt_from_uint(ty, value),
+      // Saturating, with NaN as zero
+      (NaskoType::Float(_), NaskoType::Int(kind)) if kind.bits() >= 32 => {
+        if kind.is_signed() {
+          self.builder.ins().fcvt_to_sint_sat(ty, value)
+        } else {
+          self.builder.ins().fcvt_to_uint_sat(ty, value)
+        }
+      },
+      (NaskoType::Float(_), NaskoType::Int(kind)) => {
+        let n = self.builder.ins().fcvt_to_sint_sat(types::I32, value);
+        let min = self.builder.ins().iconst(types::I32, kind.min() as i64);
+        let max = self.builder.ins().iconst(types::I32, kind.max() as i64);
+        let n = self.builder.ins().smax(n, min);
+        let n = self.builder.ins().smin(n, max);
+        self.builder.ins().ireduce(ty, n)
+      },
+      (NaskoType::Float(from), NaskoType::Float(to)) if from == to => value,
+      (NaskoType::Float(_), NaskoType::Float(FloatKind::Double)) => self.builder.ins().fpromote(ty, value),
+      (NaskoType::Float(_), NaskoType::Float(_)) => self.builder.ins().fdemote(ty, value),
+      (NaskoType::Int(_), NaskoType::Boolean) => self.builder.ins().icmp_imm(IntCC::NotEqual, value, 0),
+      (NaskoType::Boolean, NaskoType::Int(kind)) if kind.bits() > 8 => self.builder.ins().uextend(ty, value),
+      (NaskoType::Boolean, NaskoType::Int(_)) => value,
+      // Forgetting the length of an array, or a cast to the same type
+      _ => self.copy(from, value)
+    }
+  }
+
+  /// The address of `array[index]`, less the size of the array header,
+  /// after checking the index is in bounds
+  fn element(&mut self, element: &NaskoType, array: Value, index: Value, signed: bool) -> Value {
+    let index = self.extend(index, signed);
+    let len = self.load(&NaskoType::Int(IntKind::I64), array, 0);
+    // Negative indices are out of bounds as large unsigned ones
+    let out_of_bounds = self.builder.ins().icmp(IntCC::UnsignedGreaterThanOrEqual, index, len);
+    self.panic_if(out_of_bounds, "naskort_panic_bounds", &[len, index]);
+
+    let offset = self.builder.ins().imul_imm(index, size_of(element));
+    self.builder.ins().iadd(array, offset)
+  }
+
+  fn array(&mut self, element: &NaskoType, values: &[Value]) -> Value {
+    let size = size_of(element);
+    let bytes = self.builder.ins().iconst(types::I64, ARRAY_HEADER + values.len() as i64 * size);
+    let array = self.alloc(bytes);
+    let len = self.builder.ins().iconst(types::I64, values.len() as i64);
+    self.store(len, array, 0);
+    for (i, value) in values.iter().enumerate() {
+      self.store(*value, array, ARRAY_HEADER + i as i64 * size);
+    }
+    array
+  }
+
+  fn structure(&mut self, ta: &NaskoType, values: &[Value]) -> Value {
+    let (offsets, size) = layout(self.gen.struct_def(ta));
+    let bytes = self.builder.ins().iconst(types::I64, size);
+    let object = self.alloc(bytes);
+    for (value, offset) in values.iter().zip(offsets) {
+      self.store(*value, object, offset);
+    }
+    object
+  }
+
+  /// Offset of a field of a struct
+  fn field(&self, ta: &NaskoType, field: usize) -> i64 {
+    layout(self.gen.struct_def(ta)).0[field]
+  }
+}
+
+/// Lowers one IR function
+struct Translator<'g, 'a, 'f> {
+  emit: Emitter<'g, 'a, 'f>,
+  func: &'a ir::Function,
+  /// The Cranelift block of every IR block
+  blocks: Vec<clif::Block>
+}
+
+impl<'g, 'a, 'f> Translator<'g, 'a, 'f> {
+  fn ta(&self, reg: Reg) -> &'a NaskoType {
+    self.func.reg_type(reg)
+  }
+
+  fn get(&mut self, reg: Reg) -> Value {
+    self.emit.builder.use_var(variable(reg))
+  }
+
+  fn set(&mut self, reg: Reg, value: Value) {
+    self.emit.builder.def_var(variable(reg), value);
+  }
+
+  /// The value of a register, copied if it's an array or struct
+  fn copied(&mut self, reg: Reg) -> Value {
+    let value = self.get(reg);
+    self.emit.copy(self.ta(reg), value)
+  }
+
+  fn body(&mut self) {
+    let builder = &mut self.emit.builder;
+    for (i, ta) in self.func.regs.iter().enumerate() {
+      if *ta != NaskoType::Void {
+        builder.declare_var(variable(Reg(i)), clif_type(ta));
+      }
+    }
+    self.blocks = self.func.blocks.iter().map(|_| builder.create_block()).collect();
+
+    // The first IR block may be jumped to, so it can't be the entry block
+    let entry = builder.create_block();
+    builder.append_block_params_for_function_params(entry);
+    builder.switch_to_block(entry);
+    let args = builder.block_params(entry).to_vec();
+    for (param, arg) in self.func.params.iter().zip(args) {
+      builder.def_var(variable(*param), arg);
+    }
+    builder.ins().jump(self.blocks[0], &[]);
+
+    for (i, block) in self.func.blocks.iter().enumerate() {
+      self.emit.builder.switch_to_block(self.blocks[i]);
+      for inst in &block.insts {
+        self.inst(inst);
+      }
+    }
+  }
+
+  fn inst(&mut self, inst: &Inst) {
+    match inst {
+      Inst::Const { dest, value } => {
+        let value = self.emit.constant(self.ta(*dest), value);
+        self.set(*dest, value);
+      },
+      Inst::Copy { dest, src } => {
+        let value = self.copied(*src);
+        self.set(*dest, value);
+      },
+      Inst::Binary { dest, op, lhs, rhs } => {
+        let (a, b) = (self.get(*lhs), self.get(*rhs));
+        let value = self.emit.binary(op, self.ta(*lhs), a, b);
+        self.set(*dest, value);
+      },
+      Inst::Unary { dest, op, src } => {
+        let value = self.get(*src);
+        let value = self.emit.unary(op, self.ta(*src), value);
+        self.set(*dest, value);
+      },
+      Inst::Cast { dest, src } => {
+        let value = self.get(*src);
+        let value = self.emit.cast(self.ta(*src), self.ta(*dest), value);
+        self.set(*dest, value);
+      },
+      Inst::Call { dest, func, args } => {
+        let args: Vec<Value> = args.iter().map(|arg| self.copied(*arg)).collect();
+        let callee = self.emit.gen.functions[func.as_str()];
+        let result = self.emit.call(callee, &args);
+        if let (Some(dest), Some(result)) = (dest, result) {
+          self.set(*dest, result);
+        }
+      },
+      Inst::Array { dest, elements } => {
+        let values: Vec<Value> = elements.iter().map(|element| self.copied(*element)).collect();
+        let array = match self.ta(*dest) {
+          NaskoType::Array(element, _) => self.emit.array(element, &values),
+          ta => unreachable!("`{}` is not an array", ta)
+        };
+        self.set(*dest, array);
+      },
+      Inst::Index { dest, array, index } => {
+        let address = self.element(*array, *index);
+        let value = self.emit.load(self.ta(*dest), address, ARRAY_HEADER);
+        let value = self.emit.copy(self.ta(*dest), value);
+        self.set(*dest, value);
+      },
+      Inst::SetIndex { array, index, value } => {
+        let address = self.element(*array, *index);
+        let value = self.copied(*value);
+        self.emit.store(value, address, ARRAY_HEADER);
+      },
+      Inst::Struct { dest, fields } => {
+        let values: Vec<Value> = fields.iter().map(|field| self.copied(*field)).collect();
+        let object = self.emit.structure(self.ta(*dest), &values);
+        self.set(*dest, object);
+      },
+      Inst::Field { dest, object, field } => {
+        let offset = self.emit.field(self.ta(*object), *field);
+        let address = self.get(*object);
+        let value = self.emit.load(self.ta(*dest), address, offset);
+        let value = self.emit.copy(self.ta(*dest), value);
+        self.set(*dest, value);
+      },
+      Inst::SetField { object, field, value } => {
+        let offset = self.emit.field(self.ta(*object), *field);
+        let address = self.get(*object);
+        let value = self.copied(*value);
+        self.emit.store(value, address, offset);
+      },
+      Inst::Jump { target } => {
+        self.emit.builder.ins().jump(self.blocks[target.0], &[]);
+      },
+      Inst::Branch { cond, then, otherwise } => {
+        let cond = self.get(*cond);
+        self.emit.builder.ins().brif(cond, self.blocks[then.0], &[], self.blocks[otherwise.0], &[]);
+      },
+      Inst::Return { value } => {
+        // The register dies with the function, so the value is moved
+        let values: Vec<Value> = value.iter().map(|reg| self.get(*reg)).collect();
+        self.emit.builder.ins().return_(&values);
+      },
+      Inst::Unreachable => {
+        self.emit.builder.ins().trap(UNREACHABLE);
+      }
+    }
+  }
+
+  fn element(&mut self, array: Reg, index: Reg) -> Value {
+    let element = match self.ta(array) {
+      NaskoType::Array(element, _) => element,
+      ta => unreachable!("`{}` is not an array", ta)
+    };
+    let (array, signed) = (self.get(array), is_signed(self.ta(index)));
+    let index = self.get(index);
+    self.emit.element(element, array, index, signed)
+  }
+}
@@ -0,0 +1,87 @@
+//! Native code generation
+//!
+//! The IR is compiled to an object file with Cranelift, which the system
+//! `cc` links together with a small C runtime into an executable.
+//!
+//! Strings are pointers to NUL-terminated bytes. Arrays and structs live on
+//! the heap behind a pointer: an array is its length as an `i64` followed
+//! by its elements, a struct is its fields in declaration order, each
+//! aligned to its size. IR registers hold values, so an aggregate is copied
+//! whenever it's stored somewhere it could be reached from twice. Memory is
+//! never freed.
+
+mod c;
+mod cranelift;
+
+pub use self::c::c_source;
+pub use self::cranelift::object;
+
+use crate::ir;
+use crate::semantics::*;
+use std::path::Path;
+use std::process::{self, Command};
+use std::{env, fs};
+
+/// Source of the runtime, compiled along with every program
+const RUNTIME: &str = include_str!("runtime.c");
+
+/// Size of the length stored before the elements of an array
+const ARRAY_HEADER: i64 = 8;
+
+/// Links an object file produced by `object` into an executable at `output`
+///
+/// Uses the compiler named by `CC`, or `cc`.
+pub fn link(object: &[u8], output: &Path) -> Result<(), String> {
+  let dir = env::temp_dir().join(format!("naskoc-{}", process::id()));
+  let object_path = dir.join("program.o");
+  let runtime_path = dir.join("runtime.c");
+  fs::create_dir_all(&dir)
+    .and_then(|_| fs::write(&object_path, object))
+    .and_then(|_| fs::write(&runtime_path, RUNTIME))
+    .map_err(|err| format!("Failed to write to {}: {}", dir.display(), err))?;
+
+  let cc = env::var("CC").unwrap_or_else(|_| "cc".to_string());
+  let status = Command::new(&cc)
+    .arg(&object_path)
+    .arg("-DNASKORT_NATIVE")
+    .arg(&runtime_path)
+    .arg("-lm")
+    .arg("-o")
+    .arg(output)
+    .status();
+  let _ = fs::remove_dir_all(&dir);
+
+  match status {
+    Ok(status) if status.success() => Ok(()),
+    Ok(status) => Err(format!("Linking with `{}` failed ({})", cc, status)),
+    Err(err) => Err(format!("Failed to run `{}`: {}", cc, err))
+  }
+}
+
+/// Whether values of the type are pointers to memory of their own
+fn is_aggregate(ta: &NaskoType) -> bool {
+  matches!(ta, NaskoType::Array(..) | NaskoType::Struct(_))
+}
+
+/// Size in bytes of a value of the type, inside an array or struct
+fn size_of(ta: &NaskoType) -> i64 {
+  match ta {
+    NaskoType::Int(kind) => kind.bits() as i64 / 8,
+    NaskoType::Float(FloatKind::Float) => 4,
+    NaskoType::Boolean => 1,
+    _ => 8
+  }
+}
+
+/// Offset of every field of a struct, and the size of the whole
+fn layout(def: &ir::StructDef) -> (Vec<i64>, i64) {
+  let mut offsets = vec![];
+  let mut size = 0;
+  for (_, ta) in &def.fields {
+    let field = size_of(ta);
+    size = (size + field - 1) / field * field;
+    offsets.push(size);
+    size += field;
+  }
+  (offsets, (size + 7) / 8 * 8)
+}
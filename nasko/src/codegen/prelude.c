@@ -0,0 +1,68 @@
+/* Integer operations of the C output, which wrap around on overflow and
+ * report division by zero like Nasko does. Arithmetic is done on unsigned
+ * types at least as wide as `int`, where C defines it to wrap. */
+
+#include <stdbool.h>
+
+#define NASKO_SIGNED(NAME, T, U, BITS, MIN, MAX) \
+  static inline T nasko_add_##NAME(T a, T b) { return (T)((U)a + (U)b); } \
+  static inline T nasko_sub_##NAME(T a, T b) { return (T)((U)a - (U)b); } \
+  static inline T nasko_mul_##NAME(T a, T b) { return (T)((U)a * (U)b); } \
+  static inline T nasko_neg_##NAME(T a) { return (T)(0 - (U)a); } \
+  static inline T nasko_div_##NAME(T a, T b) { \
+    if (b == 0) naskort_panic("Attempt to divide by zero"); \
+    return b == -1 ? nasko_neg_##NAME(a) : a / b; \
+  } \
+  static inline T nasko_rem_##NAME(T a, T b) { \
+    if (b == 0) naskort_panic("Attempt to calculate the remainder by zero"); \
+    return b == -1 ? 0 : a % b; \
+  } \
+  static inline T nasko_pow_##NAME(T a, T b) { \
+    if (b < 0) naskort_panic("Attempt to raise an integer to a negative power"); \
+    return (T)naskort_pow((uint64_t)a, (uint64_t)b); \
+  } \
+  static inline T nasko_shl_##NAME(T a, T b) { return (T)((U)a << ((U)b & (BITS - 1))); } \
+  static inline T nasko_shr_##NAME(T a, T b) { return (T)(a >> ((U)b & (BITS - 1))); } \
+  /* Saturating, with NaN as zero */ \
+  static inline T nasko_##NAME##_from_double(double x) { \
+    if (x != x) return 0; \
+    if (x <= (double)MIN) return MIN; \
+    if (x >= (double)MAX) return MAX; \
+    return (T)x; \
+  }
+
+#define NASKO_UNSIGNED(NAME, T, U, BITS, MAX) \
+  static inline T nasko_add_##NAME(T a, T b) { return (T)((U)a + (U)b); } \
+  static inline T nasko_sub_##NAME(T a, T b) { return (T)((U)a - (U)b); } \
+  static inline T nasko_mul_##NAME(T a, T b) { return (T)((U)a * (U)b); } \
+  static inline T nasko_div_##NAME(T a, T b) { \
+    if (b == 0) naskort_panic("Attempt to divide by zero"); \
+    return a / b; \
+  } \
+  static inline T nasko_rem_##NAME(T a, T b) { \
+    if (b == 0) naskort_panic("Attempt to calculate the remainder by zero"); \
+    return a % b; \
+  } \
+  static inline T nasko_pow_##NAME(T a, T b) { return (T)naskort_pow(a, b); } \
+  static inline T nasko_shl_##NAME(T a, T b) { return (T)((U)a << (b & (BITS - 1))); } \
+  static inline T nasko_shr_##NAME(T a, T b) { return (T)((U)a >> (b & (BITS - 1))); } \
+  static inline T nasko_##NAME##_from_double(double x) { \
+    if (x != x || x <= 0) return 0; \
+    if (x >= (double)MAX) return MAX; \
+    return (T)x; \
+  }
+
+NASKO_SIGNED(i8, int8_t, uint32_t, 8, INT8_MIN, INT8_MAX)
+NASKO_SIGNED(i16, int16_t, uint32_t, 16, INT16_MIN, INT16_MAX)
+NASKO_SIGNED(i32, int32_t, uint32_t, 32, INT32_MIN, INT32_MAX)
+NASKO_SIGNED(i64, int64_t, uint64_t, 64, INT64_MIN, INT64_MAX)
+NASKO_UNSIGNED(u8, uint8_t, uint32_t, 8, UINT8_MAX)
+NASKO_UNSIGNED(u16, uint16_t, uint32_t, 16, UINT16_MAX)
+NASKO_UNSIGNED(u32, uint32_t, uint32_t, 32, UINT32_MAX)
+NASKO_UNSIGNED(u64, uint64_t, uint64_t, 64, UINT64_MAX)
+
+/* Checks an index against the length of an array */
+static inline int64_t nasko_index(int64_t len, int64_t index) {
+  if (index < 0 || index >= len) naskort_panic_bounds(len, index);
+  return index;
+}
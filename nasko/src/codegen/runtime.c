@@ -0,0 +1,158 @@
+/* Runtime support for the programs naskoc compiles
+ *
+ * Linked into native executables with NASKORT_NATIVE defined, and copied
+ * into the C source output without it. Strings are NUL-terminated. Runtime
+ * errors exit with status 101, like `naskoc run`. */
+
+#include <math.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+void naskort_panic(const char *message) {
+  fprintf(stderr, "error: %s\n", message);
+  exit(101);
+}
+
+void naskort_panic_bounds(int64_t len, int64_t index) {
+  fprintf(stderr, "error: Index out of bounds: the length is %lld but the index is %lld\n",
+          (long long)len, (long long)index);
+  exit(101);
+}
+
+void *naskort_alloc(int64_t size) {
+  void *memory = malloc(size > 0 ? (size_t)size : 1);
+  if (!memory) {
+    naskort_panic("Out of memory");
+  }
+  return memory;
+}
+
+/* `base ** exponent`, wrapping on overflow. Taken modulo 2^64 the result
+ * is the same for signed and unsigned operands. */
+uint64_t naskort_pow(uint64_t base, uint64_t exponent) {
+  uint64_t result = 1;
+  while (exponent > 0) {
+    if (exponent & 1) {
+      result *= base;
+    }
+    base *= base;
+    exponent >>= 1;
+  }
+  return result;
+}
+
+char *naskort_int_to_string(int64_t n) {
+  char *s = naskort_alloc(24);
+  snprintf(s, 24, "%lld", (long long)n);
+  return s;
+}
+
+char *naskort_uint_to_string(uint64_t n) {
+  char *s = naskort_alloc(24);
+  snprintf(s, 24, "%llu", (unsigned long long)n);
+  return s;
+}
+
+const char *naskort_bool_to_string(int b) {
+  return b ? "true" : "false";
+}
+
+/* The shortest digits that read back as the same number, without an
+ * exponent, the way the interpreter prints floats */
+char *naskort_float_to_string(double n) {
+  if (isnan(n)) {
+    return "NaN";
+  }
+  if (isinf(n)) {
+    return n < 0 ? "-inf" : "inf";
+  }
+
+  char scientific[32];
+  for (int precision = 0; precision < 17; precision++) {
+    snprintf(scientific, sizeof scientific, "%.*e", precision, n);
+    if (strtod(scientific, NULL) == n) {
+      break;
+    }
+  }
+
+  /* `scientific` is `[-]d[.ddd]e(+|-)dd` */
+  char digits[32];
+  int count = 0;
+  char *c = scientific;
+  int negative = *c == '-';
+  if (negative) {
+    c++;
+  }
+  for (; *c != 'e'; c++) {
+    if (*c != '.') {
+      digits[count++] = *c;
+    }
+  }
+  while (count > 1 && digits[count - 1] == '0') {
+    count--;
+  }
+  /* Digits before the decimal point */
+  int point = atoi(c + 1) + 1;
+
+  char *s = naskort_alloc(count + (point < 0 ? -point : point) + 4);
+  char *out = s;
+  if (negative) {
+    *out++ = '-';
+  }
+  if (point <= 0) {
+    *out++ = '0';
+    *out++ = '.';
+    for (int i = point; i < 0; i++) {
+      *out++ = '0';
+    }
+    memcpy(out, digits, count);
+    out += count;
+  } else if (point >= count) {
+    memcpy(out, digits, count);
+    out += count;
+    for (int i = count; i < point; i++) {
+      *out++ = '0';
+    }
+  } else {
+    memcpy(out, digits, point);
+    out += point;
+    *out++ = '.';
+    memcpy(out, digits + point, count - point);
+    out += count - point;
+  }
+  *out = '\0';
+  return s;
+}
+
+#ifdef NASKORT_NATIVE
+#include <signal.h>
+#include <unistd.h>
+
+/* The `string[]` passed to `main`, an `int64_t` length followed by the
+ * elements */
+void *naskort_args(int argc, char **argv) {
+  int64_t *array = naskort_alloc(sizeof(int64_t) + (int64_t)argc * sizeof(char *));
+  array[0] = argc;
+  memcpy(array + 1, argv, argc * sizeof(char *));
+  return array;
+}
+
+/* Compiled code checks indices, so running out of stack is what faults */
+static void naskort_stack_overflow(int signal) {
+  static const char message[] = "error: Stack overflow\n";
+  (void)signal;
+  write(STDERR_FILENO, message, sizeof message - 1);
+  _exit(101);
+}
+
+/* The handler needs a stack of its own, the program's is full */
+__attribute__((constructor)) static void naskort_init(void) {
+  static char handler_stack[1 << 16];
+  stack_t stack = { .ss_sp = handler_stack, .ss_size = sizeof handler_stack };
+  struct sigaction action = { .sa_handler = naskort_stack_overflow, .sa_flags = SA_ONSTACK };
+  sigaltstack(&stack, NULL);
+  sigaction(SIGSEGV, &action, NULL);
+}
+#endif
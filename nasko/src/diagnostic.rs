@@ -0,0 +1,230 @@
+#![allow(dead_code)]
+
+//! Compiler diagnostics and their rendering
+
+use crate::lex::NaskoExtras;
+use std::fmt;
+
+/// A byte range into the source code
+pub type Span = std::ops::Range<usize>;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Severity {
+  Error,
+  Warning
+}
+
+impl fmt::Display for Severity {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Severity::Error => write!(f, "error"),
+      Severity::Warning => write!(f, "warning")
+    }
+  }
+}
+
+/// A span with an optional message printed under it
+#[derive(Debug, Clone)]
+pub struct Label {
+  pub span: Span,
+  pub message: String
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+  pub severity: Severity,
+  pub message: String,
+  pub primary: Label,
+  pub secondary: Vec<Label>,
+  pub notes: Vec<String>
+}
+
+impl Diagnostic {
+  pub fn new(severity: Severity, message: String, span: Span) -> Diagnostic {
+    Diagnostic {
+      severity,
+      message,
+      primary: Label { span, message: String::new() },
+      secondary: vec![],
+      notes: vec![]
+    }
+  }
+
+  pub fn error(message: String, span: Span) -> Diagnostic {
+    Diagnostic::new(Severity::Error, message, span)
+  }
+
+  pub fn warning(message: String, span: Span) -> Diagnostic {
+    Diagnostic::new(Severity::Warning, message, span)
+  }
+
+  /// Sets the message printed under the primary span
+  pub fn with_label(mut self, message: String) -> Diagnostic {
+    self.primary.message = message;
+    self
+  }
+
+  pub fn with_secondary(mut self, span: Span, message: String) -> Diagnostic {
+    self.secondary.push(Label { span, message });
+    self
+  }
+
+  pub fn with_note(mut self, note: String) -> Diagnostic {
+    self.notes.push(note);
+    self
+  }
+
+  pub fn is_error(&self) -> bool {
+    self.severity == Severity::Error
+  }
+
+  /// Renders the diagnostic as a rustc-style snippet
+  ///
+  /// Labels are grouped by file, starting with the file of the primary label
+  pub fn render(&self, sources: &SourceMap) -> String {
+    let mut labels: Vec<(&Label, char)> = vec![(&self.primary, '^')];
+    labels.extend(self.secondary.iter().map(|l| (l, '-')));
+    labels.sort_by_key(|(l, _)| l.span.start);
+
+    let last_line = labels.iter()
+      .map(|(l, _)| sources.file(l.span.start).location(l.span.start).0)
+      .max()
+      .unwrap_or(1);
+    let gutter = " ".repeat(last_line.to_string().len());
+
+    let mut files: Vec<&SourceFile> = vec![sources.file(self.primary.span.start)];
+    for (label, _) in &labels {
+      let file = sources.file(label.span.start);
+      if !files.iter().any(|f| f.base == file.base) {
+        files.push(file);
+      }
+    }
+
+    let mut out = format!("{}: {}\n", self.severity, self.message);
+    for (i, file) in files.into_iter().enumerate() {
+      let labels: Vec<&(&Label, char)> = labels.iter()
+        .filter(|(l, _)| sources.file(l.span.start).base == file.base)
+        .collect();
+      let start = if i == 0 { self.primary.span.start } else { labels[0].0.span.start };
+      let (line, column) = file.location(start);
+      let arrow = if i == 0 { "-->" } else { ":::" };
+
+      out.push_str(&format!("{}{} {}:{}:{}\n", gutter, arrow, file.path, line, column));
+      out.push_str(&format!("{} |\n", gutter));
+
+      let mut printed_line = 0;
+      for (label, marker) in labels {
+        let (line, column) = file.location(label.span.start);
+        let text = file.line(line);
+        if line != printed_line {
+          if printed_line != 0 && line > printed_line + 1 {
+            out.push_str("...\n");
+          }
+          out.push_str(&format!("{:>width$} | {}\n", line, text, width = gutter.len()));
+          printed_line = line;
+        }
+
+        // Spans running past the end of the line are cut at the line end
+        let span = file.relative(label.span.clone());
+        let width = file.code[span].lines().next()
+          .map(|s| s.chars().count())
+          .unwrap_or(0)
+          .max(1);
+        let underline = format!(
+          "{} | {}{} {}",
+          gutter,
+          " ".repeat(column - 1),
+          marker.to_string().repeat(width),
+          label.message
+        );
+        out.push_str(underline.trim_end());
+        out.push('\n');
+      }
+    }
+
+    for note in &self.notes {
+      out.push_str(&format!("{} |\n{} = note: {}\n", gutter, gutter, note));
+    }
+
+    out
+  }
+}
+
+/// A source file with the line information collected while lexing it
+///
+/// Spans into the file are offset by `base`, see `SourceMap`
+pub struct SourceFile {
+  pub path: String,
+  pub code: String,
+  pub base: usize,
+  pub line_heads: Vec<usize>
+}
+
+impl SourceFile {
+  /// Creates a source file from a fully consumed lexer's extras
+  pub fn new(path: String, code: String, base: usize, extras: &NaskoExtras) -> SourceFile {
+    SourceFile {
+      path,
+      code,
+      base,
+      line_heads: extras.line_heads()
+    }
+  }
+
+  /// Converts a span to byte offsets into `code`, clamped to the file
+  pub fn relative(&self, span: Span) -> Span {
+    let end = span.end.saturating_sub(self.base).min(self.code.len());
+    span.start.saturating_sub(self.base).min(end)..end
+  }
+
+  /// Gets the 1-based line and column of an offset
+  pub fn location(&self, offset: usize) -> (usize, usize) {
+    let offset = offset.saturating_sub(self.base).min(self.code.len());
+    let line = match self.line_heads.binary_search(&offset) {
+      Ok(i) => i,
+      Err(i) => i - 1
+    };
+    let head = self.line_heads[line];
+
+    (line + 1, self.code[head..offset].chars().count() + 1)
+  }
+
+  /// Gets the text of a 1-based line, without the line break
+  pub fn line(&self, line: usize) -> &str {
+    let head = self.line_heads[line - 1];
+    let end = self.line_heads.get(line).map(|h| h - 1).unwrap_or(self.code.len());
+
+    self.code[head..end].trim_end_matches('\r')
+  }
+}
+
+/// Every file of a compilation, laid out one after another in a single
+/// offset space so that spans from different files never overlap
+#[derive(Default)]
+pub struct SourceMap {
+  files: Vec<SourceFile>
+}
+
+impl SourceMap {
+  /// The base offset for the next file added
+  ///
+  /// Files are one byte apart so an empty span at the end of a file is not
+  /// mistaken for the start of the next one
+  pub fn next_base(&self) -> usize {
+    match self.files.last() {
+      Some(file) => file.base + file.code.len() + 1,
+      None => 0
+    }
+  }
+
+  pub fn add(&mut self, file: SourceFile) {
+    self.files.push(file);
+  }
+
+  /// Gets the file containing an offset
+  pub fn file(&self, offset: usize) -> &SourceFile {
+    self.files.iter().rev()
+      .find(|file| file.base <= offset)
+      .expect("source map is empty")
+  }
+}
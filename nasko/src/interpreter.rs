@@ -0,0 +1,487 @@
+//! Tree-walking evaluation of a type checked program
+//!
+//! Runs `main` directly over the annotated tree. Integers are kept as
+//! `i128` and wrapped to the width of their type after every operation,
+//! arrays and structs are values and are copied on assignment.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use crate::ast::*;
+use crate::diagnostic::{Diagnostic, Span};
+use crate::semantics::*;
+
+/// Calls deeper than this are reported as a stack overflow instead of
+/// overflowing the interpreter's own stack
+pub const MAX_CALL_DEPTH: usize = 10_000;
+
+/// Stack size the interpreter needs for `MAX_CALL_DEPTH` nested calls
+pub const STACK_SIZE: usize = 1 << 28;
+
+#[derive(Debug, PartialEq, Clone)]
+enum Value {
+  Int(i128),
+  Float(f64),
+  Boolean(bool),
+  String(String),
+  Array(Vec<Value>),
+  Struct(Vec<(String, Value)>),
+  Void
+}
+
+impl fmt::Display for Value {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Value::Int(n) => write!(f, "{}", n),
+      Value::Float(n) => write!(f, "{}", n),
+      Value::Boolean(b) => write!(f, "{}", b),
+      Value::String(s) => write!(f, "{}", s),
+      Value::Array(elements) => {
+        let elements: Vec<String> = elements.iter().map(|e| e.to_string()).collect();
+        write!(f, "[{}]", elements.join(", "))
+      },
+      Value::Struct(fields) => {
+        let fields: Vec<String> = fields.iter().map(|(name, value)| format!("{}: {}", name, value)).collect();
+        write!(f, "{{ {} }}", fields.join(", "))
+      },
+      Value::Void => write!(f, "void")
+    }
+  }
+}
+
+/// How a statement finished
+enum Flow {
+  Normal,
+  Break,
+  Continue,
+  Return(Value)
+}
+
+/// Where an assignment stores its value, below a local
+enum Step {
+  Field(String),
+  Index(i128, Span)
+}
+
+type RunResult<T> = Result<T, Diagnostic>;
+
+/// Runs `main` with `args` as its `argv`, returning the exit status
+///
+/// The tree must have been checked without errors. Deep recursion in the
+/// program is deep recursion here, the caller should provide a stack of
+/// `STACK_SIZE` bytes.
+pub fn run(tree: &SourceNode, args: Vec<String>) -> Result<i32, Diagnostic> {
+  Interpreter::new(tree).main(args)
+}
+
+struct Interpreter<'a> {
+  /// Every function, by name
+  functions: HashMap<&'a str, &'a FunctionDeclNode>,
+  /// Locals of the running function, by the span of their declaration
+  locals: HashMap<Span, Value>,
+  depth: usize
+}
+
+impl<'a> Interpreter<'a> {
+  fn new(tree: &'a SourceNode) -> Interpreter<'a> {
+    let mut functions = HashMap::new();
+    for leaf in &tree.children {
+      if let Some(func) = leaf.downcast_ref::<FunctionDeclNode>() {
+        if let ExtraNodeData::String(name) = &func.value {
+          functions.entry(name.as_str()).or_insert(func);
+        }
+      }
+    }
+
+    Interpreter {
+      functions,
+      locals: HashMap::new(),
+      depth: 0
+    }
+  }
+
+  fn main(&mut self, args: Vec<String>) -> RunResult<i32> {
+    let main = self.functions["main"];
+    let argc = Value::Int(args.len() as i128);
+    let argv = Value::Array(args.into_iter().map(Value::String).collect());
+    let args: Vec<Value> = vec![argc, argv].into_iter().take(main.params.len()).collect();
+
+    match self.call(main, args, main.span.clone())? {
+      Value::Int(code) => Ok(code as i32),
+      _ => Ok(0)
+    }
+  }
+
+  fn call(&mut self, func: &FunctionDeclNode, args: Vec<Value>, span: Span) -> RunResult<Value> {
+    if self.depth == MAX_CALL_DEPTH {
+      return Err(
+        Diagnostic::error("Stack overflow".to_string(), span)
+          .with_label(format!("more than {} nested calls", MAX_CALL_DEPTH))
+      );
+    }
+
+    let locals = func.params.iter().map(|param| param.span.clone()).zip(args).collect();
+    let caller = std::mem::replace(&mut self.locals, locals);
+    self.depth += 1;
+    let flow = self.statements(&func.children);
+    self.depth -= 1;
+    self.locals = caller;
+
+    match flow? {
+      Flow::Return(value) => Ok(value),
+      _ => Ok(Value::Void)
+    }
+  }
+
+  fn statements(&mut self, statements: &[Box<dyn ASTNode>]) -> RunResult<Flow> {
+    for statement in statements {
+      match self.statement(statement.as_ref())? {
+        Flow::Normal => {},
+        flow => return Ok(flow)
+      }
+    }
+    Ok(Flow::Normal)
+  }
+
+  fn statement(&mut self, node: &(dyn ASTNode + 'static)) -> RunResult<Flow> {
+    if let Some(decl) = node.downcast_ref::<VariableDeclNode>() {
+      let value = self.expression(decl.children[0].as_ref())?;
+      self.locals.insert(decl.span.clone(), value);
+      return Ok(Flow::Normal);
+    }
+    if let Some(block) = node.downcast_ref::<BlockNode>() {
+      return self.statements(&block.children);
+    }
+    let statement = match node.downcast_ref::<StatementNode>() {
+      Some(statement) => statement,
+      None => {
+        self.expression(node)?;
+        return Ok(Flow::Normal);
+      }
+    };
+
+    match statement.ntype.as_str() {
+      "IfStatement" => {
+        if self.condition(statement.children[0].as_ref())? {
+          self.statement(statement.children[1].as_ref())
+        } else if let Some(otherwise) = statement.children.get(2) {
+          self.statement(otherwise.as_ref())
+        } else {
+          Ok(Flow::Normal)
+        }
+      },
+      "WhileStatement" => {
+        while self.condition(statement.children[0].as_ref())? {
+          match self.statement(statement.children[1].as_ref())? {
+            Flow::Break => break,
+            Flow::Return(value) => return Ok(Flow::Return(value)),
+            Flow::Normal | Flow::Continue => {}
+          }
+        }
+        Ok(Flow::Normal)
+      },
+      "BreakStatement" => Ok(Flow::Break),
+      "ContinueStatement" => Ok(Flow::Continue),
+      "ReturnStatement" => match statement.children.first() {
+        Some(value) => Ok(Flow::Return(self.expression(value.as_ref())?)),
+        None => Ok(Flow::Return(Value::Void))
+      },
+      "AssignmentStatement" => {
+        let value = self.expression(statement.children[1].as_ref())?;
+        *self.place(statement.children[0].as_ref())? = value;
+        Ok(Flow::Normal)
+      },
+      _ => {
+        for child in &statement.children {
+          self.expression(child.as_ref())?;
+        }
+        Ok(Flow::Normal)
+      }
+    }
+  }
+
+  fn condition(&mut self, node: &(dyn ASTNode + 'static)) -> RunResult<bool> {
+    Ok(self.expression(node)? == Value::Boolean(true))
+  }
+
+  fn expression(&mut self, node: &(dyn ASTNode + 'static)) -> RunResult<Value> {
+    if let Some(value) = node.downcast_ref::<ValueNode>() {
+      return Ok(match &value.value {
+        ExtraNodeData::Integer(n) => Value::Int(*n),
+        ExtraNodeData::Float(n) => float(*n, &value.ta),
+        ExtraNodeData::Boolean(b) => Value::Boolean(*b),
+        ExtraNodeData::String(s) => Value::String(s.clone()),
+        _ => Value::Void
+      });
+    }
+    if let Some(name) = node.downcast_ref::<NameNode>() {
+      return match &name.binding {
+        Binding::Local(declaration) => Ok(self.locals[declaration].clone()),
+        _ => Ok(Value::Void)
+      };
+    }
+    if let Some(binary) = node.downcast_ref::<BinaryExpression>() {
+      return self.binary(binary);
+    }
+    if let Some(unary) = node.downcast_ref::<UnaryExpression>() {
+      let operand = self.expression(unary.children[0].as_ref())?;
+      return Ok(match (&unary.expression, operand) {
+        (NaskoUnary::Negate, Value::Int(n)) => int(-n, &unary.ta),
+        (NaskoUnary::Negate, Value::Float(n)) => float(-n, &unary.ta),
+        (NaskoUnary::Not, Value::Boolean(b)) => Value::Boolean(!b),
+        (NaskoUnary::BitNot, Value::Int(n)) => int(!n, &unary.ta),
+        (_, operand) => operand
+      });
+    }
+    if let Some(call) = node.downcast_ref::<CallNode>() {
+      let mut args = vec![];
+      for arg in &call.children {
+        args.push(self.expression(arg.as_ref())?);
+      }
+      let func = self.functions[call.name.as_str()];
+      return self.call(func, args, call.span.clone());
+    }
+    if let Some(cast) = node.downcast_ref::<CastNode>() {
+      let value = self.expression(cast.children[0].as_ref())?;
+      return Ok(convert(value, &cast.ta));
+    }
+    if let Some(literal) = node.downcast_ref::<ArrayLiteralNode>() {
+      let mut elements = vec![];
+      for element in &literal.children {
+        elements.push(self.expression(element.as_ref())?);
+      }
+      return Ok(Value::Array(elements));
+    }
+    if let Some(index) = node.downcast_ref::<IndexNode>() {
+      let array = self.expression(index.children[0].as_ref())?;
+      let i = self.index(index.children[1].as_ref())?;
+      return match array {
+        Value::Array(mut elements) => {
+          let i = bounds(i, elements.len(), index.children[1].span())?;
+          Ok(elements.swap_remove(i))
+        },
+        _ => Ok(Value::Void)
+      };
+    }
+    if let Some(literal) = node.downcast_ref::<StructLiteralNode>() {
+      let mut fields = vec![];
+      for init in &literal.children {
+        if let Some(init) = init.downcast_ref::<ValueNode>() {
+          if let ExtraNodeData::String(field) = &init.value {
+            fields.push((field.clone(), self.expression(init.children[0].as_ref())?));
+          }
+        }
+      }
+      return Ok(Value::Struct(fields));
+    }
+    if let Some(access) = node.downcast_ref::<FieldAccessNode>() {
+      return match self.expression(access.children[0].as_ref())? {
+        Value::Struct(fields) => Ok(fields.into_iter()
+          .find(|(field, _)| *field == access.field)
+          .map(|(_, value)| value)
+          .unwrap_or(Value::Void)),
+        _ => Ok(Value::Void)
+      };
+    }
+
+    Ok(Value::Void)
+  }
+
+  fn index(&mut self, node: &(dyn ASTNode + 'static)) -> RunResult<i128> {
+    match self.expression(node)? {
+      Value::Int(i) => Ok(i),
+      _ => Ok(0)
+    }
+  }
+
+  fn binary(&mut self, binary: &BinaryExpression) -> RunResult<Value> {
+    let (lhs, rhs) = match (&binary.lhs, &binary.rhs) {
+      (Some(lhs), Some(rhs)) => (lhs.as_ref(), rhs.as_ref()),
+      _ => return Ok(Value::Void)
+    };
+    let op = &binary.expression;
+
+    // Logical operators only evaluate the right side if they need it
+    if op.is_logical() {
+      let lhs = self.condition(lhs)?;
+      return match op {
+        NaskoArithmetic::And if !lhs => Ok(Value::Boolean(false)),
+        NaskoArithmetic::Or if lhs => Ok(Value::Boolean(true)),
+        _ => Ok(Value::Boolean(self.condition(rhs)?))
+      };
+    }
+
+    let (a, b) = (self.expression(lhs)?, self.expression(rhs)?);
+    if op.is_comparison() {
+      let ordering = match (&a, &b) {
+        (Value::Int(a), Value::Int(b)) => a.partial_cmp(b),
+        (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+        _ => None
+      };
+      return Ok(Value::Boolean(match op {
+        NaskoArithmetic::Equal => a == b,
+        NaskoArithmetic::NotEqual => a != b,
+        NaskoArithmetic::Less => ordering == Some(Ordering::Less),
+        NaskoArithmetic::LessEqual => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+        NaskoArithmetic::Greater => ordering == Some(Ordering::Greater),
+        _ => matches!(ordering, Some(Ordering::Greater | Ordering::Equal))
+      }));
+    }
+
+    match (a, b) {
+      (Value::Int(a), Value::Int(b)) => {
+        let n = match op {
+          NaskoArithmetic::Add => a.wrapping_add(b),
+          NaskoArithmetic::Subtract => a.wrapping_sub(b),
+          NaskoArithmetic::Multiply => a.wrapping_mul(b),
+          NaskoArithmetic::Divide | NaskoArithmetic::Modulo if b == 0 => {
+            let action = if *op == NaskoArithmetic::Divide { "divide" } else { "calculate the remainder" };
+            return Err(
+              Diagnostic::error(format!("Attempt to {} by zero", action), binary.span.clone())
+                .with_label("the divisor is zero".to_string())
+            );
+          },
+          NaskoArithmetic::Divide => a / b,
+          NaskoArithmetic::Modulo => a % b,
+          NaskoArithmetic::Power if b < 0 => return Err(
+            Diagnostic::error("Attempt to raise an integer to a negative power".to_string(), binary.span.clone())
+              .with_label(format!("the exponent is {}", b))
+          ),
+          NaskoArithmetic::Power => power(a, b),
+          NaskoArithmetic::BitAnd => a & b,
+          NaskoArithmetic::BitOr => a | b,
+          NaskoArithmetic::BitXor => a ^ b,
+          // Shift amounts are taken modulo the width of the type
+          NaskoArithmetic::ShiftLeft => a << shift_amount(b, &binary.ta),
+          NaskoArithmetic::ShiftRight => a >> shift_amount(b, &binary.ta),
+          _ => a
+        };
+        Ok(int(n, &binary.ta))
+      },
+      (Value::Float(a), Value::Float(b)) => {
+        let n = match op {
+          NaskoArithmetic::Add => a + b,
+          NaskoArithmetic::Subtract => a - b,
+          NaskoArithmetic::Multiply => a * b,
+          NaskoArithmetic::Divide => a / b,
+          NaskoArithmetic::Modulo => a % b,
+          NaskoArithmetic::Power => a.powf(b),
+          _ => a
+        };
+        Ok(float(n, &binary.ta))
+      },
+      (a, _) => Ok(a)
+    }
+  }
+
+  /// The storage an assignment target refers to
+  fn place(&mut self, node: &(dyn ASTNode + 'static)) -> RunResult<&mut Value> {
+    // Indices are evaluated before anything is borrowed
+    let mut steps = vec![];
+    let mut root = node;
+    loop {
+      if let Some(access) = root.downcast_ref::<FieldAccessNode>() {
+        steps.push(Step::Field(access.field.clone()));
+        root = access.children[0].as_ref();
+      } else if let Some(index) = root.downcast_ref::<IndexNode>() {
+        let i = self.index(index.children[1].as_ref())?;
+        steps.push(Step::Index(i, index.children[1].span()));
+        root = index.children[0].as_ref();
+      } else {
+        break;
+      }
+    }
+
+    let declaration = match root.downcast_ref::<NameNode>().map(|name| &name.binding) {
+      Some(Binding::Local(declaration)) => declaration,
+      _ => unreachable!("assignment targets are checked to be places")
+    };
+    let mut place = self.locals.get_mut(declaration).expect("locals are declared before use");
+    for step in steps.into_iter().rev() {
+      place = match (step, place) {
+        (Step::Field(field), Value::Struct(fields)) => match fields.iter_mut().find(|(name, _)| *name == field) {
+          Some((_, value)) => value,
+          None => unreachable!("fields are checked to exist")
+        },
+        (Step::Index(i, span), Value::Array(elements)) => {
+          let i = bounds(i, elements.len(), span)?;
+          &mut elements[i]
+        },
+        _ => unreachable!("assignment targets are checked to be places")
+      };
+    }
+
+    Ok(place)
+  }
+}
+
+/// Checks an index against the length of an array
+fn bounds(i: i128, len: usize, span: Span) -> RunResult<usize> {
+  if i < 0 || i >= len as i128 {
+    return Err(
+      Diagnostic::error(format!("Index out of bounds: the length is {} but the index is {}", len, i), span)
+        .with_label("index out of bounds".to_string())
+    );
+  }
+  Ok(i as usize)
+}
+
+/// An integer wrapped into the range of its type
+fn int(n: i128, ta: &NaskoType) -> Value {
+  match ta {
+    NaskoType::Int(kind) => Value::Int(wrap(n, *kind)),
+    _ => Value::Int(n)
+  }
+}
+
+fn wrap(n: i128, kind: IntKind) -> i128 {
+  let bits = kind.bits();
+  let n = n & ((1 << bits) - 1);
+  if kind.is_signed() && n > kind.max() { n - (1 << bits) } else { n }
+}
+
+/// A float rounded to the precision of its type
+fn float(n: f64, ta: &NaskoType) -> Value {
+  match ta {
+    NaskoType::Float(FloatKind::Float) => Value::Float(n as f32 as f64),
+    _ => Value::Float(n)
+  }
+}
+
+/// `base ** exponent`, wrapping on overflow
+fn power(mut base: i128, mut exponent: i128) -> i128 {
+  let mut result: i128 = 1;
+  while exponent > 0 {
+    if exponent & 1 == 1 {
+      result = result.wrapping_mul(base);
+    }
+    base = base.wrapping_mul(base);
+    exponent >>= 1;
+  }
+  result
+}
+
+fn shift_amount(amount: i128, ta: &NaskoType) -> u32 {
+  let bits = match ta {
+    NaskoType::Int(kind) => kind.bits(),
+    _ => 64
+  };
+  amount.rem_euclid(bits as i128) as u32
+}
+
+/// The value of an `as` cast
+///
+/// Integers wrap into smaller types, floats saturate when converted to
+/// integers and truncate towards zero.
+fn convert(value: Value, to: &NaskoType) -> Value {
+  match (value, to) {
+    (Value::Int(n), NaskoType::Int(_)) => int(n, to),
+    (Value::Int(n), NaskoType::Float(_)) => float(n as f64, to),
+    (Value::Int(n), NaskoType::Boolean) => Value::Boolean(n != 0),
+    (Value::Float(n), NaskoType::Int(kind)) => Value::Int((n as i128).max(kind.min()).min(kind.max())),
+    (Value::Float(n), NaskoType::Float(_)) => float(n, to),
+    (Value::Boolean(b), NaskoType::Int(_)) => Value::Int(b as i128),
+    (value, NaskoType::String) => Value::String(value.to_string()),
+    (value, _) => value
+  }
+}
@@ -0,0 +1,497 @@
+//! Lowering of the checked tree into the IR
+
+use std::collections::HashMap;
+use crate::ast::*;
+use crate::diagnostic::Span;
+use crate::semantics::*;
+use super::*;
+
+/// Lowers a program that was checked without errors
+pub fn lower(tree: &SourceNode) -> Module {
+  let mut module = Module::default();
+  let mut signatures = HashMap::new();
+
+  for leaf in &tree.children {
+    if let Some(decl) = leaf.downcast_ref::<StructDeclNode>() {
+      module.structs.push(StructDef {
+        name: decl.name.clone(),
+        fields: decl.fields.iter().filter_map(|field| match &field.value {
+          ExtraNodeData::String(name) => Some((name.clone(), ir_type(&field.ta))),
+          _ => None
+        }).collect()
+      });
+    } else if let Some(func) = leaf.downcast_ref::<FunctionDeclNode>() {
+      if let ExtraNodeData::String(name) = &func.value {
+        let params = func.params.iter().map(|param| ir_type(&param.ta)).collect();
+        signatures.insert(name.clone(), (params, ir_type(&func.ta)));
+      }
+    }
+  }
+
+  for leaf in &tree.children {
+    if let Some(func) = leaf.downcast_ref::<FunctionDeclNode>() {
+      let function = Builder::new(&module.structs, &signatures).function(func);
+      module.functions.push(function);
+    }
+  }
+
+  module
+}
+
+/// The IR type of a checked type, enums are their discriminants
+fn ir_type(ta: &NaskoType) -> NaskoType {
+  match ta {
+    NaskoType::Enum(_) => NaskoType::Int(IntKind::I64),
+    NaskoType::Array(element, len) => NaskoType::Array(Box::new(ir_type(element)), *len),
+    ta => ta.clone()
+  }
+}
+
+/// Where an assignment stores its value, below a local
+enum Step {
+  Field(usize),
+  Index(Reg)
+}
+
+/// Builds the IR of one function
+struct Builder<'a> {
+  structs: &'a [StructDef],
+  /// Parameter and return types of every function, by name
+  signatures: &'a HashMap<String, (Vec<NaskoType>, NaskoType)>,
+  func: Function,
+  /// The block instructions are added to, `None` after a jump
+  current: Option<BlockId>,
+  /// Register of every variable and parameter, by the span of its
+  /// declaration
+  vars: HashMap<Span, Reg>,
+  /// Continue and break targets of the loops around the current statement
+  loops: Vec<(BlockId, BlockId)>
+}
+
+impl<'a> Builder<'a> {
+  fn new(structs: &'a [StructDef], signatures: &'a HashMap<String, (Vec<NaskoType>, NaskoType)>) -> Builder<'a> {
+    Builder {
+      structs,
+      signatures,
+      func: Function {
+        name: String::new(),
+        params: vec![],
+        ret: NaskoType::Void,
+        regs: vec![],
+        blocks: vec![]
+      },
+      current: None,
+      vars: HashMap::new(),
+      loops: vec![]
+    }
+  }
+
+  fn function(mut self, decl: &FunctionDeclNode) -> Function {
+    if let ExtraNodeData::String(name) = &decl.value {
+      self.func.name = name.clone();
+    }
+    self.func.ret = ir_type(&decl.ta);
+    for param in &decl.params {
+      let reg = self.reg(ir_type(&param.ta));
+      self.func.params.push(reg);
+      self.vars.insert(param.span.clone(), reg);
+    }
+
+    let entry = self.block();
+    self.current = Some(entry);
+    self.statements(&decl.children);
+
+    // Falling off the end is only possible without a return value
+    if self.func.ret == NaskoType::Void {
+      self.emit(Inst::Return { value: None });
+    } else {
+      self.emit(Inst::Unreachable);
+    }
+
+    self.remove_unreachable();
+    self.func
+  }
+
+  fn reg(&mut self, ta: NaskoType) -> Reg {
+    self.func.regs.push(ta);
+    Reg(self.func.regs.len() - 1)
+  }
+
+  fn block(&mut self) -> BlockId {
+    self.func.blocks.push(Block::default());
+    BlockId(self.func.blocks.len() - 1)
+  }
+
+  /// Adds an instruction to the current block, a terminator also ends it
+  ///
+  /// Code after a jump is never run and is dropped.
+  fn emit(&mut self, inst: Inst) {
+    if let Some(current) = self.current {
+      let terminator = inst.is_terminator();
+      self.func.blocks[current.0].insts.push(inst);
+      if terminator {
+        self.current = None;
+      }
+    }
+  }
+
+  fn switch(&mut self, block: BlockId) {
+    self.current = Some(block);
+  }
+
+  /// Drops the blocks no jump leads to and renumbers the others
+  fn remove_unreachable(&mut self) {
+    let mut reachable = vec![false; self.func.blocks.len()];
+    let mut pending = vec![BlockId(0)];
+    while let Some(block) = pending.pop() {
+      if !std::mem::replace(&mut reachable[block.0], true) {
+        if let Some(terminator) = self.func.block(block).insts.last() {
+          pending.extend(terminator.successors());
+        }
+      }
+    }
+
+    let mut numbers = vec![BlockId(0); reachable.len()];
+    let mut next = 0;
+    for (i, reachable) in reachable.iter().enumerate() {
+      if *reachable {
+        numbers[i] = BlockId(next);
+        next += 1;
+      }
+    }
+
+    let blocks = std::mem::take(&mut self.func.blocks);
+    for (i, mut block) in blocks.into_iter().enumerate() {
+      if !reachable[i] {
+        continue;
+      }
+      if let Some(terminator) = block.insts.last_mut() {
+        match terminator {
+          Inst::Jump { target } => *target = numbers[target.0],
+          Inst::Branch { then, otherwise, .. } => {
+            *then = numbers[then.0];
+            *otherwise = numbers[otherwise.0];
+          },
+          _ => {}
+        }
+      }
+      self.func.blocks.push(block);
+    }
+  }
+
+  /// Converts a value to the type it is stored as, only needed to forget
+  /// the length of an array
+  fn coerce(&mut self, src: Reg, ta: &NaskoType) -> Reg {
+    if self.func.reg_type(src) == ta {
+      return src;
+    }
+    let dest = self.reg(ta.clone());
+    self.emit(Inst::Cast { dest, src });
+    dest
+  }
+
+  fn struct_def(&self, ta: &NaskoType) -> &'a StructDef {
+    let structs = self.structs;
+    match ta {
+      NaskoType::Struct(name) => structs.iter().find(|def| def.name == *name).expect("struct types are checked to exist"),
+      _ => unreachable!("fields are only accessed on structs")
+    }
+  }
+
+  fn field(&self, ta: &NaskoType, field: &str) -> (usize, NaskoType) {
+    let def = self.struct_def(ta);
+    let index = def.fields.iter().position(|(name, _)| name == field).expect("fields are checked to exist");
+    (index, def.fields[index].1.clone())
+  }
+
+  fn statements(&mut self, statements: &[Box<dyn ASTNode>]) {
+    for statement in statements {
+      self.statement(statement.as_ref());
+    }
+  }
+
+  fn statement(&mut self, node: &(dyn ASTNode + 'static)) {
+    if let Some(decl) = node.downcast_ref::<VariableDeclNode>() {
+      let ta = ir_type(&decl.ta);
+      let value = self.expression(decl.children[0].as_ref());
+      let value = self.coerce(value, &ta);
+      let var = self.reg(ta);
+      self.emit(Inst::Copy { dest: var, src: value });
+      self.vars.insert(decl.span.clone(), var);
+      return;
+    }
+    if let Some(block) = node.downcast_ref::<BlockNode>() {
+      return self.statements(&block.children);
+    }
+    let statement = match node.downcast_ref::<StatementNode>() {
+      Some(statement) => statement,
+      None => {
+        self.expression(node);
+        return;
+      }
+    };
+
+    match statement.ntype.as_str() {
+      "IfStatement" => {
+        let cond = self.expression(statement.children[0].as_ref());
+        let (then, merge) = (self.block(), self.block());
+        let otherwise = if statement.children.len() > 2 { self.block() } else { merge };
+        self.emit(Inst::Branch { cond, then, otherwise });
+
+        self.switch(then);
+        self.statement(statement.children[1].as_ref());
+        self.emit(Inst::Jump { target: merge });
+        if let Some(branch) = statement.children.get(2) {
+          self.switch(otherwise);
+          self.statement(branch.as_ref());
+          self.emit(Inst::Jump { target: merge });
+        }
+        self.switch(merge);
+      },
+      "WhileStatement" => {
+        let (header, body, exit) = (self.block(), self.block(), self.block());
+        self.emit(Inst::Jump { target: header });
+
+        self.switch(header);
+        let cond = self.expression(statement.children[0].as_ref());
+        self.emit(Inst::Branch { cond, then: body, otherwise: exit });
+
+        self.switch(body);
+        self.loops.push((header, exit));
+        self.statement(statement.children[1].as_ref());
+        self.loops.pop();
+        self.emit(Inst::Jump { target: header });
+        self.switch(exit);
+      },
+      "BreakStatement" | "ContinueStatement" => {
+        let (header, exit) = *self.loops.last().expect("jumps are checked to be inside loops");
+        let target = if statement.ntype == "BreakStatement" { exit } else { header };
+        self.emit(Inst::Jump { target });
+      },
+      "ReturnStatement" => {
+        let value = statement.children.first().map(|value| {
+          let ret = self.func.ret.clone();
+          let value = self.expression(value.as_ref());
+          self.coerce(value, &ret)
+        });
+        self.emit(Inst::Return { value });
+      },
+      "AssignmentStatement" => self.assignment(statement.children[0].as_ref(), statement.children[1].as_ref()),
+      _ => for child in &statement.children {
+        self.expression(child.as_ref());
+      }
+    }
+  }
+
+  /// `target = value;`
+  ///
+  /// Storing into an element or field of a variable loads every aggregate
+  /// on the way there, updates the innermost one and writes them all back.
+  fn assignment(&mut self, target: &(dyn ASTNode + 'static), value: &(dyn ASTNode + 'static)) {
+    let value = self.expression(value);
+
+    let mut path = vec![];
+    let mut root = target;
+    loop {
+      if let Some(access) = root.downcast_ref::<FieldAccessNode>() {
+        path.push((root, access.children[0].as_ref()));
+        root = access.children[0].as_ref();
+      } else if let Some(index) = root.downcast_ref::<IndexNode>() {
+        path.push((root, index.children[0].as_ref()));
+        root = index.children[0].as_ref();
+      } else {
+        break;
+      }
+    }
+    path.reverse();
+
+    let var = match root.downcast_ref::<NameNode>().map(|name| &name.binding) {
+      Some(Binding::Local(declaration)) => self.vars[declaration],
+      _ => unreachable!("assignment targets are checked to be places")
+    };
+
+    // The aggregates along the path, starting with the variable
+    let mut containers = vec![var];
+    let mut steps = vec![];
+    for (i, (node, _)) in path.iter().enumerate() {
+      let container = containers[i];
+      let ta = self.func.reg_type(container).clone();
+      let (step, element_ta) = match node.downcast_ref::<IndexNode>() {
+        Some(index) => {
+          let index = self.expression(index.children[1].as_ref());
+          let element = match &ta {
+            NaskoType::Array(element, _) => (**element).clone(),
+            _ => unreachable!("only arrays are indexed")
+          };
+          (Step::Index(index), element)
+        },
+        None => {
+          let access = node.downcast_ref::<FieldAccessNode>().unwrap();
+          let (field, field_ta) = self.field(&ta, &access.field);
+          (Step::Field(field), field_ta)
+        }
+      };
+
+      if i + 1 < path.len() {
+        let dest = self.reg(element_ta);
+        self.emit(match step {
+          Step::Index(index) => Inst::Index { dest, array: container, index },
+          Step::Field(field) => Inst::Field { dest, object: container, field }
+        });
+        containers.push(dest);
+      } else {
+        let value = self.coerce(value, &element_ta);
+        containers.push(value);
+      }
+      steps.push(step);
+    }
+
+    if steps.is_empty() {
+      let ta = self.func.reg_type(var).clone();
+      let value = self.coerce(value, &ta);
+      self.emit(Inst::Copy { dest: var, src: value });
+      return;
+    }
+
+    for (i, step) in steps.iter().enumerate().rev() {
+      let (container, value) = (containers[i], containers[i + 1]);
+      self.emit(match step {
+        Step::Index(index) => Inst::SetIndex { array: container, index: *index, value },
+        Step::Field(field) => Inst::SetField { object: container, field: *field, value }
+      });
+    }
+  }
+
+  fn expression(&mut self, node: &(dyn ASTNode + 'static)) -> Reg {
+    if let Some(name) = node.downcast_ref::<NameNode>() {
+      return match &name.binding {
+        Binding::Local(declaration) => self.vars[declaration],
+        _ => unreachable!("only locals are checked to be values")
+      };
+    }
+
+    let ta = ir_type(node.ta());
+    if let Some(value) = node.downcast_ref::<ValueNode>() {
+      let value = match &value.value {
+        ExtraNodeData::Integer(n) => Const::Int(*n),
+        ExtraNodeData::Float(n) => Const::Float(*n),
+        ExtraNodeData::Boolean(b) => Const::Boolean(*b),
+        ExtraNodeData::String(s) => Const::String(s.clone()),
+        _ => unreachable!("constants are checked")
+      };
+      let dest = self.reg(ta);
+      self.emit(Inst::Const { dest, value });
+      dest
+    } else if let Some(binary) = node.downcast_ref::<BinaryExpression>() {
+      let (lhs, rhs) = match (&binary.lhs, &binary.rhs) {
+        (Some(lhs), Some(rhs)) => (lhs.as_ref(), rhs.as_ref()),
+        _ => unreachable!("binary expressions have two operands")
+      };
+      if binary.expression.is_logical() {
+        return self.logical(&binary.expression, lhs, rhs);
+      }
+
+      let (lhs, rhs) = (self.expression(lhs), self.expression(rhs));
+      let dest = self.reg(ta);
+      self.emit(Inst::Binary { dest, op: binary.expression.clone(), lhs, rhs });
+      dest
+    } else if let Some(unary) = node.downcast_ref::<UnaryExpression>() {
+      // `-128` is a valid `i8`, even though `128` isn't
+      if let Some(literal) = unary.children[0].downcast_ref::<ValueNode>() {
+        if let (NaskoUnary::Negate, ExtraNodeData::Integer(n)) = (&unary.expression, &literal.value) {
+          let dest = self.reg(ta);
+          self.emit(Inst::Const { dest, value: Const::Int(-n) });
+          return dest;
+        }
+      }
+
+      let src = self.expression(unary.children[0].as_ref());
+      let dest = self.reg(ta);
+      self.emit(Inst::Unary { dest, op: unary.expression.clone(), src });
+      dest
+    } else if let Some(call) = node.downcast_ref::<CallNode>() {
+      let (params, ret) = self.signatures[&call.name].clone();
+      let mut args = vec![];
+      for (arg, param) in call.children.iter().zip(params.iter()) {
+        let arg = self.expression(arg.as_ref());
+        args.push(self.coerce(arg, param));
+      }
+
+      let dest = if ret == NaskoType::Void { None } else { Some(self.reg(ret)) };
+      self.emit(Inst::Call { dest, func: call.name.clone(), args });
+      // A `void` result is never used as a value
+      dest.unwrap_or_else(|| self.reg(NaskoType::Void))
+    } else if let Some(cast) = node.downcast_ref::<CastNode>() {
+      let src = self.expression(cast.children[0].as_ref());
+      self.coerce(src, &ta)
+    } else if let Some(literal) = node.downcast_ref::<ArrayLiteralNode>() {
+      let element_ta = match &ta {
+        NaskoType::Array(element, _) => (**element).clone(),
+        _ => unreachable!("array literals are arrays")
+      };
+      let mut elements = vec![];
+      for element in &literal.children {
+        let element = self.expression(element.as_ref());
+        elements.push(self.coerce(element, &element_ta));
+      }
+      let dest = self.reg(ta);
+      self.emit(Inst::Array { dest, elements });
+      dest
+    } else if let Some(index) = node.downcast_ref::<IndexNode>() {
+      let array = self.expression(index.children[0].as_ref());
+      let index = self.expression(index.children[1].as_ref());
+      let dest = self.reg(ta);
+      self.emit(Inst::Index { dest, array, index });
+      dest
+    } else if let Some(literal) = node.downcast_ref::<StructLiteralNode>() {
+      // Initializers run in source order, but are stored in declaration order
+      let def = self.struct_def(&ta);
+      let mut fields = vec![None; def.fields.len()];
+      for init in &literal.children {
+        let init = init.downcast_ref::<ValueNode>().expect("struct literals hold field initializers");
+        let name = match &init.value {
+          ExtraNodeData::String(name) => name,
+          _ => unreachable!("field initializers are named")
+        };
+        let (field, field_ta) = self.field(&ta, name);
+        let value = self.expression(init.children[0].as_ref());
+        fields[field] = Some(self.coerce(value, &field_ta));
+      }
+
+      let dest = self.reg(ta);
+      let fields = fields.into_iter().map(|field| field.expect("struct literals are checked to be complete")).collect();
+      self.emit(Inst::Struct { dest, fields });
+      dest
+    } else if let Some(access) = node.downcast_ref::<FieldAccessNode>() {
+      let object = self.expression(access.children[0].as_ref());
+      let object_ta = self.func.reg_type(object).clone();
+      let (field, _) = self.field(&object_ta, &access.field);
+      let dest = self.reg(ta);
+      self.emit(Inst::Field { dest, object, field });
+      dest
+    } else {
+      unreachable!("every expression is lowered")
+    }
+  }
+
+  /// `&&` and `||`, which only evaluate the right side if they need it
+  fn logical(&mut self, op: &NaskoArithmetic, lhs: &(dyn ASTNode + 'static), rhs: &(dyn ASTNode + 'static)) -> Reg {
+    let result = self.reg(NaskoType::Boolean);
+    let lhs = self.expression(lhs);
+    self.emit(Inst::Copy { dest: result, src: lhs });
+
+    let (right, merge) = (self.block(), self.block());
+    self.emit(match op {
+      NaskoArithmetic::And => Inst::Branch { cond: lhs, then: right, otherwise: merge },
+      _ => Inst::Branch { cond: lhs, then: merge, otherwise: right }
+    });
+
+    self.switch(right);
+    let rhs = self.expression(rhs);
+    self.emit(Inst::Copy { dest: result, src: rhs });
+    self.emit(Inst::Jump { target: merge });
+    self.switch(merge);
+
+    result
+  }
+}
@@ -0,0 +1,246 @@
+//! Typed intermediate representation between the checked tree and the
+//! backends
+//!
+//! Functions are made of basic blocks of three-address instructions over
+//! virtual registers, each block ending in exactly one terminator. Registers
+//! are typed and may be assigned more than once, so `let` bindings map onto
+//! registers directly and control flow needs no phi nodes.
+//!
+//! Types are `NaskoType`s with enums replaced by `i64`. Arrays and structs
+//! are values, `set_index` and `set_field` update a register in place.
+
+mod lower;
+mod verify;
+
+pub use self::lower::lower;
+pub use self::verify::verify;
+
+use std::fmt;
+use crate::semantics::*;
+
+/// A virtual register, printed as `%n`
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct Reg(pub usize);
+
+/// A basic block of a function, printed as `bbn`
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct BlockId(pub usize);
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Const {
+  Int(i128),
+  Float(f64),
+  Boolean(bool),
+  String(String)
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Inst {
+  Const { dest: Reg, value: Const },
+  Copy { dest: Reg, src: Reg },
+  /// Never `&&` or `||`, those are lowered to branches. Integer division
+  /// by zero traps.
+  Binary { dest: Reg, op: NaskoArithmetic, lhs: Reg, rhs: Reg },
+  Unary { dest: Reg, op: NaskoUnary, src: Reg },
+  /// An `as` conversion to the type of `dest`, also used to forget the
+  /// length of an array
+  Cast { dest: Reg, src: Reg },
+  /// `dest` is `None` for functions returning `void`
+  Call { dest: Option<Reg>, func: String, args: Vec<Reg> },
+  Array { dest: Reg, elements: Vec<Reg> },
+  /// Traps if `index` is out of bounds
+  Index { dest: Reg, array: Reg, index: Reg },
+  SetIndex { array: Reg, index: Reg, value: Reg },
+  /// Fields in declaration order
+  Struct { dest: Reg, fields: Vec<Reg> },
+  Field { dest: Reg, object: Reg, field: usize },
+  SetField { object: Reg, field: usize, value: Reg },
+  Jump { target: BlockId },
+  Branch { cond: Reg, then: BlockId, otherwise: BlockId },
+  Return { value: Option<Reg> },
+  /// Ends blocks control never reaches
+  Unreachable
+}
+
+impl Inst {
+  /// Whether the instruction ends a block
+  pub fn is_terminator(&self) -> bool {
+    matches!(self, Inst::Jump { .. } | Inst::Branch { .. } | Inst::Return { .. } | Inst::Unreachable)
+  }
+
+  /// The register the instruction assigns, if any
+  pub fn dest(&self) -> Option<Reg> {
+    match self {
+      Inst::Const { dest, .. } | Inst::Copy { dest, .. } | Inst::Binary { dest, .. } |
+      Inst::Unary { dest, .. } | Inst::Cast { dest, .. } | Inst::Array { dest, .. } |
+      Inst::Index { dest, .. } | Inst::Struct { dest, .. } | Inst::Field { dest, .. } => Some(*dest),
+      Inst::Call { dest, .. } => *dest,
+      _ => None
+    }
+  }
+
+  /// The registers the instruction reads
+  ///
+  /// `set_index` and `set_field` also read the aggregate they update.
+  pub fn uses(&self) -> Vec<Reg> {
+    match self {
+      Inst::Const { .. } | Inst::Jump { .. } | Inst::Unreachable => vec![],
+      Inst::Copy { src, .. } | Inst::Unary { src, .. } | Inst::Cast { src, .. } => vec![*src],
+      Inst::Binary { lhs, rhs, .. } => vec![*lhs, *rhs],
+      Inst::Call { args, .. } => args.clone(),
+      Inst::Array { elements, .. } => elements.clone(),
+      Inst::Index { array, index, .. } => vec![*array, *index],
+      Inst::SetIndex { array, index, value } => vec![*array, *index, *value],
+      Inst::Struct { fields, .. } => fields.clone(),
+      Inst::Field { object, .. } => vec![*object],
+      Inst::SetField { object, value, .. } => vec![*object, *value],
+      Inst::Branch { cond, .. } => vec![*cond],
+      Inst::Return { value } => value.iter().copied().collect()
+    }
+  }
+
+  /// The blocks a terminator may continue in
+  pub fn successors(&self) -> Vec<BlockId> {
+    match self {
+      Inst::Jump { target } => vec![*target],
+      Inst::Branch { then, otherwise, .. } => vec![*then, *otherwise],
+      _ => vec![]
+    }
+  }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Block {
+  pub insts: Vec<Inst>
+}
+
+#[derive(Debug, Clone)]
+pub struct Function {
+  pub name: String,
+  /// The registers holding the arguments on entry
+  pub params: Vec<Reg>,
+  pub ret: NaskoType,
+  /// Type of every register, by register number
+  pub regs: Vec<NaskoType>,
+  /// The first block is the entry
+  pub blocks: Vec<Block>
+}
+
+impl Function {
+  pub fn reg_type(&self, reg: Reg) -> &NaskoType {
+    &self.regs[reg.0]
+  }
+
+  pub fn block(&self, id: BlockId) -> &Block {
+    &self.blocks[id.0]
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct StructDef {
+  pub name: String,
+  pub fields: Vec<(String, NaskoType)>
+}
+
+/// A whole program
+#[derive(Debug, Default, Clone)]
+pub struct Module {
+  pub structs: Vec<StructDef>,
+  pub functions: Vec<Function>
+}
+
+impl Module {
+  pub fn function(&self, name: &str) -> Option<&Function> {
+    self.functions.iter().find(|func| func.name == name)
+  }
+
+  pub fn struct_def(&self, name: &str) -> Option<&StructDef> {
+    self.structs.iter().find(|def| def.name == name)
+  }
+}
+
+impl fmt::Display for Reg {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "%{}", self.0)
+  }
+}
+
+impl fmt::Display for BlockId {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "bb{}", self.0)
+  }
+}
+
+impl fmt::Display for Const {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Const::Int(n) => write!(f, "{}", n),
+      Const::Float(n) => write!(f, "{:?}", n),
+      Const::Boolean(b) => write!(f, "{}", b),
+      Const::String(s) => write!(f, "{:?}", s)
+    }
+  }
+}
+
+/// Registers separated by commas
+fn list(regs: &[Reg]) -> String {
+  regs.iter().map(|reg| reg.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+impl fmt::Display for Inst {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Inst::Const { value, .. } => write!(f, "const {}", value),
+      Inst::Copy { src, .. } => write!(f, "copy {}", src),
+      Inst::Binary { op, lhs, rhs, .. } => write!(f, "{} {} {}", lhs, op, rhs),
+      Inst::Unary { op, src, .. } => write!(f, "{}{}", op, src),
+      Inst::Cast { src, .. } => write!(f, "cast {}", src),
+      Inst::Call { func, args, .. } => write!(f, "call {}({})", func, list(args)),
+      Inst::Array { elements, .. } => write!(f, "array [{}]", list(elements)),
+      Inst::Index { array, index, .. } => write!(f, "index {}[{}]", array, index),
+      Inst::SetIndex { array, index, value } => write!(f, "set_index {}[{}], {}", array, index, value),
+      Inst::Struct { fields, .. } => write!(f, "struct {{ {} }}", list(fields)),
+      Inst::Field { object, field, .. } => write!(f, "field {}.{}", object, field),
+      Inst::SetField { object, field, value } => write!(f, "set_field {}.{}, {}", object, field, value),
+      Inst::Jump { target } => write!(f, "jump {}", target),
+      Inst::Branch { cond, then, otherwise } => write!(f, "branch {}, {}, {}", cond, then, otherwise),
+      Inst::Return { value: Some(value) } => write!(f, "return {}", value),
+      Inst::Return { value: None } => write!(f, "return"),
+      Inst::Unreachable => write!(f, "unreachable")
+    }
+  }
+}
+
+impl fmt::Display for Function {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let params: Vec<String> = self.params.iter()
+      .map(|param| format!("{}: {}", param, self.reg_type(*param)))
+      .collect();
+    writeln!(f, "func {}({}): {} {{", self.name, params.join(", "), self.ret)?;
+
+    for (i, block) in self.blocks.iter().enumerate() {
+      writeln!(f, "{}:", BlockId(i))?;
+      for inst in &block.insts {
+        match inst.dest() {
+          Some(dest) => writeln!(f, "  {}: {} = {}", dest, self.reg_type(dest), inst)?,
+          None => writeln!(f, "  {}", inst)?
+        }
+      }
+    }
+
+    write!(f, "}}")
+  }
+}
+
+impl fmt::Display for Module {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    for def in &self.structs {
+      let fields: Vec<String> = def.fields.iter().map(|(name, ta)| format!("{}: {}", name, ta)).collect();
+      writeln!(f, "struct {} {{ {} }}\n", def.name, fields.join(", "))?;
+    }
+    for func in &self.functions {
+      writeln!(f, "{}\n", func)?;
+    }
+    Ok(())
+  }
+}
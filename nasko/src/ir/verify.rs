@@ -0,0 +1,327 @@
+//! Well-formedness checks of the IR
+//!
+//! Lowering should only ever produce valid IR, so a failure here is a bug
+//! in the compiler rather than in the program.
+
+use std::collections::HashSet;
+use crate::semantics::*;
+use super::*;
+
+/// Checks that every block is terminated, that jumps, calls and registers
+/// refer to things that exist, that instructions are applied to operands of
+/// the right types and that every register is assigned before it is read
+pub fn verify(module: &Module) -> Result<(), Vec<String>> {
+  let mut errors = vec![];
+
+  let mut names = HashSet::new();
+  for func in &module.functions {
+    if !names.insert(func.name.as_str()) {
+      errors.push(format!("function `{}` is defined more than once", func.name));
+    }
+  }
+  for def in &module.structs {
+    for (field, ta) in &def.fields {
+      if !is_concrete(ta) || *ta == NaskoType::Void {
+        errors.push(format!("field `{}.{}` has type `{}`", def.name, field, ta));
+      }
+    }
+  }
+
+  for func in &module.functions {
+    let mut verifier = Verifier { module, func, block: BlockId(0), errors: &mut errors };
+    verifier.function();
+  }
+
+  if errors.is_empty() { Ok(()) } else { Err(errors) }
+}
+
+/// Whether a type is fully known, so a backend can lay it out
+fn is_concrete(ta: &NaskoType) -> bool {
+  match ta {
+    NaskoType::Array(element, _) => is_concrete(element),
+    NaskoType::Struct(_) | NaskoType::Int(_) | NaskoType::Float(_) |
+    NaskoType::Boolean | NaskoType::String | NaskoType::Void => true,
+    _ => false
+  }
+}
+
+/// The conversions `cast` can do
+fn is_castable(from: &NaskoType, to: &NaskoType) -> bool {
+  match (from, to) {
+    (NaskoType::Array(from, Some(_)), NaskoType::Array(to, None)) => from == to,
+    (NaskoType::Int(_) | NaskoType::Float(_) | NaskoType::Boolean, NaskoType::String) => true,
+    (NaskoType::Int(_) | NaskoType::Float(_), NaskoType::Int(_) | NaskoType::Float(_)) => true,
+    (NaskoType::Int(_), NaskoType::Boolean) | (NaskoType::Boolean, NaskoType::Int(_)) => true,
+    _ => from == to
+  }
+}
+
+struct Verifier<'a> {
+  module: &'a Module,
+  func: &'a Function,
+  /// The block being checked
+  block: BlockId,
+  errors: &'a mut Vec<String>
+}
+
+impl<'a> Verifier<'a> {
+  fn error(&mut self, message: String) {
+    self.errors.push(format!("in `{}`, {}: {}", self.func.name, self.block, message));
+  }
+
+  fn check(&mut self, holds: bool, message: String) {
+    if !holds {
+      self.error(message);
+    }
+  }
+
+  fn ta(&self, reg: Reg) -> &'a NaskoType {
+    &self.func.regs[reg.0]
+  }
+
+  fn function(&mut self) {
+    let func = self.func;
+    if func.blocks.is_empty() {
+      self.errors.push(format!("in `{}`: the function has no blocks", func.name));
+      return;
+    }
+    for (reg, ta) in func.regs.iter().enumerate() {
+      self.check(is_concrete(ta), format!("{} has type `{}`", Reg(reg), ta));
+    }
+    for param in &func.params {
+      self.check(param.0 < func.regs.len(), format!("parameter {} does not exist", param));
+    }
+
+    // Types are only checked once every register exists
+    let mut well_formed = true;
+    for (i, block) in func.blocks.iter().enumerate() {
+      self.block = BlockId(i);
+      well_formed &= self.structure(block);
+    }
+    if !well_formed {
+      return;
+    }
+
+    for (i, block) in func.blocks.iter().enumerate() {
+      self.block = BlockId(i);
+      for inst in &block.insts {
+        self.inst(inst);
+      }
+    }
+    self.assignments();
+  }
+
+  /// Checks terminators and that every register and block exists
+  fn structure(&mut self, block: &Block) -> bool {
+    let errors = self.errors.len();
+
+    match block.insts.last() {
+      Some(last) => self.check(last.is_terminator(), format!("the block ends with `{}` instead of a terminator", last)),
+      None => self.error("the block is empty".to_string())
+    }
+    for inst in block.insts.iter().rev().skip(1) {
+      self.check(!inst.is_terminator(), format!("terminator `{}` in the middle of the block", inst));
+    }
+
+    for inst in &block.insts {
+      for reg in inst.uses().into_iter().chain(inst.dest()) {
+        self.check(reg.0 < self.func.regs.len(), format!("`{}` uses {}, which does not exist", inst, reg));
+      }
+      for target in inst.successors() {
+        self.check(target.0 < self.func.blocks.len(), format!("`{}` jumps to {}, which does not exist", inst, target));
+      }
+    }
+
+    self.errors.len() == errors
+  }
+
+  fn inst(&mut self, inst: &Inst) {
+    for reg in inst.uses() {
+      self.check(*self.ta(reg) != NaskoType::Void, format!("`{}` reads {}, which is `void`", inst, reg));
+    }
+    let mismatch = |what: &str, expected: &NaskoType, found: &NaskoType| {
+      format!("`{}` expects {} of type `{}`, found `{}`", inst, what, expected, found)
+    };
+
+    match inst {
+      Inst::Const { dest, value } => {
+        let fits = match (value, self.ta(*dest)) {
+          (Const::Int(n), NaskoType::Int(kind)) => kind.contains(*n),
+          (Const::Float(_), NaskoType::Float(_)) => true,
+          (Const::Boolean(_), NaskoType::Boolean) => true,
+          (Const::String(_), NaskoType::String) => true,
+          _ => false
+        };
+        self.check(fits, format!("`{}` does not fit into `{}`", inst, self.ta(*dest)));
+      },
+      Inst::Copy { dest, src } => {
+        self.check(self.ta(*dest) == self.ta(*src), mismatch("a source", self.ta(*dest), self.ta(*src)));
+      },
+      Inst::Binary { dest, op, lhs, rhs } => {
+        let (ta, dest) = (self.ta(*lhs), self.ta(*dest));
+        self.check(ta == self.ta(*rhs), mismatch("a right operand", ta, self.ta(*rhs)));
+
+        let (applies, result) = match op {
+          NaskoArithmetic::Equal | NaskoArithmetic::NotEqual => (ta.is_comparable(), &NaskoType::Boolean),
+          _ if op.is_comparison() => (ta.is_numeric(), &NaskoType::Boolean),
+          _ if op.is_bitwise() => (matches!(ta, NaskoType::Int(_)), ta),
+          _ if op.is_logical() || *op == NaskoArithmetic::None => (false, ta),
+          _ => (ta.is_numeric(), ta)
+        };
+        self.check(applies, format!("`{}` can't be applied to `{}`", op, ta));
+        self.check(dest == result, mismatch("a result", result, dest));
+      },
+      Inst::Unary { dest, op, src } => {
+        let (ta, dest) = (self.ta(*src), self.ta(*dest));
+        let applies = match op {
+          NaskoUnary::Negate => ta.is_numeric(),
+          NaskoUnary::Not => *ta == NaskoType::Boolean,
+          NaskoUnary::BitNot => matches!(ta, NaskoType::Int(_))
+        };
+        self.check(applies, format!("`{}` can't be applied to `{}`", op, ta));
+        self.check(dest == ta, mismatch("a result", ta, dest));
+      },
+      Inst::Cast { dest, src } => {
+        let (from, to) = (self.ta(*src), self.ta(*dest));
+        self.check(is_castable(from, to), format!("`{}` can't convert `{}` to `{}`", inst, from, to));
+      },
+      Inst::Call { dest, func, args } => {
+        let callee = match self.module.function(func) {
+          Some(callee) => callee,
+          None => return self.error(format!("`{}` calls a function that does not exist", inst))
+        };
+        self.check(args.len() == callee.params.len(), format!("`{}` passes {} arguments to a function taking {}", inst, args.len(), callee.params.len()));
+        for (arg, param) in args.iter().zip(callee.params.iter()) {
+          let expected = callee.reg_type(*param);
+          self.check(self.ta(*arg) == expected, mismatch("an argument", expected, self.ta(*arg)));
+        }
+        match dest {
+          Some(dest) => self.check(*self.ta(*dest) == callee.ret, mismatch("a result", &callee.ret, self.ta(*dest))),
+          None => self.check(callee.ret == NaskoType::Void, format!("`{}` drops a result of type `{}`", inst, callee.ret))
+        }
+      },
+      Inst::Array { dest, elements } => match self.ta(*dest) {
+        NaskoType::Array(element, len) => {
+          self.check(*len == Some(elements.len()), format!("`{}` has {} elements, its type `{}`", inst, elements.len(), self.ta(*dest)));
+          for reg in elements {
+            self.check(self.ta(*reg) == &**element, mismatch("an element", element, self.ta(*reg)));
+          }
+        },
+        ta => self.error(format!("`{}` creates an array of type `{}`", inst, ta))
+      },
+      Inst::Index { dest, array, index } => self.element(inst, *array, *index, *dest),
+      Inst::SetIndex { array, index, value } => self.element(inst, *array, *index, *value),
+      Inst::Struct { dest, fields } => {
+        let def = match self.struct_def(inst, *dest) {
+          Some(def) => def,
+          None => return
+        };
+        self.check(fields.len() == def.fields.len(), format!("`{}` has {} fields, `{}` has {}", inst, fields.len(), def.name, def.fields.len()));
+        for (reg, (_, ta)) in fields.iter().zip(def.fields.iter()) {
+          self.check(self.ta(*reg) == ta, mismatch("a field", ta, self.ta(*reg)));
+        }
+      },
+      Inst::Field { dest, object, field } => self.field(inst, *object, *field, *dest),
+      Inst::SetField { object, field, value } => self.field(inst, *object, *field, *value),
+      Inst::Branch { cond, .. } => {
+        self.check(*self.ta(*cond) == NaskoType::Boolean, mismatch("a condition", &NaskoType::Boolean, self.ta(*cond)));
+      },
+      Inst::Return { value } => {
+        let ret = &self.func.ret;
+        match value {
+          Some(value) => self.check(self.ta(*value) == ret, mismatch("a value", ret, self.ta(*value))),
+          None => self.check(*ret == NaskoType::Void, format!("`{}` without a value of type `{}`", inst, ret))
+        }
+      },
+      Inst::Jump { .. } | Inst::Unreachable => {}
+    }
+  }
+
+  /// `index` and `set_index`, `value` is the element read or written
+  fn element(&mut self, inst: &Inst, array: Reg, index: Reg, value: Reg) {
+    let element = match self.ta(array) {
+      NaskoType::Array(element, _) => element,
+      ta => return self.error(format!("`{}` indexes into a value of type `{}`", inst, ta))
+    };
+    self.check(matches!(self.ta(index), NaskoType::Int(_)), format!("`{}` has an index of type `{}`", inst, self.ta(index)));
+    self.check(self.ta(value) == &**element, format!("`{}` expects an element of type `{}`, found `{}`", inst, element, self.ta(value)));
+  }
+
+  /// `field` and `set_field`, `value` is the field read or written
+  fn field(&mut self, inst: &Inst, object: Reg, field: usize, value: Reg) {
+    let def = match self.struct_def(inst, object) {
+      Some(def) => def,
+      None => return
+    };
+    match def.fields.get(field) {
+      Some((_, ta)) => self.check(self.ta(value) == ta, format!("`{}` expects a field of type `{}`, found `{}`", inst, ta, self.ta(value))),
+      None => self.error(format!("`{}` has no field {}", def.name, field))
+    }
+  }
+
+  fn struct_def(&mut self, inst: &Inst, reg: Reg) -> Option<&'a StructDef> {
+    let module = self.module;
+    let def = match self.ta(reg) {
+      NaskoType::Struct(name) => module.struct_def(name),
+      _ => None
+    };
+    if def.is_none() {
+      self.error(format!("`{}` expects a struct, found `{}`", inst, self.ta(reg)));
+    }
+    def
+  }
+
+  /// Checks that no register is read before it is assigned on every path
+  /// leading there
+  fn assignments(&mut self) {
+    let func = self.func;
+    let blocks = func.blocks.len();
+
+    // Registers assigned on every path to the start of each block, blocks
+    // not reached yet assume everything is
+    let everything: HashSet<Reg> = (0..func.regs.len()).map(Reg).collect();
+    let mut assigned_in = vec![everything; blocks];
+    assigned_in[0] = func.params.iter().copied().collect();
+
+    let mut predecessors = vec![vec![]; blocks];
+    for (i, block) in func.blocks.iter().enumerate() {
+      for target in block.insts.last().map(|last| last.successors()).unwrap_or_default() {
+        predecessors[target.0].push(i);
+      }
+    }
+
+    let assigned_out = |assigned: &HashSet<Reg>, block: &Block| {
+      let mut assigned = assigned.clone();
+      assigned.extend(block.insts.iter().filter_map(|inst| inst.dest()));
+      assigned
+    };
+
+    let mut changed = true;
+    while changed {
+      changed = false;
+      for i in 1..blocks {
+        let mut incoming = predecessors[i].iter().map(|p| assigned_out(&assigned_in[*p], &func.blocks[*p]));
+        let first = match incoming.next() {
+          Some(first) => first,
+          None => continue
+        };
+        let assigned: HashSet<Reg> = incoming.fold(first, |acc, out| acc.intersection(&out).copied().collect());
+        if assigned != assigned_in[i] {
+          assigned_in[i] = assigned;
+          changed = true;
+        }
+      }
+    }
+
+    for (i, block) in func.blocks.iter().enumerate() {
+      self.block = BlockId(i);
+      let mut assigned = assigned_in[i].clone();
+      for inst in &block.insts {
+        for reg in inst.uses() {
+          self.check(assigned.contains(&reg), format!("`{}` reads {} before it is assigned", inst, reg));
+        }
+        assigned.extend(inst.dest());
+      }
+    }
+  }
+}
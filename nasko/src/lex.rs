@@ -9,26 +9,16 @@ pub struct NaskoExtras {
 }
 
 impl NaskoExtras {
-  #[allow(dead_code)]
+  /// Byte offsets of the first character of every line
   pub fn line_heads(&self) -> Vec<usize> {
     let mut heads: Vec<usize> = vec![0];
-
-    let mut i = 0;
-    for _ in self.line_caps.iter() {
-      if i == 0 {
-        i += 1;
-        continue
-      } else {
-        heads.push(self.line_caps[i] + 1);
-      }
-      i += 1;
-    }
+    heads.extend(self.line_caps.iter().map(|cap| cap + 1));
 
     heads
   }
 }
 
-#[derive(Logos, Debug, PartialEq)]
+#[derive(Logos, Debug, PartialEq, Clone)]
 #[logos(extras = NaskoExtras)]
 pub enum NaskoToken {
   #[token("\n", |lex| {
@@ -49,11 +39,12 @@ pub enum NaskoToken {
   LiteralString(String),
 
   #[regex("0[xX]([0-9a-fA-F])+", 
-    |lex| i64::from_str_radix(&lex.slice()[2..], 16))]
-  LiteralHex(i64),
+    |lex| i128::from_str_radix(&lex.slice()[2..], 16))]
+  #[regex("[0-9]+", |lex| lex.slice().parse())]
+  LiteralInteger(i128),
 
-  #[regex("-?[0-9]+(\\.[0-9]+)?", |lex| lex.slice().parse())]
-  LiteralNumber(f64),
+  #[regex("[0-9]+\\.[0-9]+", |lex| lex.slice().parse())]
+  LiteralFloat(f64),
 
   #[regex("true|false", |lex| lex.slice() == "true")]
   LiteralBoolean(bool),
@@ -61,13 +52,28 @@ pub enum NaskoToken {
   #[regex("//.*", |lex| lex.slice().replace("//", "").trim().to_string())]
   Comment(String),
 
-  #[regex("func|struct|enum|import|if|return|while|let|as", |lex| NaskoKeyword::parse(lex.slice()))]
+  #[regex("func|struct|enum|import|if|else|return|while|break|continue|let|as", |lex| NaskoKeyword::parse(lex.slice()))]
   Keyword(NaskoKeyword),
 
   #[regex("\\+|-|/|\\*|%|\\*\\*", |lex| NaskoArithmetic::parse(lex.slice()))]
   ArithmeticOperator(NaskoArithmetic),
 
-  #[regex("string|int|boolean", |lex| NaskoType::parse(lex.slice()))]
+  #[regex("==|!=|<|<=|>|>=", |lex| NaskoArithmetic::parse(lex.slice()))]
+  ComparisonOperator(NaskoArithmetic),
+
+  #[regex("&&|\\|\\|", |lex| NaskoArithmetic::parse(lex.slice()))]
+  LogicalOperator(NaskoArithmetic),
+
+  #[regex("&|\\||\\^|<<|>>", |lex| NaskoArithmetic::parse(lex.slice()))]
+  BitwiseOperator(NaskoArithmetic),
+
+  #[token("!")]
+  Not,
+
+  #[token("~")]
+  Tilde,
+
+  #[regex("string|int|boolean|i8|i16|i32|i64|u8|u16|u32|u64|float|double", |lex| NaskoType::parse(lex.slice()))]
   TypeAnnotation(NaskoType),
 
   #[regex("[a-zA-Z_]([a-zA-Z0-9_]+)?", |lex| lex.slice().to_string())]
@@ -1,26 +1,266 @@
-#[macro_use]
-mod macros;
+mod cli;
+mod diagnostic;
 mod type_check;
 mod ast;
 mod parser;
 mod semantics;
 mod lex;
+mod interpreter;
+mod bytecode;
+mod ir;
+mod codegen;
+mod module;
+mod resolve;
 
+use crate::cli::{Command, EmitKind, Options, RunOptions};
+use crate::diagnostic::{Diagnostic, SourceMap};
 use crate::lex::NaskoToken;
-use crate::ast::{SourceNode, ASTNode};
-use crate::parser::parse;
+use crate::module::ModuleLoader;
+use crate::ast::SourceNode;
 use crate::type_check::annotate_types;
-use std::fs;
+use std::{env, fs, process, thread};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 use logos::Logos;
 
 
 fn main() {
-    let code = fs::read_to_string("test.nasko")
-        .expect("Failed to read file: test.nasko");
+  let code = match cli::parse_args(env::args().skip(1)) {
+    Ok(Command::Compile(options)) => compile(&options),
+    Ok(Command::Run(options)) => run(options),
+    Ok(Command::Help) => {
+      println!("{}", cli::USAGE);
+      0
+    },
+    Ok(Command::Version) => {
+      println!("naskoc {}", env!("CARGO_PKG_VERSION"));
+      0
+    },
+    Err(err) => {
+      eprintln!("error: {}\n\n{}", err, cli::USAGE);
+      2
+    }
+  };
+
+  process::exit(code);
+}
+
+/// Compiles every input file, returning the process exit code
+fn compile(options: &Options) -> i32 {
+  let mut code = 0;
+
+  for input in &options.inputs {
+    let emitted = match compile_file(input, options) {
+      Ok(emitted) => emitted,
+      Err(err) => {
+        eprintln!("error: {}", err);
+        code = 1;
+        continue;
+      }
+    };
+
+    let written = match (options.emit, &options.output) {
+      (EmitKind::Exe, output) => {
+        let path = output.clone().unwrap_or_else(|| executable_path(input));
+        codegen::link(&emitted, &path)
+      },
+      (EmitKind::Naskoc, output) => {
+        let path = output.clone().unwrap_or_else(|| executable_path(input).with_extension("naskoc"));
+        fs::write(&path, emitted).map_err(|err| format!("Failed to write {}: {}", path.display(), err))
+      },
+      (_, Some(path)) => fs::write(path, emitted)
+        .map_err(|err| format!("Failed to write {}: {}", path.display(), err)),
+      (_, None) => io::stdout().write_all(&emitted)
+        .map_err(|err| format!("Failed to write to stdout: {}", err))
+    };
+    if let Err(err) = written {
+      eprintln!("error: {}", err);
+      code = 1;
+    }
+  }
+
+  code
+}
+
+/// Where the executable compiled from a file goes without `-o`, like rustc
+/// the file name without its extension, in the working directory. Modules
+/// go to the same path with a `.naskoc` extension.
+fn executable_path(input: &Path) -> PathBuf {
+  PathBuf::from(input.file_stem().unwrap_or(input.as_os_str()))
+}
+
+/// Runs the pipeline on a single file up to the requested phase
+///
+/// Diagnostics are printed to stderr as they are produced. Executables are
+/// returned as the object file to link.
+fn compile_file(path: &Path, options: &Options) -> Result<Vec<u8>, String> {
+  let code = fs::read_to_string(path)
+    .map_err(|err| format!("Failed to read file {}: {}", path.display(), err))?;
+
+  if options.emit == EmitKind::Tokens {
     let mut lex = NaskoToken::lexer(&code);
+    let mut out = String::new();
+    while let Some(token) = lex.next() {
+      out.push_str(&format!("{:?} {:?}\n", lex.span(), token));
+    }
+    return Ok(out.into_bytes());
+  }
+
+  let mut modules = ModuleLoader::new(&options.search_paths);
+  let (mut ast, errors) = modules.load(path, code);
+  let sources = &modules.sources;
+
+  if options.emit == EmitKind::Ast {
+    if !errors.is_empty() {
+      return Err(report(sources, path, &errors));
+    }
+    return Ok(format!("{:#?}\n", ast).into_bytes());
+  }
+  check(sources, path, &mut ast, errors)?;
+  if options.emit == EmitKind::TypedAst {
+    return Ok(format!("{:#?}\n", ast).into_bytes());
+  }
+  match options.emit {
+    EmitKind::C => return Ok(codegen::c_source(&ast).into_bytes()),
+    EmitKind::Bytecode => return Ok(compile_bytecode(sources, path, &ast)?.to_string().into_bytes()),
+    EmitKind::Naskoc => {
+      let module = compile_bytecode(sources, path, &ast)?;
+      return bytecode::serialize(&module).map_err(|err| format!("Could not write a module for {}: {}", path.display(), err));
+    },
+    _ => {}
+  }
+
+  let module = ir::lower(&ast);
+  if let Err(errors) = ir::verify(&module) {
+    for error in &errors {
+      eprintln!("error: Invalid IR {}", error);
+    }
+    return Err(format!("Could not compile {}, the generated IR is invalid", path.display()));
+  }
+
+  if options.emit == EmitKind::Ir {
+    return Ok(module.to_string().into_bytes());
+  }
+
+  codegen::object(&module)
+}
+
+/// Compiles a checked program to bytecode, reporting the limits of the
+/// bytecode it goes past
+fn compile_bytecode(sources: &SourceMap, path: &Path, ast: &SourceNode) -> Result<bytecode::Module, String> {
+  bytecode::compile(ast).map_err(|diagnostic| report(sources, path, &[diagnostic]))
+}
+
+/// Type checks a parsed program along with the errors from loading it,
+/// printing any warnings
+fn check(sources: &SourceMap, path: &Path, ast: &mut SourceNode, mut diagnostics: Vec<Diagnostic>) -> Result<(), String> {
+  diagnostics.append(&mut annotate_types(ast));
+  diagnostics.sort_by_key(|d| d.primary.span.start);
+  if diagnostics.iter().any(|d| d.is_error()) {
+    return Err(report(sources, path, &diagnostics));
+  }
+  warn(sources, &diagnostics);
+
+  Ok(())
+}
+
+/// Checks a file and runs it in the VM, or the interpreter, returning the
+/// exit code of the program
+///
+/// Runs on a thread with a stack big enough for deeply recursive programs
+/// in the interpreter.
+fn run(options: RunOptions) -> i32 {
+  thread::Builder::new()
+    .stack_size(interpreter::STACK_SIZE)
+    .spawn(move || run_file(&options))
+    .map_err(|err| eprintln!("error: Failed to start the interpreter: {}", err))
+    .and_then(|handle| handle.join().map_err(|_| ()))
+    .unwrap_or(101)
+}
+
+fn run_file(options: &RunOptions) -> i32 {
+  let path = options.input.as_path();
+  // Like in C, the program itself comes first
+  let mut args = vec![path.display().to_string()];
+  args.extend(options.args.iter().cloned());
+
+  if path.extension().is_some_and(|extension| extension == "naskoc") {
+    let module = fs::read(path)
+      .map_err(|err| format!("Failed to read file {}: {}", path.display(), err))
+      .and_then(|bytes| bytecode::deserialize(&bytes).map_err(|err| format!("Invalid module {}: {}", path.display(), err)));
+    return match module {
+      Ok(module) => run_bytecode(&module, args, &SourceMap::default()),
+      Err(err) => {
+        eprintln!("error: {}", err);
+        1
+      }
+    };
+  }
+
+  let code = match fs::read_to_string(path) {
+    Ok(code) => code,
+    Err(err) => {
+      eprintln!("error: Failed to read file {}: {}", path.display(), err);
+      return 1;
+    }
+  };
+
+  let mut modules = ModuleLoader::new(&options.search_paths);
+  let (mut ast, errors) = modules.load(path, code);
+  let sources = &modules.sources;
+
+  if let Err(err) = check(sources, path, &mut ast, errors) {
+    eprintln!("error: {}", err);
+    return 1;
+  }
+
+  if options.vm {
+    return match compile_bytecode(sources, path, &ast) {
+      Ok(module) => run_bytecode(&module, args, sources),
+      Err(err) => {
+        eprintln!("error: {}", err);
+        1
+      }
+    };
+  }
+  match interpreter::run(&ast, args) {
+    Ok(code) => code,
+    Err(diagnostic) => {
+      // Runtime errors exit like a Rust panic does
+      eprintln!("{}", diagnostic.render(sources));
+      101
+    }
+  }
+}
+
+/// Runs a module in the VM, pointing runtime errors at `sources` when the
+/// module knows where its instructions came from
+fn run_bytecode(module: &bytecode::Module, args: Vec<String>, sources: &SourceMap) -> i32 {
+  match bytecode::run(module, args) {
+    Ok(code) => code,
+    Err(err) => {
+      match err.span {
+        Some(span) => eprintln!("{}", Diagnostic::error(err.message, span).with_label(err.label).render(sources)),
+        None => eprintln!("error: {}", err.message)
+      }
+      101
+    }
+  }
+}
+
+/// Prints the warnings of a file that compiled
+fn warn(sources: &SourceMap, diagnostics: &[Diagnostic]) {
+  for diagnostic in diagnostics {
+    eprintln!("{}", diagnostic.render(sources));
+  }
+}
 
-    let mut ast = *mutate_leaf!(parse(&mut lex), Box<SourceNode>);
-    annotate_types(&mut ast);
+/// Prints diagnostics and returns the summary used as the file's error
+fn report(sources: &SourceMap, path: &Path, diagnostics: &[Diagnostic]) -> String {
+  for diagnostic in diagnostics {
+    eprintln!("{}", diagnostic.render(sources));
+  }
 
-    println!("ast: {:?}", ast);
+  let errors = diagnostics.iter().filter(|d| d.is_error()).count();
+  format!("Could not compile {} due to {} previous error{}", path.display(), errors, if errors == 1 { "" } else { "s" })
 }
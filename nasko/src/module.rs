@@ -0,0 +1,194 @@
+//! Loading of `import`ed files into a single program
+
+use crate::ast::*;
+use crate::diagnostic::{Diagnostic, SourceFile, SourceMap, Span};
+use crate::lex::NaskoToken;
+use crate::parser::parse;
+use crate::semantics::NaskoType;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use logos::Logos;
+
+/// Resolves, parses and merges every module reachable from an entry file
+///
+/// Imports are looked up relative to the importing file first and then in
+/// each search path, in order. Every module is loaded once, however many
+/// times it is imported, and its declarations are merged into the program
+/// in place of the first `import` naming it. Modules share one namespace,
+/// so a function or type declared by two of them is reported with the
+/// paths of both.
+pub struct ModuleLoader {
+  search_paths: Vec<PathBuf>,
+  pub sources: SourceMap,
+  /// Canonical paths of every module loaded so far
+  loaded: Vec<PathBuf>,
+  /// Canonical paths of the modules currently being loaded, innermost last
+  stack: Vec<PathBuf>,
+  declarations: Vec<Box<dyn ASTNode>>,
+  /// The module and span declaring every top level name, by whether it
+  /// names a type and the name
+  declared: HashMap<(bool, String), (PathBuf, Span)>,
+  diagnostics: Vec<Diagnostic>
+}
+
+impl ModuleLoader {
+  pub fn new(search_paths: &[PathBuf]) -> ModuleLoader {
+    ModuleLoader {
+      search_paths: search_paths.to_vec(),
+      sources: SourceMap::default(),
+      loaded: vec![],
+      stack: vec![],
+      declarations: vec![],
+      declared: HashMap::new(),
+      diagnostics: vec![]
+    }
+  }
+
+  /// Loads the entry file and everything it imports into one tree, with
+  /// the errors found along the way
+  ///
+  /// What failed to parse or load is left in the tree as `ErrorNode`s.
+  pub fn load(&mut self, path: &Path, code: String) -> (SourceNode, Vec<Diagnostic>) {
+    let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    let span = self.module(path, canonical, code);
+
+    let tree = SourceNode {
+      ta: NaskoType::Void,
+      span,
+      children: std::mem::take(&mut self.declarations)
+    };
+    (tree, std::mem::take(&mut self.diagnostics))
+  }
+
+  /// Parses a module and loads its imports, returning the module's span
+  fn module(&mut self, path: &Path, canonical: PathBuf, code: String) -> Span {
+    self.loaded.push(canonical.clone());
+
+    let base = self.sources.next_base();
+    let mut lex = NaskoToken::lexer(&code);
+    let (tree, mut diagnostics) = parse(&mut lex, base);
+    let extras = std::mem::take(&mut lex.extras);
+    self.sources.add(SourceFile::new(path.display().to_string(), code.clone(), base, &extras));
+    self.diagnostics.append(&mut diagnostics);
+
+    self.stack.push(canonical);
+    for leaf in tree.children {
+      match leaf.downcast::<ImportNode>() {
+        Ok(import) => self.import(path, &import),
+        Err(leaf) => {
+          self.declare(path, leaf.as_ref());
+          self.declarations.push(leaf);
+        }
+      }
+    }
+    self.stack.pop();
+
+    tree.span
+  }
+
+  /// Reports a declaration whose name another module already declares
+  ///
+  /// Names declared twice in the same module are left to the checker.
+  fn declare(&mut self, path: &Path, leaf: &(dyn ASTNode + 'static)) {
+    let (kind, name, span) = if let Some(func) = leaf.downcast_ref::<FunctionDeclNode>() {
+      match &func.value {
+        ExtraNodeData::String(name) => ("function", name, func.span.clone()),
+        _ => return
+      }
+    } else if let Some(decl) = leaf.downcast_ref::<StructDeclNode>() {
+      ("type", &decl.name, decl.span.clone())
+    } else if let Some(decl) = leaf.downcast_ref::<EnumDeclNode>() {
+      ("type", &decl.name, decl.span.clone())
+    } else {
+      return;
+    };
+
+    let key = (kind == "type", name.clone());
+    match self.declared.get(&key) {
+      Some((previous, previous_span)) if previous != path => self.diagnostics.push(
+        Diagnostic::error(
+          format!("The {} `{}` is defined by both {} and {}", kind, name, previous.display(), path.display()),
+          span
+        ).with_label(format!("`{}` redefined here", name))
+          .with_secondary(previous_span.clone(), format!("previous definition of `{}` in {}", name, previous.display()))
+          .with_note("imported modules share one namespace, so their top level names must be unique".to_string())
+      ),
+      Some(_) => {},
+      None => {
+        self.declared.insert(key, (path.to_path_buf(), span));
+      }
+    }
+  }
+
+  fn import(&mut self, importer: &Path, import: &ImportNode) {
+    let relative = match &import.path {
+      ImportPath::File(path) => PathBuf::from(path),
+      ImportPath::Module(segments) => {
+        let mut path: PathBuf = segments.iter().collect();
+        path.set_extension("nasko");
+        path
+      }
+    };
+
+    let directory = importer.parent().unwrap_or_else(|| Path::new(""));
+    let candidates: Vec<PathBuf> = std::iter::once(directory)
+      .chain(self.search_paths.iter().map(|p| p.as_path()))
+      .map(|dir| dir.join(&relative))
+      .collect();
+
+    let path = match candidates.iter().find(|path| path.is_file()) {
+      Some(path) => path.clone(),
+      None => {
+        let searched: Vec<String> = candidates.iter().map(|p| p.display().to_string()).collect();
+        self.fail(
+          import,
+          Diagnostic::error(format!("Cannot find module `{}`", import.path), import.span.clone())
+            .with_label("module not found".to_string())
+            .with_note(format!("searched for {}", searched.join(", ")))
+        );
+        return;
+      }
+    };
+    let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+
+    if let Some(start) = self.stack.iter().position(|p| *p == canonical) {
+      let cycle: Vec<String> = self.stack[start..].iter()
+        .chain(std::iter::once(&canonical))
+        .map(|p| p.display().to_string())
+        .collect();
+      self.diagnostics.push(
+        Diagnostic::error(format!("Import cycle detected while importing `{}`", import.path), import.span.clone())
+          .with_label("imported here".to_string())
+          .with_note(format!("cycle: {}", cycle.join(" -> ")))
+      );
+      return;
+    }
+    if self.loaded.contains(&canonical) {
+      return;
+    }
+
+    match fs::read_to_string(&path) {
+      Ok(code) => {
+        self.module(&path, canonical, code);
+      },
+      Err(err) => self.fail(
+        import,
+        Diagnostic::error(format!("Failed to read module {}: {}", path.display(), err), import.span.clone())
+          .with_label("imported here".to_string())
+      )
+    }
+  }
+
+  /// Reports an import that couldn't be loaded, leaving an error node for
+  /// the declarations it would have brought in
+  fn fail(&mut self, import: &ImportNode, diagnostic: Diagnostic) {
+    self.declarations.push(Box::new(ErrorNode {
+      ta: NaskoType::Unknown,
+      message: diagnostic.message.clone(),
+      span: import.span.clone(),
+      children: vec![]
+    }));
+    self.diagnostics.push(diagnostic);
+  }
+}
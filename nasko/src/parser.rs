@@ -1,276 +1,887 @@
-#![allow(dead_code)]
-
-#[allow(unused_imports)]
 use crate::{
   ast::*,
-  macros::compile_error,
+  diagnostic::{Diagnostic, Span},
   lex::NaskoToken,
   semantics::{
+    FloatKind,
     NaskoArithmetic,
     NaskoKeyword,
-    NaskoType
+    NaskoType,
+    NaskoUnary
   }
 };
 use logos::Lexer;
+use std::convert::TryFrom;
 
-#[derive(Debug, Default)]
-pub struct ParserState {
-  pub in_function_decl: bool,
-  pub in_struct_decl: bool,
-  
-  pub in_arg: bool,
-  pub in_return: bool,
-  
-  pub in_parens: bool,
-  pub in_colon: bool,
-  pub in_comma: bool,
-  pub in_block: bool,
-  pub in_expression: bool,
-  
-  pub in_subscript: bool,
-  pub subscript_has_input: bool,
-  pub subscript_input: SubscriptInput,
-  
-  pub closed_semi: bool,
-
-  pub has_unknown_ident: bool,
-}
+/// Marker for a syntax error that has already been reported
+struct SyntaxError;
 
-#[derive(Debug)]
-pub enum SubscriptInput {
-  None,
-  Number(f64),
-  String(String),
-}
+type ParseResult<T> = Result<T, SyntaxError>;
 
-impl Default for SubscriptInput {
-  fn default() -> Self {
-    Self::None
-  }
+/// Recursive descent parser over the token stream
+///
+/// Expressions are parsed with precedence climbing (Pratt parsing), see
+/// `infix_binding_power` for the operator table.
+pub struct Parser<'a> {
+  source: &'a str,
+  /// Offset of the source in the source map, added to every span
+  base: usize,
+  tokens: Vec<(NaskoToken, Span)>,
+  pos: usize,
+  /// Whether `Name { ... }` is a struct literal, off in `if`/`while`
+  /// conditions where the brace opens the body instead
+  struct_literals: bool,
+  diagnostics: Vec<Diagnostic>
 }
 
-/// Pushes a new leaf into the vec of a ValueNode
+/// Left and right binding power of an infix operator
 ///
-/// Replaces existing value if it is not vec data
-fn vec_upsert(tree: &mut ValueNode, data: Box<dyn ASTNode>) {
-  match &mut tree.value {
-    ExtraNodeData::Vec(v) => {
-      v.push(ExtraNodeData::BoxedNode(data))
-    },
-    _ => tree.value = ExtraNodeData::Vec(vec![ExtraNodeData::BoxedNode(data)])
+/// A higher left than right power makes the operator right associative
+fn infix_binding_power(op: &NaskoArithmetic) -> Option<(u8, u8)> {
+  match op {
+    NaskoArithmetic::Or => Some((2, 3)),
+    NaskoArithmetic::And => Some((4, 5)),
+    NaskoArithmetic::Equal | NaskoArithmetic::NotEqual |
+    NaskoArithmetic::Less | NaskoArithmetic::LessEqual |
+    NaskoArithmetic::Greater | NaskoArithmetic::GreaterEqual => Some((6, 7)),
+    NaskoArithmetic::BitOr => Some((8, 9)),
+    NaskoArithmetic::BitXor => Some((10, 11)),
+    NaskoArithmetic::BitAnd => Some((12, 13)),
+    NaskoArithmetic::ShiftLeft | NaskoArithmetic::ShiftRight => Some((14, 15)),
+    NaskoArithmetic::Add | NaskoArithmetic::Subtract => Some((20, 21)),
+    NaskoArithmetic::Multiply | NaskoArithmetic::Divide | NaskoArithmetic::Modulo => Some((30, 31)),
+    NaskoArithmetic::Power => Some((61, 60)),
+    NaskoArithmetic::None => None
   }
 }
 
+/// Binding power of `as`, tighter than every infix operator but `**`
+const CAST_BINDING_POWER: u8 = 50;
 
-/// Gets a mutable reference to a vec of a ValueNode
-///
-/// The vec element type is always ExtraNodeData
-///
-/// Returns `None` if the value is not `ExtraNodeData::Vec`
-fn vec_get_mut(tree: &mut ValueNode) -> Option<&mut Vec<ExtraNodeData>> {
-  match &mut tree.value {
-    ExtraNodeData::Vec(v) => Some(v),
-    _ => None
+/// Binding power of the operand of a prefix operator, so `!a as int` is
+/// `(!a) as int` but `-2 ** 2` is `-(2 ** 2)`
+const PREFIX_BINDING_POWER: u8 = 55;
+
+impl<'a> Parser<'a> {
+  /// Drains the lexer, reporting invalid characters along the way
+  pub fn new(lex: &mut Lexer<'a, NaskoToken>, base: usize) -> Parser<'a> {
+    let mut tokens = vec![];
+    let mut diagnostics = vec![];
+
+    while let Some(token) = lex.next() {
+      let span = base + lex.span().start..base + lex.span().end;
+      match token {
+        NaskoToken::Comment(_) => {},
+        NaskoToken::Error => diagnostics.push(Diagnostic::error(
+          format!("Unexpected character `{}`", lex.slice()),
+          span
+        )),
+        token => tokens.push((token, span))
+      }
+    }
+
+    Parser {
+      source: lex.source(),
+      base,
+      tokens,
+      pos: 0,
+      struct_literals: true,
+      diagnostics
+    }
+  }
+
+  fn peek(&self) -> Option<&NaskoToken> {
+    self.tokens.get(self.pos).map(|(t, _)| t)
+  }
+
+  /// Span of the next token, or an empty span at the end of the source
+  fn peek_span(&self) -> Span {
+    match self.tokens.get(self.pos) {
+      Some((_, span)) => span.clone(),
+      None => self.end()..self.end()
+    }
+  }
+
+  /// End of the source
+  fn end(&self) -> usize {
+    self.base + self.source.len()
+  }
+
+  /// Source text of a span
+  fn text(&self, span: Span) -> &str {
+    &self.source[span.start - self.base..span.end - self.base]
+  }
+
+  /// End of the last consumed token
+  fn prev_end(&self) -> usize {
+    match self.pos {
+      0 => self.base,
+      pos => self.tokens[pos - 1].1.end
+    }
+  }
+
+  fn advance(&mut self) -> Option<(NaskoToken, Span)> {
+    let token = self.tokens.get(self.pos).cloned();
+    if token.is_some() {
+      self.pos += 1;
+    }
+    token
+  }
+
+  fn check(&self, token: &NaskoToken) -> bool {
+    self.peek() == Some(token)
+  }
+
+  /// Consumes the next token if it matches
+  fn eat(&mut self, token: &NaskoToken) -> Option<Span> {
+    if self.check(token) {
+      return self.advance().map(|(_, span)| span);
+    }
+    None
+  }
+
+  /// Describes the next token for error messages
+  fn found(&self) -> String {
+    match self.tokens.get(self.pos) {
+      Some((_, span)) => format!("`{}`", self.text(span.clone())),
+      None => "end of file".to_string()
+    }
+  }
+
+  fn error<T>(&mut self, message: String, span: Span) -> ParseResult<T> {
+    self.diagnostics.push(Diagnostic::error(message, span));
+    Err(SyntaxError)
+  }
+
+  /// Reports an error at the next token
+  fn unexpected<T>(&mut self, expected: &str) -> ParseResult<T> {
+    let message = format!("Expected {}, found {}", expected, self.found());
+    let span = self.peek_span();
+    self.error(message, span)
+  }
+
+  fn expect(&mut self, token: &NaskoToken, expected: &str) -> ParseResult<Span> {
+    match self.eat(token) {
+      Some(span) => Ok(span),
+      None => self.unexpected(expected)
+    }
+  }
+
+  fn expect_ident(&mut self, expected: &str) -> ParseResult<(String, Span)> {
+    match self.peek() {
+      Some(NaskoToken::Ident(_)) => match self.advance() {
+        Some((NaskoToken::Ident(name), span)) => Ok((name, span)),
+        _ => unreachable!()
+      },
+      _ => self.unexpected(expected)
+    }
+  }
+
+  fn is_synchronizing(&self) -> bool {
+    matches!(
+      self.peek(),
+      Some(NaskoToken::BlockClose)
+        | Some(NaskoToken::Keyword(NaskoKeyword::Func))
+        | Some(NaskoToken::Keyword(NaskoKeyword::Struct))
+        | Some(NaskoToken::Keyword(NaskoKeyword::Enum))
+        | Some(NaskoToken::Keyword(NaskoKeyword::Import))
+    )
   }
-}
 
+  /// Panic-mode recovery: skips tokens until the end of the statement
+  ///
+  /// A `;` is consumed, while `}` and top level keywords are left for the
+  /// enclosing construct
+  fn synchronize(&mut self) {
+    while self.peek().is_some() && !self.is_synchronizing() {
+      if let Some((NaskoToken::Semicolon, _)) = self.advance() {
+        return;
+      }
+    }
+  }
+
+  /// Builds the node standing in for a construct that failed to parse
+  fn error_node(&self, start: usize) -> Box<dyn ASTNode> {
+    let message = self.diagnostics.last()
+      .map(|d| d.message.clone())
+      .unwrap_or_default();
 
-pub fn parse(lex: &mut Lexer<NaskoToken>) -> Box<dyn ASTNode> {
-  let mut tree = SourceNode::default();
-  let mut state = ParserState::default();
+    Box::new(ErrorNode {
+      ta: NaskoType::Unknown,
+      message,
+      span: start..self.prev_end().max(start),
+      children: vec![]
+    })
+  }
+
+  /// Runs `parse` with struct literals allowed or not
+  fn with_struct_literals<T>(&mut self, allowed: bool, parse: impl FnOnce(&mut Self) -> T) -> T {
+    let previous = std::mem::replace(&mut self.struct_literals, allowed);
+    let result = parse(self);
+    self.struct_literals = previous;
+    result
+  }
 
-  loop {
-    // println!("{:#?}", tree);
+  /// Parses every item, returning the tree with the syntax errors found
+  pub fn parse_source(mut self) -> (SourceNode, Vec<Diagnostic>) {
+    let mut tree = SourceNode {
+      span: self.base..self.end(),
+      ..SourceNode::default()
+    };
 
-    match lex.next() {
-      None => break,
-      Some(t) => {
-        if t != NaskoToken::Semicolon {
-          state.closed_semi = false;
+    while self.peek().is_some() {
+      let start = self.peek_span().start;
+      match self.item() {
+        Ok(item) => tree.push_leaf(item),
+        Err(SyntaxError) => {
+          self.synchronize();
+          // Stray closing braces can't end anything at the top level
+          self.eat(&NaskoToken::BlockClose);
+          tree.push_leaf(self.error_node(start));
         }
-        match t {
-          NaskoToken::ParenOpen => state.in_parens = true,
-          NaskoToken::ParenClose => {
-            state.in_arg = false;
-            state.in_parens = false;
-            state.has_unknown_ident = false;
-          },
-          NaskoToken::BlockOpen => state.in_block = true,
-          NaskoToken::BlockClose => {
-            state.in_struct_decl = false;
-            state.in_function_decl = false;
-            state.in_block = false
-          },
-          NaskoToken::Colon => state.in_colon = true,
-          NaskoToken::Comma => {
-            if state.in_arg {
-              state.in_arg = false;
-            }
-            state.in_comma = true
-          },
-          NaskoToken::SubscriptOpen => state.in_subscript = true,
-          NaskoToken::SubscriptClose => {
-            state.in_subscript = false;
-          },
-          NaskoToken::Semicolon => {
-            state.closed_semi = true;
-          },
+      }
+    }
 
-          NaskoToken::Ident(ident) => {
-            if state.in_function_decl {
-              match state.in_parens {
-                true => {
-                  let mut fd = pop_leaf!(tree.children, Box<FunctionDeclNode>);
-                  fd.params.push(Box::new(ValueNode {
-                    ntype: "FunctionArgumentDecl".to_string(),
-                    value: ExtraNodeData::String(ident),
-                    children: vec![],
-                    ta: NaskoType::Unknown
-                  }));
-                  tree.push_leaf(fd);
-                  state.in_arg = true;
-                },
-                false => {
-                  if !state.in_block {
-                    let mut fd = pop_leaf!(tree.children, Box<FunctionDeclNode>);
-                    fd.value = ExtraNodeData::String(ident);
-                    tree.push_leaf(fd);
-                  } else {
-                    let mut fd = pop_leaf!(tree.children, Box<FunctionDeclNode>);
-                    fd.push_leaf(Box::new(ValueNode {
-                      ta: NaskoType::Unknown,
-                      ntype: "UnknownIdent".to_string(),
-                      value: ExtraNodeData::String(ident),
-                      children: vec![]
-                    }));
-                    state.has_unknown_ident = true;
-                    tree.push_leaf(fd);
-                  }
-                }
-              }
-            }
-          },
-          NaskoToken::LiteralString(s) => {
-            literal!(tree, state, lex, || {
-              Box::new(ValueNode {
-                ntype: "Constant".to_string(),
-                value: ExtraNodeData::String(s),
-                children: vec![],
-                ta: NaskoType::String
-              }) 
-            });
-          },
-          NaskoToken::LiteralBoolean(b) => {
-            literal!(tree, state, lex, || {
-              Box::new(ValueNode {
-                ntype: "Constant".to_string(),
-                value: ExtraNodeData::Boolean(b),
-                children: vec![],
-                ta: NaskoType::Boolean
-              }) 
-            });
-          },
-          NaskoToken::LiteralNumber(n) => {
-            literal!(tree, state, lex, || {
-              Box::new(ValueNode {
-                ntype: "Constant".to_string(),
-                value: ExtraNodeData::Number(n),
-                children: vec![],
-                ta: NaskoType::Number
-              }) 
-            });
-          },
-          NaskoToken::LiteralHex(n) => {
-            literal!(tree, state, lex, || {
-              Box::new(ValueNode {
-                ntype: "Constant".to_string(),
-                value: ExtraNodeData::Number(n as f64),
-                children: vec![],
-                ta: NaskoType::Number
-              }) 
-            });
-          },
-          NaskoToken::ArithmeticOperator(op) => {
-            expr_push!(tree, state, lex, |c| {
-              tree.push_leaf(Box::new(BinaryExpression {
-                rhs: Some(c),
-                lhs: None,
-                expression: op,
-                children: vec![],
-                ta: NaskoType::Unknown
-              }));
-            });
-
-            state.in_expression = true;
-          },
-          NaskoToken::TypeAnnotation(t) => {
-            if t == NaskoType::Unknown {
-              continue;
-            }
-            
-            if state.in_function_decl && state.in_colon {
-              match state.in_arg {
-                true => {
-                  let mut fd = pop_leaf!(tree.children, Box<FunctionDeclNode>);
-                  let mut ad = pop_leaf!(fd.params, Box<ValueNode>);
-                  ad.ta = t;
-
-                  fd.params.push(ad);
-                  tree.push_leaf(fd);
-                },
-                false => {
-                  let mut fd = pop_leaf!(tree.children, Box<FunctionDeclNode>);
-                  fd.ta = t;
-                  tree.push_leaf(fd);
-                }
-              }
-            }
-          },
-          NaskoToken::Keyword(k) => {
-            match k {
-              NaskoKeyword::Func => {
-                tree.push_leaf(Box::new(ValueNode {
-                  children: vec![],
-                  ntype: "FunctionDecl".to_string(),
-                  value: ExtraNodeData::None,
-                  ta: NaskoType::Unknown
-                }));
-                state.in_function_decl = true;
-              },
-              NaskoKeyword::Struct => todo!(),
-              NaskoKeyword::Enum => todo!(),
-              NaskoKeyword::Import => todo!(),
-              NaskoKeyword::If => todo!(),
-              NaskoKeyword::Return => {
-                if !state.in_function_decl {
-                  compile_error(format!("Invalid `return` statment outside of function declaration ({:?})", lex.span()))
-                }
-
-                let mut func = tree.children.pop().unwrap();
-                func.push_leaf(Box::new(StatementNode {
-                  children: vec![],
-                  ntype: "ReturnStatement".to_string(),
-                  ta: NaskoType::Unknown
-                }));
-                tree.push_leaf(func);
-                state.in_return = true;
-              },
-              NaskoKeyword::While => todo!(),
-              NaskoKeyword::Let => todo!(),
-              NaskoKeyword::As => compile_error("The Nasko `as` keyword has not been implemented yet".to_string())
-            };
-          },
-          NaskoToken::Error => {
-            println!("error at span: {:?}\nwith slice: '{}'", lex.span(), lex.slice());
-          },
-          _ => {
-            println!("token: {:?}", t);
-            todo!()
+    // Lexer errors were collected up front
+    self.diagnostics.sort_by_key(|d| d.primary.span.start);
+    (tree, self.diagnostics)
+  }
+
+  fn item(&mut self) -> ParseResult<Box<dyn ASTNode>> {
+    match self.peek() {
+      Some(NaskoToken::Keyword(NaskoKeyword::Func)) => self.function(),
+      Some(NaskoToken::Keyword(NaskoKeyword::Struct)) => self.structure(),
+      Some(NaskoToken::Keyword(NaskoKeyword::Enum)) => self.enumeration(),
+      Some(NaskoToken::Keyword(NaskoKeyword::Import)) => self.import(),
+      _ => self.unexpected("a declaration")
+    }
+  }
+
+  /// `import "path/to/module.nasko";` or `import path.to.module;`
+  fn import(&mut self) -> ParseResult<Box<dyn ASTNode>> {
+    let start = self.peek_span().start;
+    self.advance();
+
+    let path = match self.peek() {
+      Some(NaskoToken::LiteralString(path)) => {
+        let path = ImportPath::File(path.trim_matches('"').to_string());
+        self.advance();
+        path
+      },
+      _ => {
+        let mut segments = vec![self.expect_ident("a module path")?.0];
+        while self.eat(&NaskoToken::Dot).is_some() {
+          segments.push(self.expect_ident("a module name")?.0);
+        }
+        ImportPath::Module(segments)
+      }
+    };
+    self.expect(&NaskoToken::Semicolon, "`;`")?;
+
+    Ok(Box::new(ImportNode {
+      ta: NaskoType::Unknown,
+      path,
+      span: start..self.prev_end(),
+      children: vec![]
+    }))
+  }
+
+  /// `func name(a: type, ...): type { ... }`
+  fn function(&mut self) -> ParseResult<Box<dyn ASTNode>> {
+    let start = self.peek_span().start;
+    self.advance();
+
+    let mut func = FunctionDeclNode {
+      ta: NaskoType::Unknown,
+      value: ExtraNodeData::None,
+      params: vec![],
+      span: self.peek_span(),
+      children: vec![]
+    };
+
+    // A broken signature still lets the body be parsed for syntax errors,
+    // but it can't be checked without the parameters
+    if self.function_signature(&mut func).is_err() {
+      while self.peek().is_some() && !self.check(&NaskoToken::BlockOpen) {
+        if self.is_synchronizing() {
+          return Err(SyntaxError);
+        }
+        self.advance();
+      }
+      self.block_statements()?;
+      return Ok(self.error_node(start));
+    }
+
+    func.children = self.block_statements()?;
+
+    Ok(Box::new(func))
+  }
+
+  /// `struct Name { field: type, ... }`
+  fn structure(&mut self) -> ParseResult<Box<dyn ASTNode>> {
+    self.advance();
+
+    let (name, span) = self.expect_ident("a struct name")?;
+    self.expect(&NaskoToken::BlockOpen, "`{`")?;
+
+    let mut fields = vec![];
+    while self.eat(&NaskoToken::BlockClose).is_none() {
+      let (field, span) = self.expect_ident("a field name or `}`")?;
+      self.expect(&NaskoToken::Colon, "`:`")?;
+      let ta = self.type_annotation()?;
+
+      fields.push(ValueNode {
+        ta,
+        ntype: "StructFieldDecl".to_string(),
+        value: ExtraNodeData::String(field),
+        span,
+        children: vec![]
+      });
+
+      if self.eat(&NaskoToken::Comma).is_none() {
+        self.expect(&NaskoToken::BlockClose, "`,` or `}`")?;
+        break;
+      }
+    }
+
+    Ok(Box::new(StructDeclNode {
+      ta: NaskoType::Struct(name.clone()),
+      name,
+      fields,
+      span,
+      children: vec![]
+    }))
+  }
+
+  /// `enum Name { Variant, Variant = value, ... }`
+  fn enumeration(&mut self) -> ParseResult<Box<dyn ASTNode>> {
+    self.advance();
+
+    let (name, span) = self.expect_ident("an enum name")?;
+    self.expect(&NaskoToken::BlockOpen, "`{`")?;
+
+    let mut variants = vec![];
+    while self.eat(&NaskoToken::BlockClose).is_none() {
+      let (variant, span) = self.expect_ident("a variant name or `}`")?;
+
+      let mut children = vec![];
+      if self.eat(&NaskoToken::Eq).is_some() {
+        children.push(self.expression(0)?);
+      }
+
+      variants.push(ValueNode {
+        ta: NaskoType::Enum(name.clone()),
+        ntype: "EnumVariantDecl".to_string(),
+        value: ExtraNodeData::String(variant),
+        span,
+        children
+      });
+
+      if self.eat(&NaskoToken::Comma).is_none() {
+        self.expect(&NaskoToken::BlockClose, "`,` or `}`")?;
+        break;
+      }
+    }
+
+    Ok(Box::new(EnumDeclNode {
+      ta: NaskoType::Enum(name.clone()),
+      name,
+      variants,
+      span,
+      children: vec![]
+    }))
+  }
+
+  fn function_signature(&mut self, func: &mut FunctionDeclNode) -> ParseResult<()> {
+    let (name, span) = self.expect_ident("a function name")?;
+    func.value = ExtraNodeData::String(name);
+    func.span = span;
+
+    self.expect(&NaskoToken::ParenOpen, "`(`")?;
+    if self.eat(&NaskoToken::ParenClose).is_none() {
+      loop {
+        let (name, span) = self.expect_ident("a parameter name")?;
+        let ta = match self.eat(&NaskoToken::Colon) {
+          Some(_) => self.type_annotation()?,
+          None => NaskoType::Unknown
+        };
+
+        func.params.push(ValueNode {
+          ta,
+          ntype: "FunctionArgumentDecl".to_string(),
+          value: ExtraNodeData::String(name),
+          span,
+          children: vec![]
+        });
+
+        if self.eat(&NaskoToken::Comma).is_none() {
+          self.expect(&NaskoToken::ParenClose, "`,` or `)`")?;
+          break;
+        }
+      }
+    }
+
+    if self.eat(&NaskoToken::Colon).is_some() {
+      func.ta = self.type_annotation()?;
+    }
+
+    Ok(())
+  }
+
+  /// A type name followed by any number of `[]` or `[N]`
+  fn type_annotation(&mut self) -> ParseResult<NaskoType> {
+    let mut ta = match self.peek() {
+      Some(NaskoToken::TypeAnnotation(_)) => match self.advance() {
+        Some((NaskoToken::TypeAnnotation(t), _)) => t,
+        _ => unreachable!()
+      },
+      Some(NaskoToken::Ident(_)) => {
+        let (name, _) = self.expect_ident("a type")?;
+        NaskoType::Named(name)
+      },
+      _ => return self.unexpected("a type")
+    };
+
+    while self.eat(&NaskoToken::SubscriptOpen).is_some() {
+      let len = match self.peek() {
+        Some(NaskoToken::LiteralInteger(n)) => {
+          let (n, span) = (*n, self.peek_span());
+          self.advance();
+          match usize::try_from(n) {
+            Ok(n) => Some(n),
+            Err(_) => return self.error(format!("Invalid array length `{}`", n), span)
           }
+        },
+        _ => None
+      };
+      self.expect(&NaskoToken::SubscriptClose, "`]`")?;
+      ta = NaskoType::Array(Box::new(ta), len);
+    }
+
+    Ok(ta)
+  }
+
+  /// `{ statement* }`, returning the statements
+  fn block_statements(&mut self) -> ParseResult<Vec<Box<dyn ASTNode>>> {
+    self.expect(&NaskoToken::BlockOpen, "`{`")?;
+
+    let mut statements = vec![];
+    loop {
+      match self.peek() {
+        Some(NaskoToken::BlockClose) => {
+          self.advance();
+          return Ok(statements);
+        },
+        None
+        | Some(NaskoToken::Keyword(NaskoKeyword::Func))
+        | Some(NaskoToken::Keyword(NaskoKeyword::Struct))
+        | Some(NaskoToken::Keyword(NaskoKeyword::Enum))
+        | Some(NaskoToken::Keyword(NaskoKeyword::Import)) => {
+          return self.unexpected("`}`");
+        },
+        _ => statements.push(self.statement())
+      }
+    }
+  }
+
+  fn block(&mut self) -> ParseResult<Box<dyn ASTNode>> {
+    let start = self.peek_span().start;
+    let children = self.block_statements()?;
+
+    Ok(Box::new(BlockNode {
+      ta: NaskoType::Void,
+      span: start..self.prev_end(),
+      children
+    }))
+  }
+
+  /// Parses a statement, replacing it with an error node if it is malformed
+  fn statement(&mut self) -> Box<dyn ASTNode> {
+    let start = self.peek_span().start;
+
+    match self.statement_inner() {
+      Ok(statement) => statement,
+      Err(SyntaxError) => {
+        self.synchronize();
+        self.error_node(start)
+      }
+    }
+  }
+
+  fn statement_inner(&mut self) -> ParseResult<Box<dyn ASTNode>> {
+    match self.peek() {
+      Some(NaskoToken::Keyword(NaskoKeyword::Return)) => self.return_statement(),
+      Some(NaskoToken::Keyword(NaskoKeyword::Let)) => self.let_statement(),
+      Some(NaskoToken::Keyword(NaskoKeyword::If)) => self.if_statement(),
+      Some(NaskoToken::Keyword(NaskoKeyword::While)) => self.while_statement(),
+      Some(NaskoToken::Keyword(NaskoKeyword::Break)) => self.jump_statement("BreakStatement"),
+      Some(NaskoToken::Keyword(NaskoKeyword::Continue)) => self.jump_statement("ContinueStatement"),
+      Some(NaskoToken::Keyword(_)) => self.unexpected("a statement"),
+      Some(NaskoToken::BlockOpen) => self.block(),
+      _ => {
+        let expression = self.expression(0)?;
+        let mut children = vec![expression];
+        let ntype = match self.eat(&NaskoToken::Eq) {
+          Some(_) => {
+            children.push(self.expression(0)?);
+            "AssignmentStatement"
+          },
+          None => "ExpressionStatement"
+        };
+        self.expect(&NaskoToken::Semicolon, "`;`")?;
+
+        Ok(Box::new(StatementNode {
+          ta: NaskoType::Void,
+          ntype: ntype.to_string(),
+          span: children[0].span().start..self.prev_end(),
+          children
+        }))
+      }
+    }
+  }
+
+  /// `return expression?;`
+  fn return_statement(&mut self) -> ParseResult<Box<dyn ASTNode>> {
+    let start = self.peek_span().start;
+    self.advance();
+
+    let mut children = vec![];
+    if !self.check(&NaskoToken::Semicolon) {
+      children.push(self.expression(0)?);
+    }
+    self.expect(&NaskoToken::Semicolon, "`;`")?;
+
+    Ok(Box::new(StatementNode {
+      ta: NaskoType::Void,
+      ntype: "ReturnStatement".to_string(),
+      span: start..self.prev_end(),
+      children
+    }))
+  }
+
+  /// `let name (: type)? = expression;`
+  fn let_statement(&mut self) -> ParseResult<Box<dyn ASTNode>> {
+    self.advance();
+
+    let (name, span) = self.expect_ident("a variable name")?;
+    // Once named, the variable is declared even if the rest is malformed,
+    // so its uses aren't reported too
+    let start = self.peek_span().start;
+    let mut ta = NaskoType::Unknown;
+    let initializer = match self.let_rest(&mut ta) {
+      Ok(initializer) => initializer,
+      Err(SyntaxError) => {
+        self.synchronize();
+        self.error_node(start)
+      }
+    };
+
+    Ok(Box::new(VariableDeclNode {
+      ta,
+      name,
+      span,
+      children: vec![initializer]
+    }))
+  }
+
+  /// `(: type)? = initializer;` after the name of a `let`
+  fn let_rest(&mut self, ta: &mut NaskoType) -> ParseResult<Box<dyn ASTNode>> {
+    if self.eat(&NaskoToken::Colon).is_some() {
+      *ta = self.type_annotation()?;
+    }
+    self.expect(&NaskoToken::Eq, "`=`")?;
+    let initializer = self.expression(0)?;
+    self.expect(&NaskoToken::Semicolon, "`;`")?;
+    Ok(initializer)
+  }
+
+  /// `if condition { ... } (else if ... | else { ... })?`
+  ///
+  /// An `else if` is stored as a nested if statement in the else branch
+  fn if_statement(&mut self) -> ParseResult<Box<dyn ASTNode>> {
+    let start = self.peek_span().start;
+    self.advance();
+
+    let mut children = vec![self.condition()?, self.block()?];
+    if self.eat(&NaskoToken::Keyword(NaskoKeyword::Else)).is_some() {
+      children.push(match self.peek() {
+        Some(NaskoToken::Keyword(NaskoKeyword::If)) => self.if_statement()?,
+        _ => self.block()?
+      });
+    }
+
+    Ok(Box::new(StatementNode {
+      ta: NaskoType::Void,
+      ntype: "IfStatement".to_string(),
+      span: start..self.prev_end(),
+      children
+    }))
+  }
+
+  /// `while condition { ... }`
+  fn while_statement(&mut self) -> ParseResult<Box<dyn ASTNode>> {
+    let start = self.peek_span().start;
+    self.advance();
+
+    let children = vec![self.condition()?, self.block()?];
+
+    Ok(Box::new(StatementNode {
+      ta: NaskoType::Void,
+      ntype: "WhileStatement".to_string(),
+      span: start..self.prev_end(),
+      children
+    }))
+  }
+
+  /// `break;` or `continue;`
+  fn jump_statement(&mut self, ntype: &str) -> ParseResult<Box<dyn ASTNode>> {
+    let start = self.peek_span().start;
+    self.advance();
+    self.expect(&NaskoToken::Semicolon, "`;`")?;
+
+    Ok(Box::new(StatementNode {
+      ta: NaskoType::Void,
+      ntype: ntype.to_string(),
+      span: start..self.prev_end(),
+      children: vec![]
+    }))
+  }
+
+  /// The condition of an `if` or `while`, which can't be a struct literal
+  fn condition(&mut self) -> ParseResult<Box<dyn ASTNode>> {
+    self.with_struct_literals(false, |parser| parser.expression(0))
+  }
+
+  /// Parses operators binding tighter than `min_bp`
+  fn expression(&mut self, min_bp: u8) -> ParseResult<Box<dyn ASTNode>> {
+    let mut lhs = self.prefix()?;
+
+    loop {
+      let op = match self.peek() {
+        Some(NaskoToken::ArithmeticOperator(op)) |
+        Some(NaskoToken::ComparisonOperator(op)) |
+        Some(NaskoToken::LogicalOperator(op)) |
+        Some(NaskoToken::BitwiseOperator(op)) => op.clone(),
+        Some(NaskoToken::Keyword(NaskoKeyword::As)) if CAST_BINDING_POWER >= min_bp => {
+          self.advance();
+          let ta = self.type_annotation()?;
+          let span = lhs.span().start..self.prev_end();
+
+          lhs = Box::new(CastNode {
+            ta,
+            span,
+            children: vec![lhs]
+          });
+          continue;
+        },
+        _ => break
+      };
+      let (l_bp, r_bp) = match infix_binding_power(&op) {
+        Some(bp) => bp,
+        None => break
+      };
+      if l_bp < min_bp {
+        break;
+      }
+
+      self.advance();
+      let rhs = self.expression(r_bp)?;
+      let span = lhs.span().start..rhs.span().end;
+
+      lhs = Box::new(BinaryExpression {
+        ta: NaskoType::Unknown,
+        lhs: Some(lhs),
+        rhs: Some(rhs),
+        expression: op,
+        span,
+        children: vec![]
+      });
+    }
+
+    Ok(lhs)
+  }
+
+  /// Any number of prefix operators applied to a postfix expression
+  ///
+  /// Number literals are never signed, `-1` is a negation
+  fn prefix(&mut self) -> ParseResult<Box<dyn ASTNode>> {
+    let op = match self.peek() {
+      Some(NaskoToken::ArithmeticOperator(NaskoArithmetic::Subtract)) => NaskoUnary::Negate,
+      Some(NaskoToken::Not) => NaskoUnary::Not,
+      Some(NaskoToken::Tilde) => NaskoUnary::BitNot,
+      _ => return self.postfix()
+    };
+    let start = self.peek_span().start;
+    self.advance();
+
+    let operand = self.expression(PREFIX_BINDING_POWER)?;
+    Ok(Box::new(UnaryExpression {
+      ta: NaskoType::Unknown,
+      expression: op,
+      span: start..operand.span().end,
+      children: vec![operand]
+    }))
+  }
+
+  /// A primary expression followed by any number of `.field` accesses
+  /// and `[index]` subscripts
+  fn postfix(&mut self) -> ParseResult<Box<dyn ASTNode>> {
+    let mut expression = self.primary()?;
+
+    loop {
+      if self.eat(&NaskoToken::Dot).is_some() {
+        let (field, span) = self.expect_ident("a field name")?;
+
+        expression = Box::new(FieldAccessNode {
+          ta: NaskoType::Unknown,
+          field,
+          span: expression.span().start..span.end,
+          children: vec![expression]
+        });
+      } else if self.eat(&NaskoToken::SubscriptOpen).is_some() {
+        let index = self.with_struct_literals(true, |parser| parser.expression(0))?;
+        let end = self.expect(&NaskoToken::SubscriptClose, "`]`")?.end;
+
+        expression = Box::new(IndexNode {
+          ta: NaskoType::Unknown,
+          span: expression.span().start..end,
+          children: vec![expression, index]
+        });
+      } else {
+        break;
+      }
+    }
+
+    Ok(expression)
+  }
+
+  fn primary(&mut self) -> ParseResult<Box<dyn ASTNode>> {
+    let span = self.peek_span();
+    let (value, ta) = match self.peek() {
+      Some(NaskoToken::LiteralString(s)) => (ExtraNodeData::String(s.trim_matches('"').to_string()), NaskoType::String),
+      Some(NaskoToken::LiteralBoolean(b)) => (ExtraNodeData::Boolean(*b), NaskoType::Boolean),
+      Some(NaskoToken::LiteralInteger(n)) => (ExtraNodeData::Integer(*n), NaskoType::INT),
+      Some(NaskoToken::LiteralFloat(n)) => (ExtraNodeData::Float(*n), NaskoType::Float(FloatKind::Double)),
+      Some(NaskoToken::Ident(_)) => return self.identifier(),
+      Some(NaskoToken::SubscriptOpen) => return self.array_literal(),
+      Some(NaskoToken::ParenOpen) => {
+        self.advance();
+        let expression = self.with_struct_literals(true, |parser| parser.expression(0))?;
+        self.expect(&NaskoToken::ParenClose, "`)`")?;
+        return Ok(expression);
+      },
+      _ => return self.unexpected("an expression")
+    };
+    self.advance();
+
+    Ok(Box::new(ValueNode {
+      ta,
+      ntype: "Constant".to_string(),
+      value,
+      span,
+      children: vec![]
+    }))
+  }
+
+  /// A name, a call if it is followed by an argument list, or a struct
+  /// literal if it is followed by braces
+  fn identifier(&mut self) -> ParseResult<Box<dyn ASTNode>> {
+    let (name, span) = self.expect_ident("an identifier")?;
+
+    if self.check(&NaskoToken::BlockOpen) && self.struct_literals {
+      return self.struct_literal(name, span);
+    }
+
+    if self.eat(&NaskoToken::ParenOpen).is_none() {
+      return Ok(Box::new(ValueNode {
+        ta: NaskoType::Unknown,
+        ntype: "UnknownIdent".to_string(),
+        value: ExtraNodeData::String(name),
+        span,
+        children: vec![]
+      }));
+    }
+
+    let mut args = vec![];
+    if self.eat(&NaskoToken::ParenClose).is_none() {
+      loop {
+        args.push(self.with_struct_literals(true, |parser| parser.expression(0))?);
+        if self.eat(&NaskoToken::Comma).is_none() {
+          self.expect(&NaskoToken::ParenClose, "`,` or `)`")?;
+          break;
         }
       }
     }
+
+    Ok(Box::new(CallNode {
+      ta: NaskoType::Unknown,
+      name,
+      name_span: span.clone(),
+      binding: None,
+      span: span.start..self.prev_end(),
+      children: args
+    }))
   }
-  
-  Box::new(tree)
+
+  /// `[expression, ...]`
+  fn array_literal(&mut self) -> ParseResult<Box<dyn ASTNode>> {
+    let start = self.peek_span().start;
+    self.advance();
+
+    let mut elements = vec![];
+    while self.eat(&NaskoToken::SubscriptClose).is_none() {
+      elements.push(self.with_struct_literals(true, |parser| parser.expression(0))?);
+
+      if self.eat(&NaskoToken::Comma).is_none() {
+        self.expect(&NaskoToken::SubscriptClose, "`,` or `]`")?;
+        break;
+      }
+    }
+
+    Ok(Box::new(ArrayLiteralNode {
+      ta: NaskoType::Unknown,
+      span: start..self.prev_end(),
+      children: elements
+    }))
+  }
+
+  /// `Name { field: expression, ... }`
+  fn struct_literal(&mut self, name: String, span: Span) -> ParseResult<Box<dyn ASTNode>> {
+    self.advance();
+
+    let mut fields: Vec<Box<dyn ASTNode>> = vec![];
+    while self.eat(&NaskoToken::BlockClose).is_none() {
+      let (field, span) = self.expect_ident("a field name or `}`")?;
+      self.expect(&NaskoToken::Colon, "`:`")?;
+      let value = self.expression(0)?;
+
+      fields.push(Box::new(ValueNode {
+        ta: NaskoType::Unknown,
+        ntype: "FieldInit".to_string(),
+        value: ExtraNodeData::String(field),
+        span,
+        children: vec![value]
+      }));
+
+      if self.eat(&NaskoToken::Comma).is_none() {
+        self.expect(&NaskoToken::BlockClose, "`,` or `}`")?;
+        break;
+      }
+    }
+
+    Ok(Box::new(StructLiteralNode {
+      ta: NaskoType::Unknown,
+      name,
+      span: span.start..self.prev_end(),
+      children: fields
+    }))
+  }
+}
+
+/// Parses a whole file, `base` is its offset in the source map
+///
+/// Constructs that failed to parse are left in the tree as `ErrorNode`s,
+/// so later phases can still report errors in the rest of the file.
+pub fn parse(lex: &mut Lexer<NaskoToken>, base: usize) -> (SourceNode, Vec<Diagnostic>) {
+  Parser::new(lex, base).parse_source()
 }
@@ -0,0 +1,191 @@
+//! Name resolution
+//!
+//! Binds every identifier in a function body to the declaration it refers
+//! to. Functions, structs and enums are visible everywhere, parameters and
+//! `let` bindings from their declaration to the end of the enclosing block.
+//! Functions and types have separate namespaces, calls look a name up among
+//! functions and other identifiers among types first.
+//! Identifiers that resolve are replaced by `NameNode`s, the others are
+//! reported and left as `UnknownIdent`s, unless the tree is missing
+//! declarations that failed to parse.
+
+use std::collections::{HashMap, HashSet};
+use crate::ast::*;
+use crate::diagnostic::{Diagnostic, Span};
+
+pub fn resolve_names(tree: &mut SourceNode) -> Vec<Diagnostic> {
+  let mut resolver = Resolver {
+    functions: HashSet::new(),
+    types: HashSet::new(),
+    complete: tree.is_complete(),
+    scopes: vec![],
+    diagnostics: vec![]
+  };
+
+  // Duplicate functions and types are reported by the type checker
+  for leaf in &tree.children {
+    if let Some(func) = leaf.downcast_ref::<FunctionDeclNode>() {
+      if let ExtraNodeData::String(name) = &func.value {
+        resolver.functions.insert(name.clone());
+      }
+    } else if let Some(decl) = leaf.downcast_ref::<StructDeclNode>() {
+      resolver.types.insert(decl.name.clone());
+    } else if let Some(decl) = leaf.downcast_ref::<EnumDeclNode>() {
+      resolver.types.insert(decl.name.clone());
+    }
+  }
+
+  for leaf in tree.children.iter_mut() {
+    if let Some(func) = leaf.downcast_mut::<FunctionDeclNode>() {
+      resolver.function(func);
+    }
+  }
+
+  resolver.diagnostics
+}
+
+struct Resolver {
+  /// Names of the functions
+  functions: HashSet<String>,
+  /// Names of the structs and enums
+  types: HashSet<String>,
+  /// Whether unknown names are reported, see `SourceNode::is_complete`
+  complete: bool,
+  /// Declaration span of every local in scope by name, innermost scope last
+  scopes: Vec<HashMap<String, Span>>,
+  diagnostics: Vec<Diagnostic>
+}
+
+impl Resolver {
+  fn function(&mut self, func: &mut FunctionDeclNode) {
+    self.scopes.push(HashMap::new());
+    for param in &func.params {
+      if let ExtraNodeData::String(name) = &param.value {
+        self.declare(name, param.span.clone());
+      }
+    }
+
+    // The body is a scope of its own, so a `let` may shadow a parameter
+    self.scoped(&mut func.children);
+    self.scopes.pop();
+  }
+
+  fn scoped(&mut self, statements: &mut [Box<dyn ASTNode>]) {
+    self.scopes.push(HashMap::new());
+    for statement in statements {
+      self.statement(statement);
+    }
+    self.scopes.pop();
+  }
+
+  fn statement(&mut self, node: &mut Box<dyn ASTNode>) {
+    if let Some(decl) = node.downcast_mut::<VariableDeclNode>() {
+      // The initializer can't see the variable it initializes
+      for initializer in decl.children.iter_mut() {
+        self.expression(initializer);
+      }
+      self.declare(&decl.name, decl.span.clone());
+    } else if let Some(block) = node.downcast_mut::<BlockNode>() {
+      self.scoped(&mut block.children);
+    } else if let Some(statement) = node.downcast_mut::<StatementNode>() {
+      for child in statement.children.iter_mut() {
+        self.statement(child);
+      }
+    } else {
+      self.expression(node);
+    }
+  }
+
+  fn expression(&mut self, node: &mut Box<dyn ASTNode>) {
+    if let Some(value) = node.downcast_ref::<ValueNode>() {
+      if value.ntype == "UnknownIdent" {
+        if let Some(name) = self.identifier(value) {
+          *node = Box::new(name);
+        }
+        return;
+      }
+    } else if let Some(binary) = node.downcast_mut::<BinaryExpression>() {
+      for operand in binary.lhs.iter_mut().chain(binary.rhs.iter_mut()) {
+        self.expression(operand);
+      }
+    } else if let Some(call) = node.downcast_mut::<CallNode>() {
+      call.binding = self.local(&call.name)
+        .or_else(|| self.functions.contains(&call.name).then_some(Binding::Function));
+      if call.binding.is_none() && self.complete {
+        self.diagnostics.push(
+          Diagnostic::error(format!("Cannot find function `{}` in this scope", call.name), call.name_span.clone())
+            .with_label("not found in this scope".to_string())
+        );
+      }
+    }
+
+    for leaf in node.get_leaves_mut().iter_mut() {
+      self.expression(leaf);
+    }
+  }
+
+  fn identifier(&mut self, value: &ValueNode) -> Option<NameNode> {
+    let name = match &value.value {
+      ExtraNodeData::String(name) => name,
+      _ => return None
+    };
+
+    match self.lookup(name) {
+      Some(binding) => Some(NameNode {
+        ta: value.ta.clone(),
+        name: name.clone(),
+        binding,
+        span: value.span.clone(),
+        children: vec![]
+      }),
+      None => {
+        if !self.complete {
+          return None;
+        }
+        self.diagnostics.push(
+          Diagnostic::error(format!("Cannot find value `{}` in this scope", name), value.span.clone())
+            .with_label("not found in this scope".to_string())
+        );
+        None
+      }
+    }
+  }
+
+  /// Locals shadow types, which shadow functions
+  fn lookup(&self, name: &str) -> Option<Binding> {
+    self.local(name)
+      .or_else(|| self.types.contains(name).then_some(Binding::Type))
+      .or_else(|| self.functions.contains(name).then_some(Binding::Function))
+  }
+
+  fn local(&self, name: &str) -> Option<Binding> {
+    self.scopes.iter().rev()
+      .find_map(|scope| scope.get(name))
+      .map(|span| Binding::Local(span.clone()))
+  }
+
+  fn declare(&mut self, name: &str, span: Span) {
+    let (scope, outer) = match self.scopes.split_last_mut() {
+      Some(scopes) => scopes,
+      None => return
+    };
+
+    if let Some(previous) = scope.get(name) {
+      self.diagnostics.push(
+        Diagnostic::error(format!("The name `{}` is defined multiple times in the same scope", name), span)
+          .with_label(format!("`{}` redefined here", name))
+          .with_secondary(previous.clone(), format!("previous definition of `{}` here", name))
+      );
+      return;
+    }
+    if let Some(previous) = outer.iter().rev().find_map(|scope| scope.get(name)) {
+      self.diagnostics.push(
+        Diagnostic::warning(format!("`{}` shadows a variable from an outer scope", name), span.clone())
+          .with_label(format!("`{}` declared again here", name))
+          .with_secondary(previous.clone(), format!("`{}` first declared here", name))
+      );
+    }
+
+    scope.insert(name.to_string(), span);
+  }
+}
@@ -1,3 +1,6 @@
+use std::fmt;
+
+/// Binary operators
 #[derive(Debug, PartialEq, Clone)]
 pub enum NaskoArithmetic {
   Add,
@@ -6,6 +9,19 @@ pub enum NaskoArithmetic {
   Multiply,
   Modulo,
   Power,
+  Equal,
+  NotEqual,
+  Less,
+  LessEqual,
+  Greater,
+  GreaterEqual,
+  And,
+  Or,
+  BitAnd,
+  BitOr,
+  BitXor,
+  ShiftLeft,
+  ShiftRight,
   None
 }
 
@@ -18,33 +34,256 @@ impl NaskoArithmetic {
       "*" => NaskoArithmetic::Multiply,
       "%" => NaskoArithmetic::Modulo,
       "**" => NaskoArithmetic::Power,
+      "==" => NaskoArithmetic::Equal,
+      "!=" => NaskoArithmetic::NotEqual,
+      "<" => NaskoArithmetic::Less,
+      "<=" => NaskoArithmetic::LessEqual,
+      ">" => NaskoArithmetic::Greater,
+      ">=" => NaskoArithmetic::GreaterEqual,
+      "&&" => NaskoArithmetic::And,
+      "||" => NaskoArithmetic::Or,
+      "&" => NaskoArithmetic::BitAnd,
+      "|" => NaskoArithmetic::BitOr,
+      "^" => NaskoArithmetic::BitXor,
+      "<<" => NaskoArithmetic::ShiftLeft,
+      ">>" => NaskoArithmetic::ShiftRight,
       _ => NaskoArithmetic::None
     }
   }
+
+  /// `==`, `!=`, `<`, `<=`, `>` and `>=`
+  pub fn is_comparison(&self) -> bool {
+    matches!(
+      self,
+      NaskoArithmetic::Equal | NaskoArithmetic::NotEqual |
+      NaskoArithmetic::Less | NaskoArithmetic::LessEqual |
+      NaskoArithmetic::Greater | NaskoArithmetic::GreaterEqual
+    )
+  }
+
+  /// `&&` and `||`
+  pub fn is_logical(&self) -> bool {
+    matches!(self, NaskoArithmetic::And | NaskoArithmetic::Or)
+  }
+
+  /// `&`, `|`, `^`, `<<` and `>>`
+  pub fn is_bitwise(&self) -> bool {
+    matches!(
+      self,
+      NaskoArithmetic::BitAnd | NaskoArithmetic::BitOr | NaskoArithmetic::BitXor |
+      NaskoArithmetic::ShiftLeft | NaskoArithmetic::ShiftRight
+    )
+  }
 }
 
+/// Prefix operators
 #[derive(Debug, PartialEq, Clone)]
+pub enum NaskoUnary {
+  /// `-`, negation
+  Negate,
+  /// `!`, logical not
+  Not,
+  /// `~`, bitwise not
+  BitNot
+}
+
+/// Width and signedness of an integer type
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum IntKind {
+  I8,
+  I16,
+  I32,
+  I64,
+  U8,
+  U16,
+  U32,
+  U64
+}
+
+impl IntKind {
+  pub fn bits(self) -> u32 {
+    match self {
+      IntKind::I8 | IntKind::U8 => 8,
+      IntKind::I16 | IntKind::U16 => 16,
+      IntKind::I32 | IntKind::U32 => 32,
+      IntKind::I64 | IntKind::U64 => 64
+    }
+  }
+
+  pub fn is_signed(self) -> bool {
+    matches!(self, IntKind::I8 | IntKind::I16 | IntKind::I32 | IntKind::I64)
+  }
+
+  /// Smallest value of the type
+  pub fn min(self) -> i128 {
+    if self.is_signed() { -(1 << (self.bits() - 1)) } else { 0 }
+  }
+
+  /// Largest value of the type
+  pub fn max(self) -> i128 {
+    if self.is_signed() { (1 << (self.bits() - 1)) - 1 } else { (1 << self.bits()) - 1 }
+  }
+
+  pub fn contains(self, value: i128) -> bool {
+    self.min() <= value && value <= self.max()
+  }
+}
+
+impl fmt::Display for IntKind {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let sign = if self.is_signed() { 'i' } else { 'u' };
+    write!(f, "{}{}", sign, self.bits())
+  }
+}
+
+/// Precision of a floating-point type
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum FloatKind {
+  /// 32 bit
+  Float,
+  /// 64 bit
+  Double
+}
+
+impl fmt::Display for FloatKind {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      FloatKind::Float => write!(f, "float"),
+      FloatKind::Double => write!(f, "double")
+    }
+  }
+}
+
+impl fmt::Display for NaskoArithmetic {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let symbol = match self {
+      NaskoArithmetic::Add => "+",
+      NaskoArithmetic::Subtract => "-",
+      NaskoArithmetic::Divide => "/",
+      NaskoArithmetic::Multiply => "*",
+      NaskoArithmetic::Modulo => "%",
+      NaskoArithmetic::Power => "**",
+      NaskoArithmetic::Equal => "==",
+      NaskoArithmetic::NotEqual => "!=",
+      NaskoArithmetic::Less => "<",
+      NaskoArithmetic::LessEqual => "<=",
+      NaskoArithmetic::Greater => ">",
+      NaskoArithmetic::GreaterEqual => ">=",
+      NaskoArithmetic::And => "&&",
+      NaskoArithmetic::Or => "||",
+      NaskoArithmetic::BitAnd => "&",
+      NaskoArithmetic::BitOr => "|",
+      NaskoArithmetic::BitXor => "^",
+      NaskoArithmetic::ShiftLeft => "<<",
+      NaskoArithmetic::ShiftRight => ">>",
+      NaskoArithmetic::None => "?"
+    };
+    write!(f, "{}", symbol)
+  }
+}
+
+impl fmt::Display for NaskoUnary {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      NaskoUnary::Negate => write!(f, "-"),
+      NaskoUnary::Not => write!(f, "!"),
+      NaskoUnary::BitNot => write!(f, "~")
+    }
+  }
+}
+
+/// What a type variable may stand for
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum VarKind {
+  /// Any type
+  Any,
+  /// Any integer type, from an integer literal
+  Integer,
+  /// Any float type, from a float literal
+  Float
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
 pub enum NaskoType {
   String,
   Boolean,
-  Number,
+  Int(IntKind),
+  Float(FloatKind),
+  /// A user-defined struct
+  Struct(String),
+  /// A user-defined enum
+  Enum(String),
+  /// `T[]`, or `T[N]` when the length is known
+  Array(Box<NaskoType>, Option<usize>),
+  /// A type name that has not been resolved yet
+  Named(String),
+  /// The result of a function that returns no value
+  Void,
+  /// A type being inferred, see `type_check::infer`
+  Var(usize, VarKind),
+  #[default]
   Unknown
 }
 
 impl NaskoType {
+  /// `int`, an alias of `i32`
+  pub const INT: NaskoType = NaskoType::Int(IntKind::I32);
+
   pub fn parse(slice: &str) -> NaskoType {
     match slice {
       "string" => NaskoType::String,
       "boolean" => NaskoType::Boolean,
-      "int" => NaskoType::Number,
+      "int" => NaskoType::INT,
+      "i8" => NaskoType::Int(IntKind::I8),
+      "i16" => NaskoType::Int(IntKind::I16),
+      "i32" => NaskoType::Int(IntKind::I32),
+      "i64" => NaskoType::Int(IntKind::I64),
+      "u8" => NaskoType::Int(IntKind::U8),
+      "u16" => NaskoType::Int(IntKind::U16),
+      "u32" => NaskoType::Int(IntKind::U32),
+      "u64" => NaskoType::Int(IntKind::U64),
+      "float" => NaskoType::Float(FloatKind::Float),
+      "double" => NaskoType::Float(FloatKind::Double),
       _ => NaskoType::Unknown
     }
   }
+
+  /// Whether arithmetic operators apply to the type
+  pub fn is_numeric(&self) -> bool {
+    matches!(self, NaskoType::Int(_) | NaskoType::Float(_))
+  }
+
+  /// Whether `==` and `!=` apply to the type
+  pub fn is_comparable(&self) -> bool {
+    matches!(self, NaskoType::Int(_) | NaskoType::Float(_) | NaskoType::Boolean | NaskoType::String | NaskoType::Enum(_))
+  }
+
+  /// Whether the type still contains variables being inferred
+  pub fn has_vars(&self) -> bool {
+    match self {
+      NaskoType::Var(..) => true,
+      NaskoType::Array(element, _) => element.has_vars(),
+      _ => false
+    }
+  }
 }
 
-impl Default for NaskoType {
-  fn default() -> Self {
-    NaskoType::Unknown
+impl fmt::Display for NaskoType {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      NaskoType::String => write!(f, "string"),
+      NaskoType::Boolean => write!(f, "boolean"),
+      NaskoType::Int(kind) => write!(f, "{}", kind),
+      NaskoType::Float(kind) => write!(f, "{}", kind),
+      NaskoType::Struct(name) | NaskoType::Enum(name) | NaskoType::Named(name) => write!(f, "{}", name),
+      NaskoType::Array(element, Some(len)) => write!(f, "{}[{}]", element, len),
+      NaskoType::Array(element, None) => write!(f, "{}[]", element),
+      NaskoType::Void => write!(f, "void"),
+      NaskoType::Var(_, VarKind::Any) => write!(f, "_"),
+      NaskoType::Var(_, VarKind::Integer) => write!(f, "{{integer}}"),
+      NaskoType::Var(_, VarKind::Float) => write!(f, "{{float}}"),
+      NaskoType::Unknown => write!(f, "{{unknown}}")
+    }
   }
 }
 
@@ -55,8 +294,11 @@ pub enum NaskoKeyword {
   Enum,
   Import,
   If,
+  Else,
   Return,
   While,
+  Break,
+  Continue,
   Let,
   As
 }
@@ -69,8 +311,11 @@ impl NaskoKeyword {
       "enum" => NaskoKeyword::Enum,
       "import" => NaskoKeyword::Import,
       "if" => NaskoKeyword::If,
+      "else" => NaskoKeyword::Else,
       "return" => NaskoKeyword::Return,
       "while" => NaskoKeyword::While,
+      "break" => NaskoKeyword::Break,
+      "continue" => NaskoKeyword::Continue,
       "let" => NaskoKeyword::Let,
       "as" => NaskoKeyword::As,
       _ => NaskoKeyword::Func
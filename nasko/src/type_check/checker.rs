@@ -0,0 +1,1230 @@
+use std::convert::TryFrom;
+use std::collections::{HashMap, HashSet};
+use crate::ast::*;
+use crate::semantics::*;
+use crate::diagnostic::{Diagnostic, Span};
+use super::functions::FunctionTable;
+use super::infer::Unifier;
+
+/// Parameter and return types of a function
+#[derive(Clone)]
+struct Signature {
+  params: Vec<NaskoType>,
+  ret: NaskoType
+}
+
+impl Signature {
+  /// The signature as it would be written, with the types inferred so far
+  fn display(&self, name: &str, unifier: &Unifier) -> String {
+    let params: Vec<String> = self.params.iter().map(|ta| unifier.resolve(ta).to_string()).collect();
+    format!("func {}({}): {}", name, params.join(", "), unifier.resolve(&self.ret))
+  }
+}
+
+/// A function whose return type is checked once every function is
+struct Returns {
+  name: String,
+  span: Span,
+  ta: NaskoType,
+  /// Whether a `return` in the body has a value
+  value: bool,
+  always: bool,
+  /// Whether a statement of the body failed to parse
+  malformed: bool
+}
+
+/// A check on the type of an operand, made once that type is known
+#[derive(Clone)]
+enum Constraint {
+  /// Not `void`, where a value is needed
+  Value,
+  Binary(NaskoArithmetic),
+  Unary(NaskoUnary),
+  /// Castable to the type
+  Cast(NaskoType)
+}
+
+impl Constraint {
+  /// The error for an operand of type `ta` that breaks the constraint
+  fn violation(&self, ta: &NaskoType, span: Span) -> Option<Diagnostic> {
+    // Every operand also has a `Value` constraint reporting `void`
+    let void = *ta == NaskoType::Void && !matches!(self, Constraint::Value);
+    if *ta == NaskoType::Unknown || void {
+      return None;
+    }
+
+    match self {
+      Constraint::Value => (*ta == NaskoType::Void).then(|| {
+        Diagnostic::error("Expected value, found `void`".to_string(), span)
+          .with_label("this expression does not produce a value".to_string())
+      }),
+      Constraint::Binary(op) => {
+        let (applies, needs) = match op {
+          _ if op.is_bitwise() => (matches!(ta, NaskoType::Int(_)), "integer"),
+          NaskoArithmetic::Equal | NaskoArithmetic::NotEqual => (ta.is_comparable(), "comparable"),
+          _ => (ta.is_numeric(), "numeric")
+        };
+        (!applies).then(|| {
+          Diagnostic::error(format!("Cannot apply `{}` to values of type `{}`", op, ta), span)
+            .with_label(format!("`{}` needs {} operands", op, needs))
+        })
+      },
+      Constraint::Unary(op) => {
+        let (applies, symbol, needs) = match op {
+          NaskoUnary::Negate => (
+            matches!(ta, NaskoType::Int(kind) if kind.is_signed()) || matches!(ta, NaskoType::Float(_)),
+            "-",
+            "a signed numeric"
+          ),
+          _ => (matches!(ta, NaskoType::Int(_)), "~", "an integer")
+        };
+        (!applies).then(|| {
+          Diagnostic::error(format!("Cannot apply `{}` to a value of type `{}`", symbol, ta), span)
+            .with_label(format!("`{}` needs {} operand", symbol, needs))
+        })
+      },
+      Constraint::Cast(to) => (*to != NaskoType::Unknown && !can_cast(ta, to)).then(|| {
+        Diagnostic::error(format!("Cannot cast `{}` as `{}`", ta, to), span)
+          .with_label("invalid cast".to_string())
+      })
+    }
+  }
+}
+
+/// Walks function bodies, annotating expressions with their types
+pub struct Checker<'a> {
+  /// Fields of every struct, by struct name
+  structs: HashMap<String, Vec<(String, NaskoType)>>,
+  /// Variants of every enum and their discriminants, by enum name
+  enums: HashMap<String, Vec<(String, i64)>>,
+  /// Declared signature of every function, by name
+  functions: HashMap<String, Signature>,
+  /// Name, name span and declared return type of the function being checked
+  function: Option<(String, Span, NaskoType)>,
+  /// Type of every variable and parameter, by the span of its declaration
+  locals: HashMap<Span, NaskoType>,
+  /// Number of loops around the current statement
+  loops: usize,
+  /// Every checked function, in order
+  returns: Vec<Returns>,
+  /// Constraints on operand types that were not inferred when checked
+  deferred: Vec<(Constraint, NaskoType, Span)>,
+  unifier: Unifier,
+  /// Type variables already reported as impossible to infer, or left
+  /// unknown by a syntax error
+  uninferred: Vec<usize>,
+  /// Whether unknown types are reported, see `SourceNode::is_complete`
+  complete: bool,
+  diagnostics: &'a mut Vec<Diagnostic>
+}
+
+impl<'a> Checker<'a> {
+  pub fn new(tree: &SourceNode, functions: &FunctionTable, diagnostics: &'a mut Vec<Diagnostic>) -> Checker<'a> {
+    let mut checker = Checker {
+      structs: HashMap::new(),
+      enums: HashMap::new(),
+      functions: HashMap::new(),
+      function: None,
+      locals: HashMap::new(),
+      loops: 0,
+      returns: vec![],
+      deferred: vec![],
+      unifier: Unifier::default(),
+      uninferred: vec![],
+      complete: tree.is_complete(),
+      diagnostics
+    };
+
+    // Type names come first so signatures can refer to them
+    let mut declared: HashMap<&str, Span> = HashMap::new();
+    for leaf in &tree.children {
+      let (name, span) = if let Some(decl) = leaf.downcast_ref::<StructDeclNode>() {
+        checker.structs.insert(decl.name.clone(), vec![]);
+        (&decl.name, decl.span.clone())
+      } else if let Some(decl) = leaf.downcast_ref::<EnumDeclNode>() {
+        checker.enums.insert(decl.name.clone(), vec![]);
+        (&decl.name, decl.span.clone())
+      } else {
+        continue;
+      };
+
+      if let Some(previous) = declared.insert(name, span.clone()) {
+        checker.diagnostics.push(
+          Diagnostic::error(format!("The type `{}` is defined multiple times", name), span)
+            .with_secondary(previous, format!("previous definition of `{}` here", name))
+        );
+      }
+    }
+
+    for (name, func) in functions.iter(tree) {
+      // Omitted types are inferred
+      let mut signature = Signature { params: vec![], ret: checker.inferred(&func.ta) };
+      for param in &func.params {
+        let ta = checker.inferred(&param.ta);
+        signature.params.push(ta);
+      }
+      checker.functions.insert(name.to_string(), signature);
+    }
+
+    checker
+  }
+
+  /// Resolves a type from a signature, or creates a variable to infer it
+  /// if it was omitted
+  fn inferred(&mut self, ta: &NaskoType) -> NaskoType {
+    match ta {
+      NaskoType::Unknown => self.unifier.fresh(VarKind::Any),
+      ta => self.resolve_type(ta).unwrap_or_default()
+    }
+  }
+
+  /// Resolves user-defined type names, failing with the first unknown name
+  fn resolve_type(&self, ta: &NaskoType) -> Result<NaskoType, String> {
+    match ta {
+      NaskoType::Named(name) if self.structs.contains_key(name) => Ok(NaskoType::Struct(name.clone())),
+      NaskoType::Named(name) if self.enums.contains_key(name) => Ok(NaskoType::Enum(name.clone())),
+      NaskoType::Named(name) => Err(name.clone()),
+      NaskoType::Array(element, len) => Ok(NaskoType::Array(Box::new(self.resolve_type(element)?), *len)),
+      ta => Ok(ta.clone())
+    }
+  }
+
+  /// Resolves a written type annotation in place
+  fn annotation(&mut self, ta: &mut NaskoType, span: Span) {
+    match self.resolve_type(ta) {
+      Ok(resolved) => *ta = resolved,
+      Err(name) => {
+        if self.complete {
+          self.diagnostics.push(
+            Diagnostic::error(format!("Cannot find type `{}` in this scope", name), span)
+              .with_label("not found in this scope".to_string())
+          );
+        }
+        *ta = NaskoType::Unknown;
+      }
+    }
+  }
+
+  pub fn check_struct(&mut self, decl: &mut StructDeclNode) {
+    let mut fields: Vec<(String, NaskoType)> = vec![];
+
+    for field in decl.fields.iter_mut() {
+      let name = match &field.value {
+        ExtraNodeData::String(name) => name.clone(),
+        _ => continue
+      };
+      self.annotation(&mut field.ta, field.span.clone());
+
+      if fields.iter().any(|(f, _)| *f == name) {
+        self.diagnostics.push(
+          Diagnostic::error(format!("Field `{}` is already declared", name), field.span.clone())
+            .with_label("field already declared".to_string())
+        );
+        continue;
+      }
+      fields.push((name, field.ta.clone()));
+    }
+
+    self.structs.insert(decl.name.clone(), fields);
+  }
+
+  /// Rejects a struct that holds itself other than through an array, once
+  /// every struct is known
+  pub fn check_struct_size(&mut self, decl: &StructDeclNode) {
+    for field in &decl.fields {
+      if let NaskoType::Struct(name) = &field.ta {
+        if self.holds(name, &decl.name, &mut HashSet::new()) {
+          self.diagnostics.push(
+            Diagnostic::error(format!("Recursive type `{}` has infinite size", decl.name), field.span.clone())
+              .with_label(format!("`{}` holds itself through this field", decl.name))
+              .with_note("Hold the value in an array instead".to_string())
+          );
+          return;
+        }
+      }
+    }
+  }
+
+  /// Whether values of struct `name` hold a `target` by value
+  fn holds(&self, name: &str, target: &str, visited: &mut HashSet<String>) -> bool {
+    if name == target {
+      return true;
+    }
+    if !visited.insert(name.to_string()) {
+      return false;
+    }
+    self.structs.get(name).is_some_and(|fields| fields.iter().any(|(_, ta)| match ta {
+      NaskoType::Struct(field) => self.holds(field, target, visited),
+      _ => false
+    }))
+  }
+
+  /// Checks the variants of an enum and leaves each one with its
+  /// discriminant as a constant child, for the backends
+  pub fn check_enum(&mut self, decl: &mut EnumDeclNode) {
+    let mut variants: Vec<(String, i64)> = vec![];
+    let mut next = Some(0);
+    // Only the first variant past the end is reported
+    let mut overflowed = false;
+
+    for variant in decl.variants.iter_mut() {
+      let name = match &variant.value {
+        ExtraNodeData::String(name) => name.clone(),
+        _ => continue
+      };
+
+      let discriminant = match variant.children.first() {
+        None => {
+          if next.is_none() && !overflowed {
+            overflowed = true;
+            self.diagnostics.push(
+              Diagnostic::error("Enum discriminant overflowed".to_string(), variant.span.clone())
+                .with_label(format!("overflowed on value after {}", i64::MAX))
+                .with_note(format!("Give `{}` an explicit discriminant", name))
+            );
+          }
+          next
+        },
+        Some(value) => match discriminant(value.as_ref()) {
+          Some(discriminant) => Some(discriminant),
+          None => {
+            self.diagnostics.push(
+              Diagnostic::error("Enum discriminants must be integer constants".to_string(), value.span())
+                .with_label("not an integer constant".to_string())
+            );
+            next
+          }
+        }
+      };
+      next = discriminant.and_then(|discriminant| discriminant.checked_add(1));
+
+      if variants.iter().any(|(v, _)| *v == name) {
+        self.diagnostics.push(
+          Diagnostic::error(format!("Variant `{}` is already declared", name), variant.span.clone())
+            .with_label("variant already declared".to_string())
+        );
+        continue;
+      }
+      let discriminant = match discriminant {
+        Some(discriminant) => discriminant,
+        None => {
+          variants.push((name, 0));
+          continue;
+        }
+      };
+      if let Some((other, _)) = variants.iter().find(|(_, d)| *d == discriminant) {
+        self.diagnostics.push(
+          Diagnostic::error(format!("Discriminant value `{}` assigned more than once", discriminant), variant.span.clone())
+            .with_label(format!("`{}` also has the value `{}`", other, discriminant))
+        );
+      }
+      let span = variant.children.first().map_or(variant.span.clone(), |value| value.span());
+      variant.children = vec![Box::new(ValueNode {
+        ta: NaskoType::Int(IntKind::I64),
+        ntype: "Constant".to_string(),
+        value: ExtraNodeData::Integer(discriminant as i128),
+        span,
+        children: vec![]
+      })];
+      variants.push((name, discriminant));
+    }
+
+    self.enums.insert(decl.name.clone(), variants);
+  }
+
+  pub fn check_function(&mut self, func: &mut FunctionDeclNode) {
+    let name = match &func.value {
+      ExtraNodeData::String(name) => name.clone(),
+      _ => String::new()
+    };
+    let signature = self.functions.get(&name).cloned();
+
+    // Omitted types take the variables created for the signature
+    match signature.as_ref().filter(|_| func.ta == NaskoType::Unknown) {
+      Some(signature) => func.ta = signature.ret.clone(),
+      None => self.annotation(&mut func.ta, func.span.clone())
+    }
+
+    for (i, param) in func.params.iter_mut().enumerate() {
+      match signature.as_ref().filter(|_| param.ta == NaskoType::Unknown) {
+        Some(signature) => param.ta = signature.params[i].clone(),
+        None => self.annotation(&mut param.ta, param.span.clone())
+      }
+      self.locals.insert(param.span.clone(), param.ta.clone());
+    }
+
+    self.function = Some((name.clone(), func.span.clone(), func.ta.clone()));
+    self.statements(&mut func.children);
+    self.function = None;
+
+    // Callers checked later can still decide an omitted return type
+    self.returns.push(Returns {
+      name,
+      span: func.span.clone(),
+      ta: func.ta.clone(),
+      value: returns_value(&func.children),
+      always: always_returns(&func.children),
+      malformed: malformed(&func.children)
+    });
+  }
+
+  /// Replaces every type variable in the tree by its inferred type
+  ///
+  /// Runs once every function has been checked. Variables nothing was
+  /// unified with are reported, so no `ta` is left unknown.
+  pub fn finish(&mut self, tree: &mut SourceNode) {
+    // Omitted return types of functions that never return a value are
+    // `void`, the others must be given one by the callers
+    for func in &self.returns {
+      if !func.value {
+        if let ret @ NaskoType::Var(_, VarKind::Any) = self.unifier.shallow(&func.ta) {
+          self.unifier.unify(&ret, &NaskoType::Void);
+        }
+      }
+    }
+    // Nothing is known about what a malformed function returns
+    for func in self.returns.iter().filter(|func| func.malformed) {
+      if let NaskoType::Var(id, VarKind::Any) = self.unifier.shallow(&func.ta) {
+        self.uninferred.push(id);
+      }
+    }
+    for func in &self.returns {
+      match self.unifier.shallow(&func.ta) {
+        NaskoType::Void | NaskoType::Unknown | NaskoType::Var(_, VarKind::Any) => {},
+        ret => if !func.always {
+          self.diagnostics.push(
+            Diagnostic::error(format!("Function `{}` does not return a value on every path", func.name), func.span.clone())
+              .with_label(format!("expected to return `{}`", self.unifier.resolve(&ret)))
+          );
+        }
+      }
+    }
+
+    for (constraint, ta, span) in std::mem::take(&mut self.deferred) {
+      // Variables that are still unbound are reported by `write_back`
+      if let Some(diagnostic) = self.unifier.finish(&ta).ok().and_then(|ta| constraint.violation(&ta, span)) {
+        self.diagnostics.push(diagnostic);
+      }
+    }
+
+    for leaf in tree.children.iter_mut() {
+      self.write_back(leaf.as_mut());
+    }
+  }
+
+  fn write_back(&mut self, node: &mut (dyn ASTNode + 'static)) {
+    match self.unifier.finish(node.ta()) {
+      Ok(ta) => *node.ta_mut() = ta,
+      Err(id) => {
+        if !self.uninferred.contains(&id) {
+          self.uninferred.push(id);
+          let label = if let Some(decl) = node.downcast_ref::<VariableDeclNode>() {
+            format!("cannot infer the type of `{}`", decl.name)
+          } else if let Some(call) = node.downcast_ref::<CallNode>() {
+            format!("cannot infer the return type of `{}`", call.name)
+          } else if let Some(ExtraNodeData::String(name)) = node.downcast_ref::<FunctionDeclNode>().map(|func| &func.value) {
+            format!("cannot infer the return type of `{}`", name)
+          } else if let Some(name) = node.downcast_ref::<NameNode>() {
+            format!("cannot infer the type of `{}`", name.name)
+          } else {
+            "cannot infer the type of this expression".to_string()
+          };
+          self.diagnostics.push(
+            Diagnostic::error("Type annotations needed".to_string(), node.span())
+              .with_label(label)
+          );
+        }
+        *node.ta_mut() = NaskoType::Unknown;
+      }
+    }
+
+    // A negated literal is checked as a whole, `-128` fits into an `i8`
+    // but `128` doesn't
+    if let Some(n) = integer_constant(node) {
+      let ta = node.ta().clone();
+      self.literal_range(n, &ta, node.span());
+      for literal in node.get_leaves_mut().iter_mut() {
+        *literal.ta_mut() = ta.clone();
+      }
+      return;
+    }
+
+    if let Some(binary) = node.downcast_mut::<BinaryExpression>() {
+      for operand in binary.lhs.iter_mut().chain(binary.rhs.iter_mut()) {
+        self.write_back(operand.as_mut());
+      }
+    } else if let Some(func) = node.downcast_mut::<FunctionDeclNode>() {
+      for param in func.params.iter_mut() {
+        self.write_back(param);
+      }
+    }
+
+    for leaf in node.get_leaves_mut().iter_mut() {
+      self.write_back(leaf.as_mut());
+    }
+  }
+
+  fn statements(&mut self, statements: &mut [Box<dyn ASTNode>]) {
+    for statement in statements {
+      self.statement(statement);
+    }
+  }
+
+  fn statement(&mut self, node: &mut Box<dyn ASTNode>) {
+    if let Some(decl) = node.downcast_mut::<VariableDeclNode>() {
+      self.variable_decl(decl);
+    } else if let Some(block) = node.downcast_mut::<BlockNode>() {
+      self.statements(&mut block.children);
+    } else if let Some(statement) = node.downcast_mut::<StatementNode>() {
+      match statement.ntype.as_str() {
+        "IfStatement" => {
+          let (condition, branches) = statement.children.split_at_mut(1);
+          self.condition(&mut condition[0], "if");
+          self.statements(branches);
+        },
+        "WhileStatement" => {
+          let (condition, body) = statement.children.split_at_mut(1);
+          self.condition(&mut condition[0], "while");
+          self.loops += 1;
+          self.statements(body);
+          self.loops -= 1;
+        },
+        "BreakStatement" | "ContinueStatement" => {
+          if self.loops == 0 {
+            let keyword = if statement.ntype == "BreakStatement" { "break" } else { "continue" };
+            self.diagnostics.push(
+              Diagnostic::error(format!("`{}` outside of a loop", keyword), statement.span.clone())
+                .with_label(format!("cannot `{}` outside of a loop", keyword))
+            );
+          }
+        },
+        "AssignmentStatement" => self.assignment(statement),
+        "ReturnStatement" => self.return_statement(statement),
+        _ => for child in statement.children.iter_mut() {
+          self.expression(child);
+        }
+      }
+    }
+  }
+
+  /// `return value;`, checked against the declared return type
+  fn return_statement(&mut self, statement: &mut StatementNode) {
+    let (name, span, expected) = match self.function.clone() {
+      Some(function) => function,
+      None => return
+    };
+
+    match statement.children.first_mut() {
+      Some(value) => {
+        let ta = self.expected_expression(value, &expected);
+        if !self.require(Constraint::Value, &ta, value.span()) {
+          return;
+        }
+        if !self.assignable(&ta, &expected) {
+          let diagnostic = self.mismatch(value.span(), &expected, &ta)
+            .with_secondary(span, format!("`{}` is declared to return `{}`", name, self.unifier.resolve(&expected)));
+          self.diagnostics.push(diagnostic);
+        }
+      },
+      None => if !self.unifier.unify(&expected, &NaskoType::Void) {
+        let expected = self.unifier.resolve(&expected);
+        self.diagnostics.push(
+          Diagnostic::error("Mismatched types".to_string(), statement.span.clone())
+            .with_label(format!("expected a value of type `{}`", expected))
+            .with_secondary(span, format!("`{}` is declared to return `{}`", name, expected))
+        );
+      }
+    }
+  }
+
+  /// `target = value;`
+  fn assignment(&mut self, statement: &mut StatementNode) {
+    let (target, value) = statement.children.split_at_mut(1);
+    let target_ta = self.expression(&mut target[0]);
+    let value_ta = self.expected_expression(&mut value[0], &target_ta);
+
+    if !is_place(target[0].as_ref()) {
+      self.diagnostics.push(
+        Diagnostic::error("Invalid left-hand side of assignment".to_string(), target[0].span())
+          .with_label("cannot assign to this expression".to_string())
+      );
+    } else if !self.assignable(&value_ta, &target_ta) {
+      let diagnostic = self.mismatch(value[0].span(), &target_ta, &value_ta);
+      self.diagnostics.push(diagnostic);
+    }
+  }
+
+  /// Checks that a condition is a boolean
+  fn condition(&mut self, node: &mut Box<dyn ASTNode>, keyword: &str) {
+    let ta = self.expression(node);
+    if !self.unifier.unify(&ta, &NaskoType::Boolean) {
+      let diagnostic = self.mismatch(node.span(), &NaskoType::Boolean, &ta)
+        .with_note(format!("`{}` conditions must be of type `boolean`", keyword));
+      self.diagnostics.push(diagnostic);
+    }
+  }
+
+  fn variable_decl(&mut self, decl: &mut VariableDeclNode) {
+    let initializer = match decl.children.first_mut() {
+      Some(initializer) => initializer,
+      None => return
+    };
+    self.annotation(&mut decl.ta, decl.span.clone());
+    let ta = self.expected_expression(initializer, &decl.ta);
+    let value = self.require(Constraint::Value, &ta, initializer.span());
+
+    if decl.ta == NaskoType::Unknown {
+      decl.ta = if value { ta } else { NaskoType::Unknown };
+    } else if value && !self.assignable(&ta, &decl.ta) {
+      let diagnostic = self.mismatch(initializer.span(), &decl.ta, &ta)
+        .with_secondary(decl.span.clone(), format!("`{}` is declared as `{}` here", decl.name, decl.ta));
+      self.diagnostics.push(diagnostic);
+    }
+
+    self.locals.insert(decl.span.clone(), decl.ta.clone());
+  }
+
+  /// Annotates an expression and returns its type
+  fn expression(&mut self, node: &mut Box<dyn ASTNode>) -> NaskoType {
+    if let Some(value) = node.downcast_mut::<ValueNode>() {
+      let ta = match value.ntype.as_str() {
+        // Number literals get their type from how they are used
+        "Constant" => match value.value {
+          ExtraNodeData::Integer(_) => self.unifier.fresh(VarKind::Integer),
+          ExtraNodeData::Float(_) => self.unifier.fresh(VarKind::Float),
+          _ => value.ta.clone()
+        },
+        // Left unresolved by name resolution, already reported
+        _ => NaskoType::Unknown
+      };
+      value.ta = ta.clone();
+      ta
+    } else if let Some(name) = node.downcast_mut::<NameNode>() {
+      name.ta = self.identifier(name);
+      name.ta.clone()
+    } else if let Some(binary) = node.downcast_mut::<BinaryExpression>() {
+      let lhs = binary.lhs.as_mut().map(|lhs| self.expression(lhs)).unwrap_or_default();
+      let rhs = binary.rhs.as_mut().map(|rhs| self.expression(rhs)).unwrap_or_default();
+
+      binary.ta = self.binary(binary, lhs, rhs);
+      binary.ta.clone()
+    } else if let Some(unary) = node.downcast_mut::<UnaryExpression>() {
+      let operand = match unary.children.first_mut() {
+        Some(operand) => self.expression(operand),
+        None => NaskoType::Unknown
+      };
+
+      unary.ta = self.unary(unary, operand);
+      unary.ta.clone()
+    } else if let Some(call) = node.downcast_mut::<CallNode>() {
+      self.call(call)
+    } else if let Some(literal) = node.downcast_mut::<ArrayLiteralNode>() {
+      self.array_literal(literal, None)
+    } else if let Some(index) = node.downcast_mut::<IndexNode>() {
+      self.index(index)
+    } else if let Some(cast) = node.downcast_mut::<CastNode>() {
+      self.cast(cast)
+    } else if let Some(literal) = node.downcast_mut::<StructLiteralNode>() {
+      self.struct_literal(literal)
+    } else if let Some(access) = node.downcast_mut::<FieldAccessNode>() {
+      if let Some(variant) = self.enum_variant(access) {
+        let ta = variant.ta.clone();
+        *node = variant;
+        return ta;
+      }
+      self.field_access(access)
+    } else {
+      NaskoType::Unknown
+    }
+  }
+
+  /// Checks the operand types of a binary expression, returning the type
+  /// of the result
+  ///
+  /// Both operands must have the same type. Arithmetic needs numbers,
+  /// bitwise operators integers and logical operators booleans, ordering
+  /// needs numbers and equality any scalar. Comparisons and logical
+  /// operators produce a `boolean`.
+  fn binary(&mut self, binary: &BinaryExpression, lhs: NaskoType, rhs: NaskoType) -> NaskoType {
+    let op = &binary.expression;
+    // Comparisons are booleans even if their operands are wrong
+    let result = |ta| if op.is_comparison() { NaskoType::Boolean } else { ta };
+
+    let mut values = true;
+    for (operand, ta) in [(&binary.lhs, &lhs), (&binary.rhs, &rhs)].iter() {
+      let span = operand.as_ref().map(|operand| operand.span()).unwrap_or_else(|| binary.span.clone());
+      values &= self.require(Constraint::Value, ta, span);
+    }
+    if !values {
+      return if op.is_logical() { NaskoType::Boolean } else { result(NaskoType::Unknown) };
+    }
+
+    if op.is_logical() {
+      for (operand, ta) in [(&binary.lhs, &lhs), (&binary.rhs, &rhs)].iter() {
+        if !self.unifier.unify(ta, &NaskoType::Boolean) {
+          let span = operand.as_ref().map(|operand| operand.span()).unwrap_or_else(|| binary.span.clone());
+          let diagnostic = self.mismatch(span, &NaskoType::Boolean, ta)
+            .with_note(format!("both operands of `{}` must be of type `boolean`", op));
+          self.diagnostics.push(diagnostic);
+        }
+      }
+      return NaskoType::Boolean;
+    }
+
+    if !self.unifier.unify(&lhs, &rhs) {
+      let rhs_span = binary.rhs.as_ref().map(|rhs| rhs.span()).unwrap_or_else(|| binary.span.clone());
+      let diagnostic = self.mismatch(rhs_span, &lhs, &rhs)
+        .with_note(format!("both operands of `{}` must have the same type", op));
+      self.diagnostics.push(diagnostic);
+      return result(NaskoType::Unknown);
+    }
+
+    if !self.require(Constraint::Binary(op.clone()), &lhs, binary.span.clone()) {
+      return result(NaskoType::Unknown);
+    }
+
+    result(lhs)
+  }
+
+  /// Checks the operand type of a prefix expression, returning the type of
+  /// the result
+  fn unary(&mut self, unary: &UnaryExpression, operand: NaskoType) -> NaskoType {
+    if let Some(child) = unary.children.first() {
+      if !self.require(Constraint::Value, &operand, child.span()) {
+        return if unary.expression == NaskoUnary::Not { NaskoType::Boolean } else { NaskoType::Unknown };
+      }
+    }
+
+    match unary.expression {
+      NaskoUnary::Not => {
+        if !self.unifier.unify(&operand, &NaskoType::Boolean) {
+          let diagnostic = self.mismatch(unary.children[0].span(), &NaskoType::Boolean, &operand)
+            .with_note("the operand of `!` must be of type `boolean`".to_string());
+          self.diagnostics.push(diagnostic);
+        }
+        NaskoType::Boolean
+      },
+      NaskoUnary::Negate | NaskoUnary::BitNot => {
+        if !self.require(Constraint::Unary(unary.expression.clone()), &operand, unary.span.clone()) {
+          return NaskoType::Unknown;
+        }
+        operand
+      }
+    }
+  }
+
+  /// Checks an operand type against a constraint, returning whether it
+  /// holds
+  ///
+  /// Types that are not inferred yet are checked again by `finish`, once
+  /// later uses have decided them.
+  fn require(&mut self, constraint: Constraint, ta: &NaskoType, span: Span) -> bool {
+    let ta = self.unifier.resolve(ta);
+    if ta.has_vars() {
+      self.deferred.push((constraint, ta, span));
+      return true;
+    }
+
+    match constraint.violation(&ta, span) {
+      Some(diagnostic) => {
+        self.diagnostics.push(diagnostic);
+        false
+      },
+      None => true
+    }
+  }
+
+  /// A "Mismatched types" error, showing the types as inferred so far
+  fn mismatch(&self, span: Span, expected: &NaskoType, found: &NaskoType) -> Diagnostic {
+    Diagnostic::error("Mismatched types".to_string(), span)
+      .with_label(format!("expected `{}`, found `{}`", self.unifier.resolve(expected), self.unifier.resolve(found)))
+  }
+
+  /// Whether a value of type `found` can be stored where `expected` is
+  /// wanted, unifying the two
+  ///
+  /// Arrays of a known length also fit where any length is accepted
+  fn assignable(&mut self, found: &NaskoType, expected: &NaskoType) -> bool {
+    match (self.unifier.shallow(found), self.unifier.shallow(expected)) {
+      (NaskoType::Array(found, Some(_)), NaskoType::Array(expected, None)) => self.unifier.unify(&found, &expected),
+      _ => self.unifier.unify(found, expected)
+    }
+  }
+
+  /// `name(arg, ...)`, checked against the function's signature
+  fn call(&mut self, call: &mut CallNode) -> NaskoType {
+    let signature = match &call.binding {
+      Some(Binding::Function) => self.functions.get(&call.name).cloned(),
+      Some(Binding::Local(declaration)) => {
+        let ta = self.locals.get(declaration).map(|ta| self.unifier.resolve(ta)).unwrap_or_default();
+        self.diagnostics.push(
+          Diagnostic::error(format!("Expected function, found `{}`", call.name), call.name_span.clone())
+            .with_label(format!("`{}` is a value of type `{}`", call.name, ta))
+        );
+        None
+      },
+      Some(Binding::Type) => {
+        self.diagnostics.push(
+          Diagnostic::error(format!("Expected function, found type `{}`", call.name), call.name_span.clone())
+            .with_label("not a function".to_string())
+        );
+        None
+      },
+      // Already reported by name resolution
+      None => None
+    };
+    let signature = match signature {
+      Some(signature) => signature,
+      None => {
+        for arg in call.children.iter_mut() {
+          self.expression(arg);
+        }
+        return NaskoType::Unknown;
+      }
+    };
+
+    if signature.params.len() != call.children.len() {
+      let plural = |n: usize| if n == 1 { "" } else { "s" };
+      self.diagnostics.push(
+        Diagnostic::error(
+          format!(
+            "This function takes {} argument{} but {} argument{} supplied",
+            signature.params.len(),
+            plural(signature.params.len()),
+            call.children.len(),
+            if call.children.len() == 1 { " was" } else { "s were" }
+          ),
+          call.span.clone()
+        ).with_label(format!("expected {} argument{}", signature.params.len(), plural(signature.params.len())))
+        .with_note(format!("`{}` is declared as `{}`", call.name, signature.display(&call.name, &self.unifier)))
+      );
+    }
+
+    for (i, arg) in call.children.iter_mut().enumerate() {
+      match signature.params.get(i) {
+        Some(expected) => {
+          let ta = self.expected_expression(arg, expected);
+          if self.require(Constraint::Value, &ta, arg.span()) && !self.assignable(&ta, expected) {
+            let diagnostic = self.mismatch(arg.span(), expected, &ta);
+            self.diagnostics.push(diagnostic);
+          }
+        },
+        None => {
+          self.expression(arg);
+        }
+      }
+    }
+
+    signature.ret
+  }
+
+  /// Annotates an expression that should be of type `expected`
+  ///
+  /// The expected type only guides array literals, the caller still checks
+  /// the result against it.
+  fn expected_expression(&mut self, node: &mut Box<dyn ASTNode>, expected: &NaskoType) -> NaskoType {
+    if let Some(literal) = node.downcast_mut::<ArrayLiteralNode>() {
+      return self.array_literal(literal, Some(expected));
+    }
+
+    self.expression(node)
+  }
+
+  /// Reports integer literals that do not fit their type
+  fn literal_range(&mut self, n: i128, ta: &NaskoType, span: Span) {
+    if let NaskoType::Int(kind) = ta {
+      if !kind.contains(n) {
+        self.diagnostics.push(
+          Diagnostic::error(format!("Literal out of range for `{}`", kind), span)
+            .with_label(format!("`{}` does not fit into the range `{}..={}`", n, kind.min(), kind.max()))
+        );
+      }
+    }
+  }
+
+  /// Annotates an array literal, whose elements all share one type
+  ///
+  /// An expected array type gives the elements their type, otherwise it is
+  /// inferred from the elements
+  fn array_literal(&mut self, literal: &mut ArrayLiteralNode, expected: Option<&NaskoType>) -> NaskoType {
+    let element = match expected.map(|expected| self.unifier.shallow(expected)) {
+      Some(NaskoType::Array(element, _)) => *element,
+      _ => self.unifier.fresh(VarKind::Any)
+    };
+
+    for child in literal.children.iter_mut() {
+      let ta = self.expected_expression(child, &element);
+      if self.require(Constraint::Value, &ta, child.span()) && !self.assignable(&ta, &element) {
+        let diagnostic = self.mismatch(child.span(), &element, &ta);
+        self.diagnostics.push(diagnostic);
+      }
+    }
+
+    literal.ta = NaskoType::Array(Box::new(element), Some(literal.children.len()));
+    literal.ta.clone()
+  }
+
+  /// `array[index]`
+  fn index(&mut self, node: &mut IndexNode) -> NaskoType {
+    let (array, index) = node.children.split_at_mut(1);
+    let array_ta = self.expression(&mut array[0]);
+    let index_ta = self.expression(&mut index[0]);
+
+    let integer = self.unifier.fresh(VarKind::Integer);
+    if !self.unifier.unify(&index_ta, &integer) {
+      self.diagnostics.push(
+        Diagnostic::error("Array indices must be integers".to_string(), index[0].span())
+          .with_label(format!("expected an integer, found `{}`", self.unifier.resolve(&index_ta)))
+      );
+    }
+
+    match self.unifier.shallow(&array_ta) {
+      NaskoType::Array(element, len) => {
+        let constant = index[0].downcast_ref::<ValueNode>()
+          .filter(|value| value.ntype == "Constant")
+          .and_then(|value| match value.value {
+            ExtraNodeData::Integer(n) => Some(n),
+            _ => None
+          });
+        if let (Some(len), Some(constant)) = (len, constant) {
+          if constant < 0 || constant >= len as i128 {
+            self.diagnostics.push(
+              Diagnostic::error("Index out of bounds".to_string(), node.span.clone())
+                .with_label(format!("the length is {} but the index is {}", len, constant))
+            );
+          }
+        }
+        node.ta = *element;
+      },
+      NaskoType::Unknown => {},
+      NaskoType::Var(_, VarKind::Any) => self.diagnostics.push(type_needed(array[0].span())),
+      ta => self.diagnostics.push(
+        Diagnostic::error(format!("Cannot index into a value of type `{}`", self.unifier.finish(&ta).unwrap_or(ta)), array[0].span())
+          .with_label("not an array".to_string())
+      )
+    }
+
+    node.ta.clone()
+  }
+
+  fn cast(&mut self, cast: &mut CastNode) -> NaskoType {
+    let from = match cast.children.first_mut() {
+      Some(value) => self.expression(value),
+      None => return NaskoType::Unknown
+    };
+    let span = cast.span.clone();
+    self.annotation(&mut cast.ta, span.clone());
+
+    // A literal cast to another type keeps its default type
+    if self.require(Constraint::Value, &from, cast.children[0].span()) {
+      self.require(Constraint::Cast(cast.ta.clone()), &from, span);
+    }
+
+    cast.ta.clone()
+  }
+
+  fn struct_literal(&mut self, literal: &mut StructLiteralNode) -> NaskoType {
+    let fields = match self.structs.get(&literal.name) {
+      Some(fields) => fields.clone(),
+      None => {
+        if self.complete {
+          self.diagnostics.push(
+            Diagnostic::error(format!("Cannot find struct `{}` in this scope", literal.name), literal.span.clone())
+              .with_label("not found in this scope".to_string())
+          );
+        }
+        for init in literal.children.iter_mut() {
+          self.expression(init);
+        }
+        return NaskoType::Unknown;
+      }
+    };
+
+    let mut initialized: Vec<String> = vec![];
+    for init in literal.children.iter_mut() {
+      let init = match init.downcast_mut::<ValueNode>() {
+        Some(init) => init,
+        None => continue
+      };
+      let name = match &init.value {
+        ExtraNodeData::String(name) => name.clone(),
+        _ => continue
+      };
+      let expected = fields.iter()
+        .find(|(field, _)| *field == name)
+        .map(|(_, ta)| ta.clone())
+        .unwrap_or_default();
+      let ta = match init.children.first_mut() {
+        Some(value) => self.expected_expression(value, &expected),
+        None => NaskoType::Unknown
+      };
+
+      match fields.iter().find(|(field, _)| *field == name) {
+        None => self.diagnostics.push(
+          Diagnostic::error(format!("Struct `{}` has no field named `{}`", literal.name, name), init.span.clone())
+            .with_label("unknown field".to_string())
+        ),
+        Some(_) if initialized.contains(&name) => self.diagnostics.push(
+          Diagnostic::error(format!("Field `{}` specified more than once", name), init.span.clone())
+            .with_label("used more than once".to_string())
+        ),
+        Some((_, expected)) => {
+          if !self.assignable(&ta, expected) {
+            let diagnostic = self.mismatch(init.children[0].span(), expected, &ta);
+            self.diagnostics.push(diagnostic);
+          }
+          init.ta = expected.clone();
+          initialized.push(name);
+        }
+      }
+    }
+
+    let missing: Vec<String> = fields.iter()
+      .filter(|(field, _)| !initialized.contains(field))
+      .map(|(field, _)| format!("`{}`", field))
+      .collect();
+    if !missing.is_empty() {
+      self.diagnostics.push(
+        Diagnostic::error(format!("Missing fields {} in initializer of `{}`", missing.join(", "), literal.name), literal.span.clone())
+          .with_label("missing fields".to_string())
+      );
+    }
+
+    literal.ta = NaskoType::Struct(literal.name.clone());
+    literal.ta.clone()
+  }
+
+  fn field_access(&mut self, access: &mut FieldAccessNode) -> NaskoType {
+    let object = match access.children.first_mut() {
+      Some(object) => self.expression(object),
+      None => return NaskoType::Unknown
+    };
+
+    let object = match self.unifier.shallow(&object) {
+      NaskoType::Var(_, VarKind::Any) => {
+        self.diagnostics.push(type_needed(access.children[0].span()));
+        return NaskoType::Unknown;
+      },
+      // A number has no fields whatever its type turns out to be
+      number @ NaskoType::Var(..) => self.unifier.finish(&number).unwrap_or_default(),
+      object => self.unifier.resolve(&object)
+    };
+    let field = match &object {
+      NaskoType::Unknown => None,
+      NaskoType::Struct(name) => self.structs.get(name)
+        .and_then(|fields| fields.iter().find(|(field, _)| *field == access.field))
+        .map(|(_, ta)| ta.clone()),
+      _ => None
+    };
+
+    match field {
+      Some(ta) => access.ta = ta,
+      None if object != NaskoType::Unknown => self.diagnostics.push(
+        Diagnostic::error(format!("No field `{}` on type `{}`", access.field, object), access.span.clone())
+          .with_label("unknown field".to_string())
+      ),
+      None => {}
+    }
+
+    access.ta.clone()
+  }
+
+  /// Resolves `Enum.Variant`, which is parsed as a field access on the enum
+  /// name, into an `EnumVariant` constant holding the discriminant
+  fn enum_variant(&mut self, access: &FieldAccessNode) -> Option<Box<ValueNode>> {
+    let object = access.children.first()?.downcast_ref::<NameNode>()?;
+    if object.binding != Binding::Type {
+      return None;
+    }
+    let name = &object.name;
+    let variants = self.enums.get(name)?;
+
+    let value = match variants.iter().find(|(variant, _)| *variant == access.field) {
+      Some((_, discriminant)) => ExtraNodeData::Integer(*discriminant as i128),
+      None => {
+        self.diagnostics.push(
+          Diagnostic::error(format!("No variant named `{}` found for enum `{}`", access.field, name), access.span.clone())
+            .with_label("variant not found".to_string())
+        );
+        ExtraNodeData::None
+      }
+    };
+
+    Some(Box::new(ValueNode {
+      ta: NaskoType::Enum(name.clone()),
+      ntype: "EnumVariant".to_string(),
+      value,
+      span: access.span.clone(),
+      children: vec![]
+    }))
+  }
+
+  fn identifier(&mut self, name: &NameNode) -> NaskoType {
+    let kind = match &name.binding {
+      Binding::Local(declaration) => return self.locals.get(declaration).cloned().unwrap_or_default(),
+      Binding::Function => "function",
+      Binding::Type => "type"
+    };
+
+    self.diagnostics.push(
+      Diagnostic::error(format!("Expected value, found {} `{}`", kind, name.name), name.span.clone())
+        .with_label("not a value".to_string())
+    );
+    NaskoType::Unknown
+  }
+}
+
+/// The conversions allowed with `as`
+///
+/// Anything can be cast to its own type. Scalars convert to `string` by
+/// formatting, numbers convert between each other (truncating or wrapping
+/// as needed), integers and `boolean` convert into each other (`0` is
+/// `false`) and enums convert to their integer discriminant.
+fn can_cast(from: &NaskoType, to: &NaskoType) -> bool {
+  if from == to {
+    return true;
+  }
+
+  matches!(
+    (from, to),
+    (NaskoType::Int(_), NaskoType::Int(_))
+      | (NaskoType::Int(_), NaskoType::Float(_))
+      | (NaskoType::Float(_), NaskoType::Int(_))
+      | (NaskoType::Float(_), NaskoType::Float(_))
+      | (NaskoType::Int(_), NaskoType::Boolean)
+      | (NaskoType::Boolean, NaskoType::Int(_))
+      | (NaskoType::Int(_), NaskoType::String)
+      | (NaskoType::Float(_), NaskoType::String)
+      | (NaskoType::Boolean, NaskoType::String)
+      | (NaskoType::Enum(_), NaskoType::Int(_))
+  )
+}
+
+/// The error for a value whose type has to be known but is not inferred yet
+fn type_needed(span: Span) -> Diagnostic {
+  Diagnostic::error("Type annotations needed".to_string(), span)
+    .with_label("the type of this value must be known at this point".to_string())
+}
+
+/// Whether a list of statements returns on every path
+fn always_returns(statements: &[Box<dyn ASTNode>]) -> bool {
+  statements.iter().any(|statement| returns(statement.as_ref()))
+}
+
+/// A statement that failed to parse counts as returning, whatever it was
+fn returns(node: &(dyn ASTNode + 'static)) -> bool {
+  if node.is::<ErrorNode>() {
+    return true;
+  }
+  if let Some(block) = node.downcast_ref::<BlockNode>() {
+    return always_returns(&block.children);
+  }
+
+  match node.downcast_ref::<StatementNode>() {
+    Some(statement) => match statement.ntype.as_str() {
+      "ReturnStatement" => true,
+      "IfStatement" => statement.children.len() == 3
+        && returns(statement.children[1].as_ref())
+        && returns(statement.children[2].as_ref()),
+      // `while true` without a `break` never finishes
+      "WhileStatement" => {
+        let infinite = statement.children[0].downcast_ref::<ValueNode>()
+          .map(|value| value.ntype == "Constant" && matches!(value.value, ExtraNodeData::Boolean(true)))
+          .unwrap_or(false);
+        infinite && !breaks(&statement.children[1..])
+      },
+      _ => false
+    },
+    None => false
+  }
+}
+
+/// Whether a `return` in the statements has a value, or a statement failed
+/// to parse
+fn returns_value(statements: &[Box<dyn ASTNode>]) -> bool {
+  statements.iter().any(|node| {
+    if node.is::<ErrorNode>() {
+      return true;
+    }
+    if let Some(block) = node.downcast_ref::<BlockNode>() {
+      return returns_value(&block.children);
+    }
+    match node.downcast_ref::<StatementNode>() {
+      Some(statement) => match statement.ntype.as_str() {
+        "ReturnStatement" => !statement.children.is_empty(),
+        "IfStatement" | "WhileStatement" => returns_value(&statement.children[1..]),
+        _ => false
+      },
+      None => false
+    }
+  })
+}
+
+/// Whether a statement failed to parse
+fn malformed(statements: &[Box<dyn ASTNode>]) -> bool {
+  statements.iter().any(|node| {
+    if let Some(block) = node.downcast_ref::<BlockNode>() {
+      return malformed(&block.children);
+    }
+    match node.downcast_ref::<StatementNode>() {
+      Some(statement) => match statement.ntype.as_str() {
+        "IfStatement" | "WhileStatement" => malformed(&statement.children[1..]),
+        _ => false
+      },
+      None => node.is::<ErrorNode>()
+    }
+  })
+}
+
+/// Whether a `break` in the statements leaves the loop around them
+fn breaks(statements: &[Box<dyn ASTNode>]) -> bool {
+  statements.iter().any(|node| {
+    if let Some(block) = node.downcast_ref::<BlockNode>() {
+      return breaks(&block.children);
+    }
+    match node.downcast_ref::<StatementNode>() {
+      Some(statement) => match statement.ntype.as_str() {
+        "BreakStatement" => true,
+        "IfStatement" => breaks(&statement.children[1..]),
+        _ => false
+      },
+      None => false
+    }
+  })
+}
+
+/// Whether an expression refers to a storage location that can be assigned
+fn is_place(node: &(dyn ASTNode + 'static)) -> bool {
+  if let Some(name) = node.downcast_ref::<NameNode>() {
+    return matches!(name.binding, Binding::Local(_));
+  }
+  if let Some(access) = node.downcast_ref::<FieldAccessNode>() {
+    return access.children.first().map(|object| is_place(object.as_ref())).unwrap_or(false);
+  }
+  if let Some(index) = node.downcast_ref::<IndexNode>() {
+    return index.children.first().map(|array| is_place(array.as_ref())).unwrap_or(false);
+  }
+  false
+}
+
+/// The value of an explicit enum discriminant, if it is an integer constant
+pub(crate) fn discriminant(node: &(dyn ASTNode + 'static)) -> Option<i64> {
+  integer_constant(node).and_then(|n| i64::try_from(n).ok())
+}
+
+/// The value of an integer literal, or of a negated one
+fn integer_constant(node: &(dyn ASTNode + 'static)) -> Option<i128> {
+  if let Some(unary) = node.downcast_ref::<UnaryExpression>() {
+    let literal = unary.children.first()?.downcast_ref::<ValueNode>()?;
+    return match (&unary.expression, &literal.value, literal.ntype.as_str()) {
+      (NaskoUnary::Negate, ExtraNodeData::Integer(n), "Constant") => Some(-n),
+      _ => None
+    };
+  }
+
+  let value = node.downcast_ref::<ValueNode>()?;
+  match (&value.value, value.ntype.as_str()) {
+    (ExtraNodeData::Integer(n), "Constant") => Some(*n),
+    _ => None
+  }
+}
@@ -0,0 +1,59 @@
+//! Lookup of function declarations by name
+
+use std::collections::HashMap;
+use crate::ast::*;
+use crate::diagnostic::Diagnostic;
+
+/// Where each function of a program is declared
+pub struct FunctionTable {
+  /// Index of every function among the top level items, by name
+  functions: HashMap<String, usize>
+}
+
+impl FunctionTable {
+  /// Collects the functions of a tree, reporting names declared twice
+  ///
+  /// The first declaration of a name is the one calls resolve to
+  pub fn new(tree: &SourceNode, diagnostics: &mut Vec<Diagnostic>) -> FunctionTable {
+    let mut functions: HashMap<String, usize> = HashMap::new();
+
+    for (index, leaf) in tree.children.iter().enumerate() {
+      let func = match leaf.downcast_ref::<FunctionDeclNode>() {
+        Some(func) => func,
+        None => continue
+      };
+      let name = match &func.value {
+        ExtraNodeData::String(name) => name,
+        _ => continue
+      };
+
+      match functions.get(name) {
+        Some(&previous) => diagnostics.push(
+          Diagnostic::error(format!("The function `{}` is defined multiple times", name), func.span.clone())
+            .with_label(format!("`{}` redefined here", name))
+            .with_secondary(tree.children[previous].span(), format!("previous definition of `{}` here", name))
+        ),
+        None => {
+          functions.insert(name.clone(), index);
+        }
+      }
+    }
+
+    FunctionTable { functions }
+  }
+
+  pub fn get<'a>(&self, name: &str, tree: &'a SourceNode) -> Option<&'a FunctionDeclNode> {
+    let index = *self.functions.get(name)?;
+    tree.children[index].downcast_ref::<FunctionDeclNode>()
+  }
+
+  pub fn get_mut<'a>(&self, name: &str, tree: &'a mut SourceNode) -> Option<&'a mut FunctionDeclNode> {
+    let index = *self.functions.get(name)?;
+    tree.children[index].downcast_mut::<FunctionDeclNode>()
+  }
+
+  /// Every function, in no particular order
+  pub fn iter<'a>(&'a self, tree: &'a SourceNode) -> impl Iterator<Item = (&'a str, &'a FunctionDeclNode)> {
+    self.functions.keys().filter_map(move |name| Some((name.as_str(), self.get(name, tree)?)))
+  }
+}
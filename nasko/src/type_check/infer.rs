@@ -0,0 +1,106 @@
+//! Unification of type variables for local type inference
+//!
+//! Unannotated `let` bindings and parameters, omitted return types and
+//! number literals start out as type variables. Checking an expression
+//! unifies the types it relates, and once every function is checked the
+//! variables are replaced by what they were bound to.
+
+use crate::semantics::*;
+
+#[derive(Default)]
+pub struct Unifier {
+  /// What each variable is bound to, by variable id
+  bindings: Vec<Option<NaskoType>>
+}
+
+impl Unifier {
+  /// Creates a new unbound variable
+  pub fn fresh(&mut self, kind: VarKind) -> NaskoType {
+    self.bindings.push(None);
+    NaskoType::Var(self.bindings.len() - 1, kind)
+  }
+
+  /// Follows the bindings of a variable until a type or an unbound
+  /// variable is reached
+  pub fn shallow(&self, ta: &NaskoType) -> NaskoType {
+    let mut ta = ta.clone();
+    while let NaskoType::Var(id, _) = ta {
+      match &self.bindings[id] {
+        Some(bound) => ta = bound.clone(),
+        None => break
+      }
+    }
+    ta
+  }
+
+  /// Replaces every bound variable in a type
+  pub fn resolve(&self, ta: &NaskoType) -> NaskoType {
+    match self.shallow(ta) {
+      NaskoType::Array(element, len) => NaskoType::Array(Box::new(self.resolve(&element)), len),
+      ta => ta
+    }
+  }
+
+  /// Like `resolve`, but number literals nothing else constrained get their
+  /// default type, `i32` or `double`
+  ///
+  /// Fails with the id of the first variable that is still unbound.
+  pub fn finish(&self, ta: &NaskoType) -> Result<NaskoType, usize> {
+    match self.shallow(ta) {
+      NaskoType::Var(_, VarKind::Integer) => Ok(NaskoType::INT),
+      NaskoType::Var(_, VarKind::Float) => Ok(NaskoType::Float(FloatKind::Double)),
+      NaskoType::Var(id, VarKind::Any) => Err(id),
+      NaskoType::Array(element, len) => Ok(NaskoType::Array(Box::new(self.finish(&element)?), len)),
+      ta => Ok(ta)
+    }
+  }
+
+  /// Makes two types equal by binding variables, returning whether that
+  /// is possible
+  ///
+  /// `Unknown` unifies with everything, it stands for a type that has
+  /// already been reported as wrong.
+  pub fn unify(&mut self, a: &NaskoType, b: &NaskoType) -> bool {
+    let (a, b) = (self.shallow(a), self.shallow(b));
+
+    match (&a, &b) {
+      (NaskoType::Unknown, _) | (_, NaskoType::Unknown) => true,
+      (NaskoType::Var(x, _), NaskoType::Var(y, _)) if x == y => true,
+      (NaskoType::Var(x, kind), other) | (other, NaskoType::Var(x, kind)) => {
+        // Bind the less constrained variable to the other one
+        if let NaskoType::Var(y, other_kind) = other {
+          return match (kind, other_kind) {
+            (VarKind::Any, _) => self.bind(*x, other.clone()),
+            (_, VarKind::Any) => self.bind(*y, NaskoType::Var(*x, *kind)),
+            (kind, other_kind) if kind == other_kind => self.bind(*x, other.clone()),
+            _ => false
+          };
+        }
+
+        let fits = match kind {
+          VarKind::Any => !self.occurs(*x, other),
+          VarKind::Integer => matches!(other, NaskoType::Int(_)),
+          VarKind::Float => matches!(other, NaskoType::Float(_))
+        };
+        fits && self.bind(*x, other.clone())
+      },
+      (NaskoType::Array(a, a_len), NaskoType::Array(b, b_len)) => a_len == b_len && self.unify(a, b),
+      _ => a == b
+    }
+  }
+
+  fn bind(&mut self, id: usize, ta: NaskoType) -> bool {
+    self.bindings[id] = Some(ta);
+    true
+  }
+
+  /// Whether a variable appears in a type, binding it then would make the
+  /// type infinite
+  fn occurs(&self, id: usize, ta: &NaskoType) -> bool {
+    match self.shallow(ta) {
+      NaskoType::Var(other, _) => other == id,
+      NaskoType::Array(element, _) => self.occurs(id, &element),
+      _ => false
+    }
+  }
+}
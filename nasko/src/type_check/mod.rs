@@ -1,57 +1,89 @@
-mod search;
+mod checker;
+mod functions;
+mod infer;
 
 use crate::ast::*;
 use crate::semantics::*;
-use crate::macros::compile_error;
+use crate::diagnostic::Diagnostic;
+use crate::resolve::resolve_names;
 
-use self::search::func_with_name;
+use self::checker::Checker;
+pub(crate) use self::checker::discriminant;
+use self::functions::FunctionTable;
+
+/// Resolves names and checks types, returning every error and warning
+///
+/// The tree is only fully annotated if none of them is an error.
+pub fn annotate_types(tree: &mut SourceNode) -> Vec<Diagnostic> {
+  let mut diagnostics = resolve_names(tree);
+  let functions = FunctionTable::new(tree, &mut diagnostics);
+  let main_function = functions.get_mut("main", tree);
 
-pub fn annotate_types(tree: &mut SourceNode) {
-  let main_function = func_with_name("main", tree.children.clone());
-  
   match main_function {
-    Some(mut main_function) => {
-      if main_function.ta != NaskoType::Number {
-        compile_error(format!("`main` function must be annotated with the return value: int"))
+    Some(main_function) => {
+      if main_function.ta != NaskoType::INT {
+        diagnostics.push(
+          Diagnostic::error("`main` function must be annotated with the return value: int".to_string(), main_function.span.clone())
+            .with_label(format!("found return type `{}`", main_function.ta))
+        );
       }
 
       match main_function.params.len() {
         0 => {}, // Ignore if there aren't any arg params
-        1 | 2 => check_main_args(&mut main_function.params),
-        _ => compile_error(format!("Only 2 arguments allowed in the main function: argc (int), and argv (string[])"))
+        1 | 2 => check_main_args(&mut main_function.params, &mut diagnostics),
+        _ => diagnostics.push(
+          Diagnostic::error("Too many arguments for the `main` function".to_string(), main_function.params[2].span.clone())
+            .with_note("Only 2 arguments allowed in the main function: argc (int), and argv (string[])".to_string())
+        )
       }
     }
-    None => {
-      compile_error(format!("Missing `main` function from source. Please implement a main function"));
+    None => if tree.is_complete() {
+      diagnostics.push(
+        Diagnostic::error("Missing `main` function from source".to_string(), tree.span.start..tree.span.start)
+          .with_note("Please implement a main function".to_string())
+      );
     }
   }
-}
-
-fn check_main_args(params: &mut Vec<Box<ValueNode>>) {
-  println!("checking params: {:?}", params);
-  let double = params.len() == 2;
-  let mut err = String::from("");
 
-  if params[0].ta != NaskoType::Number && params[0].ta != NaskoType::Unknown {
-    err.push_str(&format!("Invalid type for main argument ({:?})\nExpected type {:?} instead found type: {:?}\n", params[0].value, NaskoType::Number, params[0].ta));
+  let mut checker = Checker::new(tree, &functions, &mut diagnostics);
+  for leaf in tree.children.iter_mut() {
+    if let Some(decl) = leaf.downcast_mut::<StructDeclNode>() {
+      checker.check_struct(decl);
+    } else if let Some(decl) = leaf.downcast_mut::<EnumDeclNode>() {
+      checker.check_enum(decl);
+    }
   }
-  if double {
-    if params[1].ta != NaskoType::String && params[1].ta != NaskoType::Unknown {
-      err.push_str(&format!("Invalid type for main argument ({:?})\nExpected type {:?} instead found type: {:?}", params[1].value, NaskoType::String, params[1].ta));
+  for leaf in tree.children.iter() {
+    if let Some(decl) = leaf.downcast_ref::<StructDeclNode>() {
+      checker.check_struct_size(decl);
     }
   }
-
-  if err != "".to_string() {
-    compile_error(err);
+  for leaf in tree.children.iter_mut() {
+    if let Some(func) = leaf.downcast_mut::<FunctionDeclNode>() {
+      checker.check_function(func);
+    }
   }
+  checker.finish(tree);
 
-  // Annotate argument types if unknown
-  if params[0].ta == NaskoType::Unknown {
-    params[0].ta = NaskoType::Number;
-  }
-  if double {
-    if params[1].ta == NaskoType::Unknown {
-      params[1].ta = NaskoType::String;
+  // Inference errors are only found at the end
+  diagnostics.sort_by_key(|d| d.primary.span.start);
+  diagnostics
+}
+
+fn check_main_args(params: &mut [ValueNode], diagnostics: &mut Vec<Diagnostic>) {
+  let expected = [NaskoType::INT, NaskoType::Array(Box::new(NaskoType::String), None)];
+
+  for (param, expected) in params.iter_mut().zip(expected.iter()) {
+    if param.ta != *expected && param.ta != NaskoType::Unknown {
+      diagnostics.push(
+        Diagnostic::error(format!("Invalid type for main argument `{:?}`", param.value), param.span.clone())
+          .with_label(format!("expected type `{}` instead found type: `{}`", expected, param.ta))
+      );
+    }
+
+    // Annotate argument types if unknown
+    if param.ta == NaskoType::Unknown {
+      param.ta = expected.clone();
     }
   }
 }
@@ -0,0 +1,110 @@
+//! Runs the programs in `tests/programs` with every backend, which must
+//! agree on the exit status and output
+
+mod common;
+
+use common::*;
+use std::process::Command;
+
+/// Compiles a program once for each backend that builds an executable,
+/// returning the executables
+fn build(name: &str) -> Vec<(&'static str, String)> {
+  let input = program(name);
+  let input = input.to_str().unwrap();
+
+  let exe = scratch(&format!("{}-exe", name));
+  let output = naskoc(&[input, "-o", exe.to_str().unwrap()]);
+  assert_eq!(output.code, Some(0), "--emit=exe failed for {}:\n{}", name, output.stderr);
+
+  let c = scratch(&format!("{}.c", name));
+  let output = naskoc(&["--emit=c", input, "-o", c.to_str().unwrap()]);
+  assert_eq!(output.code, Some(0), "--emit=c failed for {}:\n{}", name, output.stderr);
+  let c_exe = scratch(&format!("{}-c", name));
+  let cc = Command::new("cc")
+    .args(["-std=c99", c.to_str().unwrap(), "-o", c_exe.to_str().unwrap(), "-lm"])
+    .output()
+    .expect("failed to run cc");
+  assert!(cc.status.success(), "cc failed for {}:\n{}", name, String::from_utf8_lossy(&cc.stderr));
+
+  vec![
+    ("--emit=exe", exe.to_str().unwrap().to_string()),
+    ("--emit=c", c_exe.to_str().unwrap().to_string())
+  ]
+}
+
+/// Checks that every backend exits with `code` for each list of arguments
+fn agree(name: &str, runs: &[(&[&str], i32)]) {
+  let input = program(name);
+  let input = input.to_str().unwrap();
+  let executables = build(name);
+
+  for (args, code) in runs {
+    let mut results = vec![];
+
+    let mut run_args = vec!["run", input];
+    run_args.extend_from_slice(args);
+    results.push(("run", naskoc(&run_args)));
+    let mut vm_args = vec!["run", "--vm", input];
+    vm_args.extend_from_slice(args);
+    results.push(("run --vm", naskoc(&vm_args)));
+    for (backend, exe) in &executables {
+      results.push((backend, run(exe, args)));
+    }
+
+    for (backend, output) in &results {
+      assert_no_panic(output, name);
+      assert_eq!(output.code, Some(*code), "{} with {:?} exited wrongly under {}:\n{}", name, args, backend, output.stderr);
+      assert_eq!(output.stdout, results[0].1.stdout, "{} with {:?} printed something else under {}", name, args, backend);
+    }
+  }
+}
+
+#[test]
+fn features() {
+  agree("features", &[(&[], 7), (&["hello"], 133)]);
+}
+
+#[test]
+fn values() {
+  agree("values", &[(&[], 170)]);
+}
+
+#[test]
+fn nested() {
+  agree("nested", &[(&[], 4), (&["a"], 1)]);
+}
+
+#[test]
+fn declarations() {
+  agree("declarations", &[(&[], 135)]);
+}
+
+#[test]
+fn loops() {
+  agree("loops", &[(&[], 20)]);
+}
+
+#[test]
+fn enums() {
+  agree("enums", &[(&[], 0)]);
+}
+
+#[test]
+fn structs() {
+  agree("structs", &[(&[], 7)]);
+}
+
+#[test]
+fn precedence() {
+  agree("precedence", &[(&[], 0)]);
+}
+
+#[test]
+fn runtime_errors() {
+  agree("runtime_errors", &[(&[], 101), (&["a"], 101), (&["a", "b"], 101), (&["a", "b", "c"], 101)]);
+}
+
+#[test]
+fn trees() {
+  agree("trees", &[(&[], 125)]);
+}
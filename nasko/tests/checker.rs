@@ -0,0 +1,194 @@
+//! Programs the type checker accepts, and the errors it rejects the others
+//! with
+
+mod common;
+
+use common::*;
+
+#[test]
+fn let_declarations() {
+  exits("let-annotated", "func main(): int { let x: u8 = 200; let y = x; return y as int; }\n", 200);
+  exits("let-inferred", "func main(): int { let x = 4; let y = x * 2; return y; }\n", 8);
+  rejects("let-mismatch", "func main(): int { let x: boolean = 1; return 0; }\n", "expected `boolean`, found `{integer}`");
+  rejects(
+    "let-twice",
+    "func main(): int { let x = 1; let x = 2; return x; }\n",
+    "The name `x` is defined multiple times in the same scope"
+  );
+  rejects("let-unknown-type", "func main(): int { let x: Point = 1; return 0; }\n", "Cannot find type `Point` in this scope");
+}
+
+#[test]
+fn if_statements() {
+  exits(
+    "else-if-chain",
+    "func classify(n: int): int {\n  if n < 0 { return 1; } else if n == 0 { return 2; } else { return 3; }\n}\n\
+     func main(): int { return classify(-5) * 100 + classify(0) * 10 + classify(7); }\n",
+    123
+  );
+  rejects("if-integer-condition", "func main(): int { if 1 { return 1; } return 0; }\n", "`if` conditions must be of type `boolean`");
+  rejects(
+    "if-without-else-returns",
+    "func f(b: boolean): int { if b { return 1; } }\nfunc main(): int { return f(true); }\n",
+    "Function `f` does not return a value on every path"
+  );
+}
+
+#[test]
+fn while_loops() {
+  exits(
+    "while-break-continue",
+    "func main(): int {\n  let i = 0;\n  let odd = 0;\n  while true {\n    i = i + 1;\n    if i > 9 { break; }\n    \
+     if i % 2 == 0 { continue; }\n    odd = odd + i;\n  }\n  return odd;\n}\n",
+    25
+  );
+  rejects("break-outside-loop", "func main(): int { break; return 0; }\n", "`break` outside of a loop");
+  rejects("continue-outside-loop", "func main(): int { if true { continue; } return 0; }\n", "`continue` outside of a loop");
+  rejects("while-integer-condition", "func main(): int { while 1 { } return 0; }\n", "`while` conditions must be of type `boolean`");
+  rejects("assign-to-literal", "func main(): int { 1 = 2; return 0; }\n", "Invalid left-hand side of assignment");
+}
+
+#[test]
+fn structs() {
+  exits(
+    "struct-copies",
+    "struct P { x: int, y: int }\nfunc main(): int { let p = P { y: 2, x: 1 }; let q = p; q.x = 10; return p.x + q.x + q.y; }\n",
+    13
+  );
+  let p = "struct P { x: int }\n";
+  rejects("duplicate-field", "struct P { x: int, x: int }\nfunc main(): int { return 0; }\n", "Field `x` is already declared");
+  rejects("missing-field", &format!("{}func main(): int {{ let p = P {{ }}; return 0; }}\n", p), "Missing fields `x` in initializer of `P`");
+  rejects("extra-field", &format!("{}func main(): int {{ let p = P {{ x: 1, y: 2 }}; return 0; }}\n", p), "Struct `P` has no field named `y`");
+  rejects("unknown-field", &format!("{}func main(): int {{ let p = P {{ x: 1 }}; return p.z; }}\n", p), "No field `z` on type `P`");
+  rejects("field-on-number", "func main(): int { let s = 1; s.x = 2; return 0; }\n", "No field `x` on type `i32`");
+  rejects("unknown-struct", "func main(): int { let p = Q { x: 1 }; return 0; }\n", "Cannot find struct `Q` in this scope");
+}
+
+#[test]
+fn recursive_structs() {
+  exits(
+    "recursive-through-array",
+    "struct Node { value: int, children: Node[] }\n\
+     func main(): int { let none: Node[] = []; let n = Node { value: 1, children: [Node { value: 2, children: none }] }; \
+     return n.children[0].value; }\n",
+    2
+  );
+  rejects("holds-itself", "struct A { a: A, n: int }\nfunc main(): int { return 0; }\n", "Recursive type `A` has infinite size");
+  rejects(
+    "holds-itself-indirectly",
+    "struct A { b: B }\nstruct B { a: A }\nfunc main(): int { return 0; }\n",
+    "Recursive type `B` has infinite size"
+  );
+}
+
+#[test]
+fn enums() {
+  exits(
+    "enum-discriminants",
+    "enum Color { Red, Green = 5, Blue, Dark = -3, Darker }\n\
+     func main(): int { return Color.Blue as int * 10 + (Color.Darker as int + 5) + Color.Red as int; }\n",
+    63
+  );
+  rejects("duplicate-discriminant", "enum E { A = 1, B = 1 }\nfunc main(): int { return 0; }\n", "Discriminant value `1` assigned more than once");
+  rejects("computed-discriminant", "enum E { A = 1 + 1 }\nfunc main(): int { return 0; }\n", "Enum discriminants must be integer constants");
+  rejects("duplicate-variant", "enum E { A, A }\nfunc main(): int { return 0; }\n", "Variant `A` is already declared");
+  rejects("unknown-variant", "enum E { A }\nfunc main(): int { return E.B as int; }\n", "No variant named `B` found for enum `E`");
+  rejects(
+    "discriminant-overflow",
+    "enum E { A = 9223372036854775807, B }\nfunc main(): int { return 0; }\n",
+    "Enum discriminant overflowed"
+  );
+}
+
+#[test]
+fn casts() {
+  exits(
+    "allowed-casts",
+    "enum E { A = 7 }\nfunc main(): int {\n  let wide: i32 = 300;\n  let big = 3.9;\n  \
+     return (wide as u8) as int + big as int + E.A as int + true as int;\n}\n",
+    55
+  );
+  rejects("string-to-int", "func main(): int { let s = \"a\"; return s as int; }\n", "Cannot cast `string` as `i32`");
+  rejects("int-to-enum", "enum E { A }\nfunc main(): int { let e = 0 as E; return 0; }\n", "Cannot cast `i32` as `E`");
+}
+
+#[test]
+fn numeric_types() {
+  exits("unsigned-wraps", "func main(): int { let b: u8 = 250; b = b + 10; return b as int; }\n", 4);
+  exits("signed-wraps", "func main(): int { let x: i8 = 127; x = x + 1; return x as int + 200; }\n", 72);
+  exits("float-arithmetic", "func main(): int { let f: float = 2.5; return (f * 4.0) as int; }\n", 10);
+  exits("negative-literal-fits", "func main(): int { let x: i8 = -128; return x as int + 128; }\n", 0);
+  rejects("literal-too-big", "func main(): int { let a: u8 = 256; return 0; }\n", "Literal out of range for `u8`");
+  rejects("literal-too-small", "func main(): int { let b: i8 = -129; return 0; }\n", "Literal out of range for `i8`");
+  rejects("float-to-int", "func main(): int { let f = 1.5; let i: int = f; return 0; }\n", "expected `i32`, found `{float}`");
+  rejects("mixed-widths", "func main(): int { let a: i64 = 1; let b: i32 = 2; return (a + b) as int; }\n", "Mismatched types");
+}
+
+#[test]
+fn arrays() {
+  exits(
+    "array-values",
+    "func sum(a: int[]): int { return a[0] + a[1] + a[2]; }\n\
+     func main(): int { let a = [1, 2, 3]; let b = a; b[0] = 10; let grid = [[1, 2], [3, 4]]; return sum(a) + b[0] + grid[1][0]; }\n",
+    19
+  );
+  rejects("boolean-index", "func main(): int { let a = [1, 2]; return a[true]; }\n", "Array indices must be integers");
+  rejects("constant-out-of-bounds", "func main(): int { let a = [1, 2]; return a[5]; }\n", "Index out of bounds");
+  rejects("mixed-elements", "func main(): int { let a = [1, true]; return 0; }\n", "Mismatched types");
+  rejects("index-non-array", "func main(): int { let f = 1.5; return f[0]; }\n", "Cannot index into a value of type `double`");
+}
+
+#[test]
+fn every_function_is_checked() {
+  exits(
+    "helpers",
+    "func twice(a: int): int { return a * 2; }\nfunc pick(c: boolean, a: int, b: int): int { if c { return a; } return b; }\n\
+     func main(argc: int, argv: string[]): int { return twice(3) + pick(argc > 5, 1, 2); }\n",
+    8
+  );
+  rejects("helper-return", "func f(): int { return true; }\nfunc main(): int { return 0; }\n", "Mismatched types");
+  rejects(
+    "helper-missing-return",
+    "func f(a: int): int { if a > 0 { return 1; } }\nfunc main(): int { return f(1); }\n",
+    "Function `f` does not return a value on every path"
+  );
+  rejects("main-without-type", "func main() { return; }\n", "`main` function must be annotated with the return value: int");
+  rejects("main-argument", "func main(a: int, b: int): int { return 0; }\n", "Invalid type for main argument");
+  rejects("no-main", "func f(): int { return 1; }\n", "Missing `main` function from source");
+}
+
+#[test]
+fn calls() {
+  exits(
+    "nested-calls",
+    "func f(a: int, b: int): int { return a - b; }\nfunc g(): int { return f(f(10, 3), 2); }\nfunc main(): int { return g(); }\n",
+    5
+  );
+  rejects(
+    "extra-argument",
+    "func f(a: int): int { return a; }\nfunc main(): int { return f(1, 2); }\n",
+    "This function takes 1 argument but 2 arguments were supplied"
+  );
+  rejects("argument-type", "func f(a: int): int { return a; }\nfunc main(): int { return f(true); }\n", "Mismatched types");
+  rejects("call-local", "func main(): int { let g = 1; return g(); }\n", "Expected function, found `g`");
+  rejects("unknown-function", "func main(): int { return nope(); }\n", "Cannot find function `nope` in this scope");
+}
+
+#[test]
+fn names_and_scopes() {
+  rejects("own-initializer", "func main(): int { let x = x; return 0; }\n", "Cannot find value `x` in this scope");
+  rejects("block-local", "func main(): int { { let y = 1; } return y; }\n", "Cannot find value `y` in this scope");
+  rejects(
+    "same-scope",
+    "func main(): int { let x = 1; let x = 2; return x; }\n",
+    "The name `x` is defined multiple times in the same scope"
+  );
+  exits("enum-and-function", "enum P { A }\nfunc P(): int { return 2; }\nfunc main(): int { return P(); }\n", 2);
+
+  // Shadowing warns, and the outer variable is visible again after the block
+  let path = source("shadowed", "func main(): int { let x = 1; { let x = 2; } return x; }\n");
+  let output = naskoc(&["run", path.to_str().unwrap()]);
+  assert_eq!(output.code, Some(1), "{}", output.stderr);
+  assert!(output.stderr.contains("`x` shadows a variable from an outer scope"), "{}", output.stderr);
+  exits("shadowed-parameter", "func f(a: int): int { let a = a + 1; return a; }\nfunc main(): int { return f(4); }\n", 5);
+}
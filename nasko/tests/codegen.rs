@@ -0,0 +1,97 @@
+//! What `naskoc` emits for each `--emit` kind
+
+mod common;
+
+use common::*;
+use std::fs;
+use std::process::Command;
+
+/// Emits `code` as `kind` to stdout
+fn emit(name: &str, kind: &str, code: &str) -> String {
+  let path = source(name, code);
+  let output = naskoc(&[&format!("--emit={}", kind), path.to_str().unwrap()]);
+  assert_no_panic(&output, name);
+  assert_eq!(output.code, Some(0), "--emit={} failed for {}:\n{}", kind, name, output.stderr);
+  output.stdout
+}
+
+#[test]
+fn ir() {
+  let ir = emit(
+    "ir",
+    "ir",
+    "func add(a: int, b: int): int { return a + b; }\n\
+     func main(): int { let x = 0; while x < 3 { x = add(x, 1); } return x; }\n"
+  );
+  assert!(
+    ir.contains("func add(%0: i32, %1: i32): i32 {\nbb0:\n  %2: i32 = %0 + %1\n  return %2\n}\n"),
+    "unexpected IR for `add`:\n{}",
+    ir
+  );
+  // The loop condition gets a block of its own, which the body jumps back to
+  assert!(ir.contains("  branch %3, bb2, bb3\n"), "unexpected IR for the loop:\n{}", ir);
+  assert!(
+    ir.contains("bb2:\n  %4: i32 = const 1\n  %5: i32 = call add(%1, %4)\n  %1: i32 = copy %5\n  jump bb1\n"),
+    "unexpected IR for the loop body:\n{}",
+    ir
+  );
+}
+
+/// Builds `code` into an executable with `-o`, returning its path
+fn executable(name: &str, code: &str) -> String {
+  let path = source(name, code);
+  let exe = scratch(&format!("{}-exe", name));
+  let output = naskoc(&[path.to_str().unwrap(), "-o", exe.to_str().unwrap()]);
+  assert_no_panic(&output, name);
+  assert_eq!(output.code, Some(0), "--emit=exe failed for {}:\n{}", name, output.stderr);
+  exe.to_str().unwrap().to_string()
+}
+
+#[test]
+fn native_executables() {
+  let exe = executable(
+    "native",
+    "func main(argc: int, argv: string[]): int { let f = 2.5 * argc as double; let a = [argc, 2, 3]; return (f as int) * 10 + a[0] + a[2]; }\n"
+  );
+  assert_eq!(run(&exe, &["a"]).code, Some(55));
+  assert_eq!(run(&exe, &[]).code, Some(24));
+
+  let exe = executable("native-divide", "func main(argc: int, argv: string[]): int { let z = argc - 1; return 10 / z; }\n");
+  let output = run(&exe, &[]);
+  assert_eq!(output.code, Some(101));
+  assert!(output.stderr.contains("Attempt to divide by zero"), "{}", output.stderr);
+  assert_eq!(run(&exe, &["a", "b"]).code, Some(5));
+
+  let exe = executable("native-recursion", "func f(n: int): int { return f(n + 1) + 1; }\nfunc main(): int { return f(0); }\n");
+  let output = run(&exe, &[]);
+  assert_eq!(output.code, Some(101));
+  assert!(output.stderr.contains("Stack overflow"), "{}", output.stderr);
+}
+
+#[test]
+fn c_compiles_without_warnings() {
+  let mut programs: Vec<_> = fs::read_dir(program("").parent().unwrap())
+    .unwrap()
+    .map(|entry| entry.unwrap().path())
+    .filter(|path| path.extension().is_some_and(|ext| ext == "nasko"))
+    .collect();
+  programs.sort();
+  assert!(!programs.is_empty(), "no programs in tests/programs");
+
+  for input in &programs {
+    let name = input.file_stem().unwrap().to_str().unwrap();
+    let c = scratch(&format!("{}-strict.c", name));
+    let output = naskoc(&["--emit=c", input.to_str().unwrap(), "-o", c.to_str().unwrap()]);
+    assert_eq!(output.code, Some(0), "--emit=c failed for {}:\n{}", name, output.stderr);
+
+    // Some programs leave their own variables unused, which isn't the backend's doing
+    let cc = Command::new("cc")
+      .args(["-std=c99", "-Wall", "-Werror", "-Wno-unused-variable", "-Wno-unused-but-set-variable", "-c"])
+      .arg(&c)
+      .arg("-o")
+      .arg(scratch(&format!("{}-strict.o", name)))
+      .output()
+      .expect("failed to run cc");
+    assert!(cc.status.success(), "cc warned about {}:\n{}", name, String::from_utf8_lossy(&cc.stderr));
+  }
+}
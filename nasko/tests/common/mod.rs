@@ -0,0 +1,109 @@
+//! Helpers for the end-to-end tests, which run the `naskoc` binary
+
+#![allow(dead_code)]
+
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const NASKOC: &str = env!("CARGO_BIN_EXE_naskoc");
+
+#[derive(Debug, PartialEq)]
+pub struct Output {
+  /// `None` if a signal ended the process
+  pub code: Option<i32>,
+  pub stdout: String,
+  pub stderr: String
+}
+
+/// Path of a program in `tests/programs`
+pub fn program(name: &str) -> PathBuf {
+  Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/programs").join(format!("{}.nasko", name))
+}
+
+/// A path for files a test writes, unique to `name`
+pub fn scratch(name: &str) -> PathBuf {
+  let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("naskoc-tests");
+  fs::create_dir_all(&dir).expect("failed to create the scratch directory");
+  dir.join(name)
+}
+
+/// Writes `code` to a scratch file and returns its path
+pub fn source(name: &str, code: &str) -> PathBuf {
+  let path = scratch(&format!("{}.nasko", name));
+  fs::write(&path, code).expect("failed to write the source");
+  path
+}
+
+pub fn run<P: AsRef<Path>>(program: P, args: &[&str]) -> Output {
+  run_for(program, args, Duration::from_secs(60)).expect("the program timed out")
+}
+
+/// Runs a program, killing it if it takes longer than `timeout`
+pub fn run_for<P: AsRef<Path>>(program: P, args: &[&str], timeout: Duration) -> Option<Output> {
+  let mut child = Command::new(program.as_ref())
+    .args(args)
+    .stdin(Stdio::null())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()
+    .expect("failed to start the program");
+
+  // Read while waiting, so a full pipe can't block the child
+  let mut stdout = child.stdout.take().expect("stdout is piped");
+  let mut stderr = child.stderr.take().expect("stderr is piped");
+  let stdout = thread::spawn(move || {
+    let mut out = String::new();
+    stdout.read_to_string(&mut out).map(|_| out)
+  });
+  let stderr = thread::spawn(move || {
+    let mut out = String::new();
+    stderr.read_to_string(&mut out).map(|_| out)
+  });
+
+  let start = Instant::now();
+  let status = loop {
+    if let Some(status) = child.try_wait().expect("failed to wait for the program") {
+      break Some(status);
+    }
+    if start.elapsed() > timeout {
+      let _ = child.kill();
+      let _ = child.wait();
+      break None;
+    }
+    thread::sleep(Duration::from_millis(5));
+  };
+
+  let stdout = stdout.join().unwrap().unwrap_or_default();
+  let stderr = stderr.join().unwrap().unwrap_or_default();
+  status.map(|status| Output { code: status.code(), stdout, stderr })
+}
+
+pub fn naskoc(args: &[&str]) -> Output {
+  run(NASKOC, args)
+}
+
+/// Checks that `naskoc run` rejects a program with `message`
+pub fn rejects(name: &str, code: &str, message: &str) {
+  let path = source(name, code);
+  let output = naskoc(&["run", path.to_str().unwrap()]);
+  assert_no_panic(&output, name);
+  assert_eq!(output.code, Some(1), "{} was accepted:\n{}", name, output.stderr);
+  assert!(output.stderr.contains(message), "{} was rejected for another reason:\n{}", name, output.stderr);
+}
+
+/// Checks that `naskoc run` accepts a program, which exits with `expected`
+pub fn exits(name: &str, code: &str, expected: i32) {
+  let path = source(name, code);
+  let output = naskoc(&["run", path.to_str().unwrap()]);
+  assert_no_panic(&output, name);
+  assert_eq!(output.code, Some(expected), "{} exited wrongly:\n{}", name, output.stderr);
+}
+
+/// Checks that `naskoc` didn't panic, which is never the right way to fail
+pub fn assert_no_panic(output: &Output, context: &str) {
+  assert!(!output.stderr.contains("panicked"), "naskoc panicked on {}:\n{}", context, output.stderr);
+}
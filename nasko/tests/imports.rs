@@ -0,0 +1,45 @@
+//! Programs split into modules, from `tests/programs/imports`
+
+mod common;
+
+use common::*;
+
+fn module(name: &str) -> String {
+  program(&format!("imports/{}", name)).to_str().unwrap().to_string()
+}
+
+#[test]
+fn shared_module_is_loaded_once() {
+  // `main` and `shapes` both import `util.math`
+  let output = naskoc(&["run", &module("main")]);
+  assert_eq!(output.code, Some(11), "{}", output.stderr);
+}
+
+#[test]
+fn search_paths() {
+  let output = naskoc(&["run", &module("searched")]);
+  assert_eq!(output.code, Some(1));
+  assert!(output.stderr.contains("Cannot find module `extra`"), "{}", output.stderr);
+
+  let lib = program("imports/lib/extra");
+  let lib = lib.parent().unwrap().to_str().unwrap();
+  let output = naskoc(&["run", "-I", lib, &module("searched")]);
+  assert_eq!(output.code, Some(15), "{}", output.stderr);
+}
+
+#[test]
+fn import_cycle() {
+  let output = naskoc(&["run", &module("cycle_a")]);
+  assert_no_panic(&output, "cycle_a");
+  assert_eq!(output.code, Some(1));
+  assert!(output.stderr.contains("Import cycle detected while importing `\"cycle_a.nasko\"`"), "{}", output.stderr);
+  assert!(output.stderr.contains("due to 1 previous error"), "{}", output.stderr);
+}
+
+#[test]
+fn name_declared_by_two_modules() {
+  let output = naskoc(&["run", &module("clash")]);
+  assert_eq!(output.code, Some(1));
+  assert!(output.stderr.contains("The function `area` is defined by both"), "{}", output.stderr);
+  assert!(output.stderr.contains("shapes.nasko"), "{}", output.stderr);
+}
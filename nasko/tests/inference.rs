@@ -0,0 +1,89 @@
+//! Programs the type checker has to infer or reject the same way whatever
+//! the order of their declarations
+
+mod common;
+
+use common::*;
+
+#[test]
+fn return_type_from_later_callers() {
+  let id = "func id(x) { return x; }\n";
+  let main = "func main(): int { return id(3); }\n";
+
+  for (name, code) in [("id-first", format!("{}{}", id, main)), ("id-last", format!("{}{}", main, id))].iter() {
+    let path = source(name, code);
+    for engine in [&["run"][..], &["run", "--vm"][..]].iter() {
+      let mut args = engine.to_vec();
+      args.push(path.to_str().unwrap());
+      let output = naskoc(&args);
+      assert_eq!(output.code, Some(3), "{} under {:?}:\n{}", name, engine, output.stderr);
+    }
+  }
+}
+
+#[test]
+fn omitted_return_type_without_value_is_void() {
+  rejects(
+    "void-used-as-int",
+    "func main(): int { return g() + 1; }\nfunc g() {}\n",
+    "Function `g` does not return a value on every path"
+  );
+}
+
+#[test]
+fn operators_on_inferred_operands() {
+  rejects(
+    "compare-structs",
+    "struct P { x: int }\nfunc lt(a, b) { return a < b; }\n\
+     func main(): int { let p = P { x: 1 }; if lt(p, p) { return 1; } return 0; }\n",
+    "Cannot apply `<` to values of type `P`"
+  );
+  rejects(
+    "negate-unsigned",
+    "func neg(a) { return -a; }\nfunc main(): int { let b: u8 = 1; neg(b); return 0; }\n",
+    "Cannot apply `-` to a value of type `u8`"
+  );
+}
+
+#[test]
+fn casts_of_inferred_operands() {
+  rejects(
+    "cast-struct",
+    "struct P { x: int }\nfunc conv(v) { return v as int; }\nfunc main(): int { return conv(P { x: 1 }); }\n",
+    "Cannot cast `P` as `i32`"
+  );
+}
+
+#[test]
+fn void_is_not_a_value() {
+  // `h` is known to return nothing before `main` is checked, arguments
+  // would decide the return type of `g`
+  let g = "func g() {}\nfunc h() { return; }\nfunc take(x: int): int { return x; }\n";
+  for (name, body) in [
+    ("void-let", "let v = g();"),
+    ("void-element", "let a = [g()];"),
+    ("void-argument", "take(h());"),
+    ("void-operand", "let b = g() == g();"),
+    ("void-cast", "let c = g() as int;")
+  ].iter() {
+    rejects(name, &format!("{}func main(): int {{ {} return 0; }}\n", g, body), "Expected value, found `void`");
+  }
+  rejects("void-return", "func g() {}\nfunc f() { return g(); }\nfunc main(): int { f(); return 0; }\n", "Expected value, found `void`");
+}
+
+#[test]
+fn let_bindings_and_parameters() {
+  exits(
+    "inferred-locals",
+    "func add(a, b) { return a + b; }\n\
+     func main(): int { let x = add(2, 3); let y: u8 = 250; let z = y + 10; return x + z as int; }\n",
+    9
+  );
+  rejects(
+    "conflicting-arguments",
+    "func f(a) { return a; }\nfunc main(): int { let x = f(1); let y = f(true); return 0; }\n",
+    "expected `{integer}`, found `boolean`"
+  );
+  rejects("unused-parameter", "func f(a) { return 0; }\nfunc main(): int { return 0; }\n", "Type annotations needed");
+  rejects("empty-array", "func main(): int { let a = []; return 0; }\n", "cannot infer the type of `a`");
+}
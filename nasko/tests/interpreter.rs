@@ -0,0 +1,55 @@
+//! Runs programs with `naskoc run`, which interprets the checked tree
+
+mod common;
+
+use common::*;
+
+/// Runs a program with `args`, which must end with a runtime error
+fn fails(name: &str, code: &str, args: &[&str], message: &str) {
+  let path = source(name, code);
+  let mut run_args = vec!["run", path.to_str().unwrap()];
+  run_args.extend_from_slice(args);
+  let output = naskoc(&run_args);
+  assert_no_panic(&output, name);
+  assert_eq!(output.code, Some(101), "{} didn't fail at runtime:\n{}", name, output.stderr);
+  assert!(output.stderr.contains(message), "{} failed for another reason:\n{}", name, output.stderr);
+}
+
+#[test]
+fn arguments() {
+  let path = source("arguments", "func main(argc: int, argv: string[]): int { return argc; }\n");
+  let path = path.to_str().unwrap();
+  assert_eq!(naskoc(&["run", path]).code, Some(1));
+  assert_eq!(naskoc(&["run", path, "a", "b", "c"]).code, Some(4));
+}
+
+#[test]
+fn deep_recursion() {
+  exits(
+    "deep-recursion",
+    "func depth(n: int): int { if n == 0 { return 0; } return depth(n - 1) + 1; }\n\
+     func main(): int { return depth(9000) - 8900; }\n",
+    100
+  );
+  fails(
+    "unbounded-recursion",
+    "func f(n: int): int { return f(n + 1) + 1; }\nfunc main(): int { return f(0); }\n",
+    &[],
+    "Stack overflow"
+  );
+}
+
+#[test]
+fn runtime_errors() {
+  let divide = "func main(argc: int, argv: string[]): int { let z = argc - 1; return 10 / z; }\n";
+  fails("divide-by-zero", divide, &[], "Attempt to divide by zero");
+  let output = naskoc(&["run", source("divide-by-zero", divide).to_str().unwrap(), "a", "b"]);
+  assert_eq!(output.code, Some(5), "{}", output.stderr);
+
+  fails(
+    "index-out-of-bounds",
+    "func main(argc: int, argv: string[]): int { let a = [1, 2]; return a[argc]; }\n",
+    &["a", "b"],
+    "Index out of bounds: the length is 2 but the index is 3"
+  );
+}
@@ -0,0 +1,82 @@
+//! Running `.naskoc` modules, including ones that weren't written by
+//! `naskoc`
+
+mod common;
+
+use common::*;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Compiles a program from `tests/programs` to a module
+fn module(name: &str) -> (PathBuf, Vec<u8>) {
+  let path = scratch(&format!("{}.naskoc", name));
+  let output = naskoc(&["--emit=naskoc", program(name).to_str().unwrap(), "-o", path.to_str().unwrap()]);
+  assert_eq!(output.code, Some(0), "--emit=naskoc failed for {}:\n{}", name, output.stderr);
+  let bytes = fs::read(&path).expect("failed to read the module");
+  (path, bytes)
+}
+
+#[test]
+fn round_trip() {
+  let (path, _) = module("features");
+  let path = path.to_str().unwrap();
+  assert_eq!(naskoc(&["run", path]).code, Some(7));
+  assert_eq!(naskoc(&["run", path, "hello"]).code, Some(133));
+}
+
+#[test]
+fn truncated() {
+  let (_, bytes) = module("structs");
+  let path = scratch("truncated.naskoc");
+
+  for len in 0..bytes.len() {
+    fs::write(&path, &bytes[..len]).unwrap();
+    let output = naskoc(&["run", path.to_str().unwrap()]);
+    let context = format!("the first {} bytes", len);
+    assert_no_panic(&output, &context);
+    assert_eq!(output.code, Some(1), "{} were accepted", context);
+    assert!(output.stderr.contains("Invalid module"), "{} failed with:\n{}", context, output.stderr);
+  }
+}
+
+#[test]
+fn corrupted() {
+  let (_, bytes) = module("structs");
+  let path = scratch("corrupted.naskoc");
+
+  for i in 0..bytes.len() {
+    for mask in [0x01, 0x80, 0xff].iter() {
+      let mut corrupted = bytes.clone();
+      corrupted[i] ^= mask;
+      fs::write(&path, &corrupted).unwrap();
+
+      // A changed jump can loop forever, which is fine
+      let output = run_for(NASKOC, &["run", path.to_str().unwrap()], Duration::from_secs(5));
+      if let Some(output) = output {
+        assert_no_panic(&output, &format!("byte {} xor {:#x}", i, mask));
+      }
+    }
+  }
+}
+
+#[test]
+fn too_many_locals() {
+  let mut code = "func main(): int {\n".to_string();
+  for i in 0..70000 {
+    code.push_str(&format!("  let v{} = {};\n", i, i % 7));
+  }
+  code.push_str("  return v69999;\n}\n");
+  let path = source("too-many-locals", &code);
+  let path = path.to_str().unwrap();
+  let module = scratch("too-many-locals.naskoc");
+
+  for args in [&["run", "--vm", path][..], &["--emit=naskoc", path, "-o", module.to_str().unwrap()][..]].iter() {
+    let output = naskoc(args);
+    assert_no_panic(&output, "70000 locals");
+    assert_eq!(output.code, Some(1), "{:?} accepted 70000 locals", args);
+    assert!(output.stderr.contains("Too many locals to compile to bytecode"), "{:?} failed with:\n{}", args, output.stderr);
+  }
+  // The interpreter has no such limit
+  assert_eq!(naskoc(&["run", path]).code, Some(6));
+}
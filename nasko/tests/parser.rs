@@ -0,0 +1,148 @@
+//! How `naskoc` reads programs, checked through what they evaluate to
+
+mod common;
+
+use common::*;
+
+/// Checks that `main` returning `expression` exits with `code`
+fn evaluates(name: &str, expression: &str, code: i32) {
+  let path = source(name, &format!("func main(): int {{ return {}; }}\n", expression));
+  let output = naskoc(&["run", path.to_str().unwrap()]);
+  assert_no_panic(&output, name);
+  assert_eq!(output.code, Some(code), "`{}` evaluated wrongly:\n{}", expression, output.stderr);
+}
+
+#[test]
+fn prefix_minus_and_power() {
+  evaluates("negated-power", "10 + -2 ** 2", 6);
+  evaluates("power-of-negation", "(-2) ** 2", 4);
+  evaluates("power-right-associative", "2 ** 3 ** 2 - 500", 12);
+  evaluates("negation-before-cast", "(-1 as u8) as int", 255);
+  evaluates("power-before-cast", "2 ** 2 as i64 as int", 4);
+}
+
+#[test]
+fn minus_is_an_operator_not_part_of_the_literal() {
+  evaluates("subtract-negative", "3 - -2", 5);
+  evaluates("no-spaces", "3-2", 1);
+  evaluates("double-negation", "- - 4 + 10", 14);
+  evaluates("negated-group", "10 - -(2 + 3)", 15);
+  evaluates("smallest-int", "-2147483648 / 2 + 1073741830", 6);
+  exits("negated-variable", "func main(): int { let x = 3; let y = -x; return y + 10 - -1.5 as int; }\n", 8);
+}
+
+/// Compiles a program that must be rejected, returning what was reported
+fn errors(name: &str, code: &str) -> String {
+  let path = source(name, code);
+  let output = naskoc(&["run", path.to_str().unwrap()]);
+  assert_no_panic(&output, name);
+  assert_eq!(output.code, Some(1), "{} was accepted", name);
+  output.stderr
+}
+
+#[test]
+fn checking_continues_past_syntax_errors() {
+  let stderr = errors(
+    "syntax-and-type-errors",
+    "func f(a: int): int {\n  let y = a + ;\n  return y;\n}\n\
+     func main(): int {\n  let q: boolean = 5;\n  return f(1)\n}\n"
+  );
+  assert!(stderr.contains("Expected an expression, found `;`"), "{}", stderr);
+  assert!(stderr.contains("Expected `;`, found `}`"), "{}", stderr);
+  assert!(stderr.contains("expected `boolean`, found `{integer}`"), "{}", stderr);
+  // Neither the half parsed `y` nor the missing `return` is reported
+  assert!(stderr.contains("due to 3 previous errors"), "{}", stderr);
+}
+
+#[test]
+fn names_from_broken_declarations_are_not_reported() {
+  let stderr = errors(
+    "broken-declarations",
+    "import missing;\nstruct P { x: int,, }\n\
+     func main(): int { let p: P = P { x: 1 }; return helper(p.x); }\n"
+  );
+  assert!(stderr.contains("Cannot find module `missing`"), "{}", stderr);
+  assert!(stderr.contains("Expected a field name or `}`, found `,`"), "{}", stderr);
+  assert!(stderr.contains("due to 2 previous errors"), "{}", stderr);
+}
+
+#[test]
+fn reports_every_syntax_error() {
+  let stderr = errors(
+    "many-syntax-errors",
+    "func f(): int {\n  let a = 1 +;\n  let b = @;\n  return a\n}\n\
+     struct S { x int }\nfunc main(): int { return f(); }\n"
+  );
+  assert!(stderr.contains("Unexpected character `@`"), "{}", stderr);
+  assert!(stderr.contains("Expected `;`, found `}`"), "{}", stderr);
+  assert!(stderr.contains("Expected `:`, found `int`"), "{}", stderr);
+  assert!(stderr.contains("due to 5 previous errors"), "{}", stderr);
+}
+
+#[test]
+fn recovers_at_declarations() {
+  for (name, code, message) in [
+    ("stray-brace", "}\nfunc main(): int { return 2; }\n", "Expected a declaration, found `}`"),
+    ("top-level-let", "let x = 1;\nfunc main(): int { return 2; }\n", "Expected a declaration, found `let`"),
+    ("unclosed-body", "func main(): int {\n  if true { return 1;\n  return 0;\n}\nfunc g() {}\n", "Expected `}`, found `func`")
+  ].iter() {
+    let stderr = errors(name, code);
+    assert!(stderr.contains(message), "{}:\n{}", name, stderr);
+    assert!(stderr.contains("due to 1 previous error"), "{}:\n{}", name, stderr);
+  }
+}
+
+#[test]
+fn binary_precedence_and_associativity() {
+  evaluates("product-before-sum", "1 + 2 * 3", 7);
+  evaluates("parenthesized-sum", "(1 + 2) * 3", 9);
+  evaluates("left-associative-difference", "10 - 4 - 3", 3);
+  evaluates("left-associative-division", "64 / 4 / 2", 8);
+  evaluates("same-level-product-and-remainder", "2 * 7 % 4", 2);
+}
+
+#[test]
+fn postfix_chains() {
+  let path = source(
+    "postfix-chains",
+    "struct P { x: int[] }\nfunc id(p: P): P { return p; }\n\
+     func main(): int { return id(P { x: [4, 5] }).x[1] - 2 * 3 % 4 + 20; }\n"
+  );
+  assert_eq!(naskoc(&["run", path.to_str().unwrap()]).code, Some(23));
+}
+
+#[test]
+fn braces_after_conditions_open_the_body() {
+  let path = source(
+    "condition-struct-name",
+    "struct P { x: int }\nfunc main(): int {\n  let p = P { x: 1 };\n  let one = 1;\n  \
+     if p.x == one { return 3; }\n  return 0;\n}\n"
+  );
+  assert_eq!(naskoc(&["run", path.to_str().unwrap()]).code, Some(3));
+}
+
+#[test]
+fn comparison_logical_and_bitwise_operators() {
+  evaluates("bitwise", "(6 & 3) + (6 | 1) * 10 + (6 ^ 3) * 20 + (1 << 4) - (64 >> 2)", 172);
+  exits(
+    "comparisons",
+    "func main(): int { let r = 0; if 1 < 2 && 3 >= 3 && 2 > 1 && 2 <= 2 { r = r + 1; } if 1 != 1 || !(2 == 2) { r = r + 2; } return r; }\n",
+    1
+  );
+  exits(
+    "short-circuit",
+    "func main(): int { let z = 0; if false && 1 / z == 0 { return 1; } if true || 1 / z == 0 { return 2; } return 3; }\n",
+    2
+  );
+  rejects("bitwise-double", "func main(): int { let f = 1.5; let g = f & f; return 0; }\n", "Cannot apply `&` to values of type `double`");
+  rejects(
+    "logical-integer",
+    "func main(): int { if 1 && true { return 1; } return 0; }\n",
+    "both operands of `&&` must be of type `boolean`"
+  );
+  rejects(
+    "compare-mixed",
+    "func main(): int { let a = 1; if a == true { return 1; } return 0; }\n",
+    "both operands of `==` must have the same type"
+  );
+}
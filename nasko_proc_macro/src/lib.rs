@@ -5,21 +5,18 @@ use proc_macro2::{TokenTree, Spacing, Span, TokenStream, token_stream::IntoIter
 use syn::{Attribute, Data, DeriveInput, Fields, parse_macro_input, spanned::Spanned};
 
 fn is_punct(tt: &TokenTree, expect: char) -> bool {
-    match tt {
-        TokenTree::Punct(punct)
-            if punct.as_char() == expect && punct.spacing() == Spacing::Alone =>
-        {
-            true
-        }
-        _ => false,
-    }
+    matches!(
+        tt,
+        TokenTree::Punct(punct) if punct.as_char() == expect && punct.spacing() == Spacing::Alone
+    )
 }
 
 /// If supplied `tt` is a punct matching a char, returns `None`, else returns `tt`
 fn expect_punct(tt: Option<TokenTree>, expect: char) -> Option<TokenTree> {
-    tt.filter(|tt| !is_punct(&tt, expect))
+    tt.filter(|tt| !is_punct(tt, expect))
 }
 
+#[allow(dead_code)]
 enum NestedValue {
     /// `name = ...`
     Assign(TokenStream),
@@ -29,6 +26,7 @@ enum NestedValue {
     KeywordAssign(Ident, TokenStream),
 }
 
+#[allow(dead_code)]
 enum Nested {
     /// Unnamed nested attribute, such as a string,
     /// callback closure, or a lone ident/path
@@ -145,6 +143,7 @@ enum Callback {
     None
 }
 
+#[allow(dead_code)]
 #[derive(Clone, Debug)]
 struct InlineCallback {
     pub arg: Ident,
@@ -229,61 +228,61 @@ impl Parser {
     }
 }
 
-#[proc_macro_derive(GenericASTNode, attributes(children, node_type))]
+#[proc_macro_derive(GenericASTNode, attributes(children, node_type, span))]
 pub fn derive_generic_ast_node(input: TS1) -> TS1 {
     let input = parse_macro_input!(input as DeriveInput);
     let ident = format_ident!("{}", input.ident);
     let mut ext = format_ident!("_");
+    let mut span = format_ident!("_");
+    let mut ta = format_ident!("_");
     let mut ecb = Callback::None;
     
-    match input.data {
-        Data::Struct(s) => {
-            match s.fields {
-                Fields::Named(mut f) => {
-                    for field in &mut f.named {
-                        for attr in &mut field.attrs {
-                            if attr.path.is_ident("children") {
-                                ext = attr.path.segments[0].ident.clone();
-                            } else if attr.path.is_ident("node_type") {
-                                let nested = match Parser::parse_attr(attr) {
-                                    None => {
-                                        panic!("GenericASTNode derive must include a #[node_type] attribute on the struct")
+    if let Data::Struct(s) = input.data {
+        if let Fields::Named(mut f) = s.fields {
+            for field in &mut f.named {
+                for attr in &mut field.attrs {
+                    if attr.path.is_ident("children") {
+                        ext = field.ident.clone().unwrap();
+                    } else if attr.path.is_ident("span") {
+                        span = field.ident.clone().unwrap();
+                    } else if attr.path.is_ident("node_type") {
+                        ta = field.ident.clone().unwrap();
+                        let nested = match Parser::parse_attr(attr) {
+                            None => {
+                                panic!("GenericASTNode derive must include a #[node_type] attribute on the struct")
+                            },
+                            Some(tokens) => tokens
+                        };
+
+                        for (pos, next) in nested.enumerate() {
+                            match next {
+                                Nested::Unnamed(tokens) => match pos {
+                                    0 => ecb = match Parser::parse_callback(tokens) {
+                                        Some(cb) => cb,
+                                        None => panic!("Invalid callback")
                                     },
-                                    Some(tokens) => tokens
-                                };
-
-                                for (pos, next) in nested.enumerate() {
-                                    match next {
-                                        Nested::Unnamed(tokens) => match pos {
-                                            0 => ecb = match Parser::parse_callback(tokens) {
-                                                Some(cb) => cb,
-                                                None => panic!("Invalid callback")
-                                            },
-                                            _ => panic!("Expected named argument")
-                                        },
-                                        _ => panic!("Unexpected token")
-                                    }
-                                }
+                                    _ => panic!("Expected named argument")
+                                },
+                                _ => panic!("Unexpected token")
                             }
                         }
                     }
-                },
-                _ => {}
+                }
             }
-        },
-        _ => {}
-    };
+        }
+    }
 
     if ext == format_ident!("_") {
         panic!("GenericASTNode derive must include a #[children] attribute on one field")
     }
 
-    match ecb {
-        Callback::None => {
-            panic!("GenericASTNode derive must include a #[node_type] attribute with a callback for the node type")
-        }
-        _ => {}
-    };
+    if span == format_ident!("_") {
+        panic!("GenericASTNode derive must include a #[span] attribute on one field")
+    }
+
+    if let Callback::None = ecb {
+        panic!("GenericASTNode derive must include a #[node_type] attribute with a callback for the node type")
+    }
 
     let expanded = quote! {
         impl ASTNode for #ident {
@@ -295,10 +294,38 @@ pub fn derive_generic_ast_node(input: TS1) -> TS1 {
                 (#ecb)()
             }
 
+            fn span(&self) -> Span {
+                self.#span.clone()
+            }
+
+            fn ta(&self) -> &NaskoType {
+                &self.#ta
+            }
+
+            fn ta_mut(&mut self) -> &mut NaskoType {
+                &mut self.#ta
+            }
+
+            fn as_any(&self) -> &dyn ::std::any::Any {
+                self
+            }
+
+            fn as_any_mut(&mut self) -> &mut dyn ::std::any::Any {
+                self
+            }
+
+            fn into_any(self: Box<Self>) -> Box<dyn ::std::any::Any> {
+                self
+            }
+
             fn get_leaves(&self) -> &Vec<Box<dyn ASTNode>> {
                 &self.#ext
             }
 
+            fn get_leaves_mut(&mut self) -> &mut Vec<Box<dyn ASTNode>> {
+                &mut self.#ext
+            }
+
             fn push_leaf(&mut self, data: Box<dyn ASTNode>) {
                 self.#ext.push(data)
             }